@@ -1,9 +1,48 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::invoke_signed;
 use anchor_lang::solana_program::sysvar::clock::Clock;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_spl::token::{self, CloseAccount, Mint, Token, TokenAccount, Transfer};
 
-const CV_SCALE: u128 = 1_000_000;
-const CV_SCALE_U64: u64 = 1_000_000;
+pub mod math;
+
+use math::{
+    compute_required_conviction, compute_signaling_conviction, decay_since, lock_multiplier,
+    predict_pass_time, voter_weight, CV_SCALE_U64,
+};
+
+// Room for an ipfs:// CIDv1 or ar:// link, including a gateway prefix
+pub const MAX_METADATA_URI_LEN: usize = 200;
+pub const MAX_VOTER_ALLOCATIONS: usize = 10;
+pub const MAX_ALLOWED_PROGRAMS: usize = 8;
+pub const MAX_PROPOSAL_INSTRUCTIONS: usize = 4;
+pub const MAX_LOCK_TIERS: usize = 4;
+pub const MAX_FUNDING_CATEGORIES: usize = 8;
+pub const MAX_FUNDING_CATEGORY_NAME_LEN: usize = 32;
+pub const MAX_REGISTERED_TREASURIES: usize = 8;
+pub const SPENDING_BUCKETS: usize = 4;
+pub const SPENDING_WINDOW_SPACE: usize = SPENDING_BUCKETS * (8 + 8);
+pub const PROPOSAL_SPACE: usize = 8
+    + 8
+    + 32
+    + 32
+    + 8
+    + 4
+    + MAX_METADATA_URI_LEN
+    + 1
+    + 8
+    + 8
+    + 8
+    + 8
+    + 8
+    + 8
+    + 32
+    + 8
+    + 4
+    + MAX_FUNDING_CATEGORY_NAME_LEN
+    + 32
+    + 8;
 
 declare_id!("sn9bNZ3gZxyiy5zE5FGGSJGQEXeedgoSGEMRQNUiSME");
 
@@ -16,19 +55,24 @@ pub mod commons_conviction_voting {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn initialize_cv_config(
         ctx: Context<InitializeCvConfig>,
         decay_rate: u64,
         max_ratio: u64,
         weight_exponent: u64,
         min_threshold: u64,
+        proposal_expiry_slots: u64,
+        signaling_threshold: u64,
+        stream_duration_slots: u64,
+        proposal_deposit: u64,
+        time_base: TimeBase,
     ) -> Result<()> {
         let cv_config = &mut ctx.accounts.cv_config;
-        require!(decay_rate <= CV_SCALE_U64, CustomError::InvalidDecayRate);
-        require!(max_ratio <= CV_SCALE_U64, CustomError::InvalidMaxRatio);
+        validate_cv_params(decay_rate, max_ratio, min_threshold)?;
         require!(
-            min_threshold <= CV_SCALE_U64,
-            CustomError::InvalidMinThreshold
+            signaling_threshold <= CV_SCALE_U64,
+            CustomError::InvalidSignalingThreshold
         );
         cv_config.decay_rate = decay_rate;
         cv_config.max_ratio = max_ratio;
@@ -41,69 +85,502 @@ pub mod commons_conviction_voting {
         cv_config.staking_vault_bump = ctx.bumps.staking_vault;
         cv_config.authority = ctx.accounts.authority.key();
         cv_config.total_staked = 0;
+        cv_config.proposal_count = 0;
+        cv_config.proposal_expiry_slots = proposal_expiry_slots;
+        cv_config.signaling_threshold = signaling_threshold;
+        cv_config.abstain_proposal = Pubkey::default();
+        cv_config.allowed_programs = Vec::new();
+        cv_config.stream_duration_slots = stream_duration_slots;
+        cv_config.committed_stream_amount = 0;
+        cv_config.challenge_period_slots = 0;
+        cv_config.challenge_bond = 0;
+        cv_config.arbiter = Pubkey::default();
+        cv_config.proposal_deposit = proposal_deposit;
+        cv_config.time_base = time_base;
+        cv_config.previous_decay_rate = decay_rate;
+        cv_config.decay_rate_updated_at = 0;
+        cv_config.lock_schedule = Vec::new();
+        cv_config.spending_window_slots = 0;
+        cv_config.spending_cap = 0;
+        cv_config.spending = SpendingWindow::default();
+        cv_config.funding_categories = Vec::new();
+        cv_config.guardian = Pubkey::default();
+        cv_config.paused = false;
+        cv_config.conviction_mode = ConvictionMode::Linear;
+        cv_config.personhood_root = [0; 32];
+        Ok(())
+    }
+
+    /// Updates the core CV parameters. Callable by the CV authority, or by the
+    /// CV config PDA itself when a passed meta-proposal executes this via CPI.
+    ///
+    /// Conviction accrued before a `decay_rate` change keeps decaying at the old
+    /// rate up to the change and at the new rate afterwards. Only one previous
+    /// rate is remembered, so pending proposals can be passed as writable
+    /// remaining accounts to checkpoint them at the outgoing rate.
+    pub fn update_cv_params<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UpdateCvParams<'info>>,
+        decay_rate: u64,
+        max_ratio: u64,
+        weight_exponent: u64,
+        min_threshold: u64,
+    ) -> Result<()> {
+        validate_cv_params(decay_rate, max_ratio, min_threshold)?;
+        let cv_config = &mut ctx.accounts.cv_config;
+        let now = current_time(&ctx.accounts.clock, cv_config);
+        for proposal_info in ctx.remaining_accounts.iter() {
+            let mut proposal = Account::<Proposal>::try_from(proposal_info)?;
+            let conviction_before =
+                update_conviction_for_proposal(&mut proposal, 0, cv_config, now)?;
+            emit!(ConvictionUpdated {
+                proposal: proposal.key(),
+                conviction_before,
+                conviction_after: proposal.current_conviction,
+            });
+            proposal.exit(&crate::ID)?;
+        }
+
+        if decay_rate != cv_config.decay_rate {
+            cv_config.previous_decay_rate = cv_config.decay_rate;
+            cv_config.decay_rate_updated_at = now;
+            cv_config.decay_rate = decay_rate;
+        }
+        cv_config.max_ratio = max_ratio;
+        cv_config.weight_exponent = weight_exponent;
+        cv_config.min_threshold = min_threshold;
         Ok(())
     }
 
+    /// `category` names one of the config's funding categories, or is empty
+    /// for a proposal only bound by the overall spending cap.
     pub fn create_proposal(
         ctx: Context<CreateProposal>,
         requested_amount: u64,
-        metadata_hash: String,
+        metadata_uri: String,
+        category: String,
     ) -> Result<()> {
+        require!(
+            metadata_uri.len() <= MAX_METADATA_URI_LEN,
+            CustomError::MetadataUriTooLong
+        );
+        let cv_config = &mut ctx.accounts.cv_config;
         let proposal = &mut ctx.accounts.proposal;
+        proposal.id = take_proposal_id(cv_config)?;
+        // Signaling proposals (requested_amount == 0) move no funds, so need no beneficiary
+        (proposal.beneficiary, proposal.request_mint) = match ctx.accounts.beneficiary.as_ref() {
+            Some(beneficiary) => (beneficiary.key(), beneficiary.mint),
+            None => {
+                require!(requested_amount == 0, CustomError::MissingBeneficiary);
+                (Pubkey::default(), cv_config.commons_token_mint)
+            }
+        };
+        // Spending caps are denominated in the commons treasury's asset
+        let pays_commons = proposal.request_mint == cv_config.commons_token_mint;
+        require!(
+            category.is_empty()
+                || (pays_commons && cv_config.funding_category(&category).is_some()),
+            CustomError::UnknownFundingCategory
+        );
         proposal.creator = ctx.accounts.authority.key();
         proposal.requested_amount = requested_amount;
-        proposal.metadata_hash = metadata_hash;
+        proposal.metadata_uri = metadata_uri;
+        proposal.category = category;
         proposal.status = ProposalStatus::Pending;
         proposal.current_conviction = 0;
-        proposal.last_update_slot = Clock::get()?.slot;
+        proposal.stake_count = 0;
+        let clock = Clock::get()?;
+        proposal.last_update = current_time(&clock, cv_config);
+        proposal.decided_at_slot = 0;
+        proposal.expires_at_slot = proposal_expiry_slot(cv_config, clock.slot);
+        proposal.stream_duration_slots = if requested_amount == 0 || !pays_commons {
+            0
+        } else {
+            cv_config.stream_duration_slots
+        };
+        proposal.challenge_ends_at_slot = 0;
+        escrow_proposal_deposit(
+            proposal,
+            cv_config.proposal_deposit,
+            ctx.accounts.creator_token_account.as_ref(),
+            ctx.accounts.deposit_escrow.as_ref(),
+            &ctx.accounts.authority,
+            &ctx.accounts.token_program,
+        )?;
+        emit_proposal_created(proposal);
         Ok(())
     }
 
-    pub fn stake_tokens(ctx: Context<StakeTokens>, amount: u64) -> Result<()> {
-        require!(amount > 0, CustomError::InvalidStakeAmount);
-        let stake_account = &mut ctx.accounts.stake_account;
-        let proposal = &mut ctx.accounts.proposal;
+    /// Creates a proposal that, once approved, runs `instructions` via CPI signed
+    /// by the CV config PDA. The payload is fixed at creation so it cannot be
+    /// swapped after support has accrued.
+    pub fn create_executable_proposal(
+        ctx: Context<CreateExecutableProposal>,
+        metadata_uri: String,
+        instructions: Vec<ProposalInstruction>,
+    ) -> Result<()> {
+        require!(
+            metadata_uri.len() <= MAX_METADATA_URI_LEN,
+            CustomError::MetadataUriTooLong
+        );
+        require!(
+            !instructions.is_empty() && instructions.len() <= MAX_PROPOSAL_INSTRUCTIONS,
+            CustomError::InvalidProposalInstructions
+        );
         let cv_config = &mut ctx.accounts.cv_config;
-        let slot = ctx.accounts.clock.slot;
+        for instruction in instructions.iter() {
+            require!(
+                cv_config.allowed_programs.contains(&instruction.program_id),
+                CustomError::ProgramNotAllowed
+            );
+            // Staked tokens are never at the mercy of a proposal payload
+            require!(
+                instruction
+                    .accounts
+                    .iter()
+                    .all(|meta| meta.pubkey != cv_config.staking_vault),
+                CustomError::InvalidProposalInstructions
+            );
+        }
+
+        let proposal = &mut ctx.accounts.proposal;
+        proposal.id = take_proposal_id(cv_config)?;
+        proposal.creator = ctx.accounts.authority.key();
+        proposal.beneficiary = Pubkey::default();
+        proposal.request_mint = cv_config.commons_token_mint;
+        proposal.requested_amount = 0;
+        proposal.metadata_uri = metadata_uri;
+        proposal.category = String::new();
+        proposal.status = ProposalStatus::Pending;
+        proposal.current_conviction = 0;
+        proposal.stake_count = 0;
+        proposal.last_update = current_time(&ctx.accounts.clock, cv_config);
+        proposal.decided_at_slot = 0;
+        proposal.expires_at_slot = proposal_expiry_slot(cv_config, ctx.accounts.clock.slot);
+        proposal.stream_duration_slots = 0;
+        proposal.challenge_ends_at_slot = 0;
+        escrow_proposal_deposit(
+            proposal,
+            cv_config.proposal_deposit,
+            ctx.accounts.creator_token_account.as_ref(),
+            ctx.accounts.deposit_escrow.as_ref(),
+            &ctx.accounts.authority,
+            &ctx.accounts.token_program,
+        )?;
+        emit_proposal_created(proposal);
+
+        let proposal_instructions = &mut ctx.accounts.proposal_instructions;
+        proposal_instructions.proposal = proposal.key();
+        proposal_instructions.instructions = instructions;
+        proposal_instructions.executed = false;
+        proposal_instructions.bump = ctx.bumps.proposal_instructions;
+        Ok(())
+    }
+
+    /// Permissionless: replays an approved proposal's instructions. Every account
+    /// they reference, plus each target program, must be passed as remaining accounts.
+    pub fn execute_proposal_instructions<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteProposalInstructions<'info>>,
+    ) -> Result<()> {
+        // Read-only snapshot: the config itself may be rewritten by the CPIs below
+        let cv_config_info = ctx.accounts.cv_config.to_account_info();
+        let cv_config = CVConfig::try_deserialize(&mut &cv_config_info.data.borrow()[..])?;
+        require!(!cv_config.paused, CustomError::ConfigPaused);
         require!(
-            proposal.status == ProposalStatus::Pending,
-            CustomError::ProposalNotPending
+            ctx.accounts.proposal.status == ProposalStatus::Approved,
+            CustomError::ProposalNotApproved
+        );
+        let proposal_instructions = &mut ctx.accounts.proposal_instructions;
+        require!(
+            !proposal_instructions.executed,
+            CustomError::InstructionsAlreadyExecuted
         );
+        // Persist the flag before any CPI so a re-entrant call cannot replay the payload
+        proposal_instructions.executed = true;
+        proposal_instructions.exit(&crate::ID)?;
 
-        // Transfer Commons tokens from user to staking vault
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.user_commons_token_account.to_account_info(),
-            to: ctx.accounts.staking_vault.to_account_info(),
-            authority: ctx.accounts.authority.to_account_info(),
+        let seeds = &[b"cv_config".as_ref(), &[cv_config.cv_config_bump]];
+        let signer = &[&seeds[..]];
+        let find_account = |key: &Pubkey| -> Result<AccountInfo<'info>> {
+            if *key == cv_config_info.key() {
+                return Ok(cv_config_info.clone());
+            }
+            ctx.remaining_accounts
+                .iter()
+                .find(|info| info.key == key)
+                .cloned()
+                .ok_or_else(|| error!(CustomError::MissingInstructionAccount))
         };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        token::transfer(cpi_ctx, amount)?;
 
-        // Update stake account
-        stake_account.user = ctx.accounts.authority.key();
-        stake_account.proposal = proposal.key();
-        stake_account.staked_amount = stake_account
-            .staked_amount
-            .checked_add(amount)
-            .ok_or(CustomError::StakeOverflow)?;
-        stake_account.last_update_slot = slot;
-        stake_account.authority = ctx.accounts.authority.key();
+        for instruction in ctx.accounts.proposal_instructions.instructions.iter() {
+            require!(
+                cv_config.allowed_programs.contains(&instruction.program_id),
+                CustomError::ProgramNotAllowed
+            );
+            let mut account_infos = Vec::with_capacity(instruction.accounts.len() + 1);
+            let mut metas = Vec::with_capacity(instruction.accounts.len());
+            for meta in instruction.accounts.iter() {
+                let info = find_account(&meta.pubkey)?;
+                require!(
+                    !is_cv_controlled_token_account(&info, &cv_config_info.key()),
+                    CustomError::ProtectedTokenAccount
+                );
+                account_infos.push(info);
+                metas.push(AccountMeta {
+                    pubkey: meta.pubkey,
+                    is_signer: meta.is_signer,
+                    is_writable: meta.is_writable,
+                });
+            }
+            account_infos.push(find_account(&instruction.program_id)?);
+            let ix = Instruction {
+                program_id: instruction.program_id,
+                accounts: metas,
+                data: instruction.data.clone(),
+            };
+            invoke_signed(&ix, &account_infos, signer)?;
+        }
+        Ok(())
+    }
 
-        cv_config.total_staked = cv_config
-            .total_staked
-            .checked_add(amount)
-            .ok_or(CustomError::StakeOverflow)?;
+    pub fn set_allowed_programs(
+        ctx: Context<SetAllowedPrograms>,
+        allowed_programs: Vec<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            allowed_programs.len() <= MAX_ALLOWED_PROGRAMS,
+            CustomError::TooManyAllowedPrograms
+        );
+        ctx.accounts.cv_config.allowed_programs = allowed_programs;
+        Ok(())
+    }
+
+    /// Configures the dispute window passed proposals sit in before paying out.
+    /// `challenge_period_slots == 0` disables challenges. The arbiter only has to
+    /// sign, so it may be a plain key, a multisig PDA or a program signing via CPI.
+    pub fn set_challenge_params(
+        ctx: Context<SetChallengeParams>,
+        challenge_period_slots: u64,
+        challenge_bond: u64,
+        arbiter: Pubkey,
+    ) -> Result<()> {
+        require!(
+            challenge_period_slots == 0 || arbiter != Pubkey::default(),
+            CustomError::MissingArbiter
+        );
+        let cv_config = &mut ctx.accounts.cv_config;
+        cv_config.challenge_period_slots = challenge_period_slots;
+        cv_config.challenge_bond = challenge_bond;
+        cv_config.arbiter = arbiter;
+        Ok(())
+    }
+
+    pub fn create_abstain_proposal(ctx: Context<CreateAbstainProposal>) -> Result<()> {
+        let cv_config = &mut ctx.accounts.cv_config;
+        require!(
+            cv_config.abstain_proposal == Pubkey::default(),
+            CustomError::AbstainProposalExists
+        );
+        let proposal = &mut ctx.accounts.proposal;
+        proposal.id = take_proposal_id(cv_config)?;
+        cv_config.abstain_proposal = proposal.key();
+        proposal.creator = ctx.accounts.authority.key();
+        proposal.beneficiary = Pubkey::default();
+        proposal.request_mint = cv_config.commons_token_mint;
+        proposal.requested_amount = 0;
+        proposal.metadata_uri = String::new();
+        proposal.category = String::new();
+        proposal.status = ProposalStatus::Pending;
+        proposal.current_conviction = 0;
+        proposal.stake_count = 0;
+        proposal.last_update = current_time(&ctx.accounts.clock, cv_config);
+        proposal.decided_at_slot = 0;
+        proposal.expires_at_slot = 0;
+        proposal.stream_duration_slots = 0;
+        proposal.challenge_ends_at_slot = 0;
+        proposal.deposit_amount = 0;
+        proposal.deposit_refund_account = Pubkey::default();
+        emit_proposal_created(proposal);
+        Ok(())
+    }
+
+    pub fn stake_tokens(ctx: Context<StakeTokens>, amount: u64) -> Result<()> {
+        add_stake(ctx.accounts, amount, None)
+    }
+
+    /// Stakes `amount` and locks the whole position for `lock_duration`
+    /// (time base units). The position's weight is multiplied by the best
+    /// `lock_schedule` tier the duration reaches. Re-locking may not end earlier
+    /// than the existing lock.
+    pub fn stake_tokens_locked(
+        ctx: Context<StakeTokens>,
+        amount: u64,
+        lock_duration: u64,
+    ) -> Result<()> {
+        add_stake(ctx.accounts, amount, Some(lock_duration))
+    }
+
+    pub fn set_lock_schedule(
+        ctx: Context<SetLockSchedule>,
+        lock_schedule: Vec<LockTier>,
+    ) -> Result<()> {
+        validate_lock_schedule(&lock_schedule)?;
+        ctx.accounts.cv_config.lock_schedule = lock_schedule;
+        Ok(())
+    }
+
+    /// Caps the commons tokens paid out over any `spending_window_slots`; a window
+    /// of 0 removes every mint's cap. Starts fresh commons and category windows.
+    pub fn set_spending_cap(
+        ctx: Context<SetSpendingLimits>,
+        spending_window_slots: u64,
+        spending_cap: u64,
+    ) -> Result<()> {
+        let cv_config = &mut ctx.accounts.cv_config;
+        cv_config.spending_window_slots = spending_window_slots;
+        cv_config.spending_cap = spending_cap;
+        reset_spending_windows(cv_config);
+        Ok(())
+    }
+
+    /// Adds a named funding category or changes an existing one's cap, keeping
+    /// what it has already spent.
+    pub fn set_funding_category(
+        ctx: Context<SetSpendingLimits>,
+        name: String,
+        cap: u64,
+    ) -> Result<()> {
+        require!(
+            !name.is_empty() && name.len() <= MAX_FUNDING_CATEGORY_NAME_LEN,
+            CustomError::InvalidFundingCategoryName
+        );
+        let categories = &mut ctx.accounts.cv_config.funding_categories;
+        match categories.iter_mut().find(|category| category.name == name) {
+            Some(category) => category.cap = cap,
+            None => {
+                require!(
+                    categories.len() < MAX_FUNDING_CATEGORIES,
+                    CustomError::TooManyFundingCategories
+                );
+                categories.push(FundingCategory {
+                    name,
+                    cap,
+                    spending: SpendingWindow::default(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers (or replaces) the treasury account that pays proposals
+    /// requesting `treasury.mint`, with `spending_cap` bounding what it pays out
+    /// per spending window. Commons token requests always come from
+    /// `commons_treasury`.
+    pub fn register_treasury(ctx: Context<RegisterTreasury>, spending_cap: u64) -> Result<()> {
+        let mint = ctx.accounts.treasury.mint;
+        require!(
+            mint != ctx.accounts.cv_config.commons_token_mint,
+            CustomError::CommonsMintTreasury
+        );
+        let treasury = ctx.accounts.treasury.key();
+        let registry = &mut ctx.accounts.treasury_registry;
+        registry.cv_config = ctx.accounts.cv_config.key();
+        registry.bump = ctx.bumps.treasury_registry;
+        match registry
+            .treasuries
+            .iter_mut()
+            .find(|entry| entry.mint == mint)
+        {
+            Some(entry) => {
+                entry.treasury = treasury;
+                entry.spending_cap = spending_cap;
+            }
+            None => {
+                require!(
+                    registry.treasuries.len() < MAX_REGISTERED_TREASURIES,
+                    CustomError::TooManyTreasuries
+                );
+                registry.treasuries.push(RegisteredTreasury {
+                    mint,
+                    treasury,
+                    spending_cap,
+                    spending: SpendingWindow::default(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_guardian(ctx: Context<SetGuardian>, guardian: Pubkey) -> Result<()> {
+        ctx.accounts.cv_config.guardian = guardian;
+        Ok(())
+    }
+
+    /// Guardian-only: halts new stakes, support increases, execution and
+    /// treasury payouts, and opens `emergency_withdraw_stake` to every staker.
+    pub fn emergency_pause(ctx: Context<GuardianAction>) -> Result<()> {
+        ctx.accounts.cv_config.paused = true;
+        Ok(())
+    }
+
+    pub fn resume(ctx: Context<GuardianAction>) -> Result<()> {
+        ctx.accounts.cv_config.paused = false;
+        Ok(())
+    }
+
+    /// Switches between linear and quadratic conviction. Only possible while
+    /// nothing is staked, since existing weights would no longer add up.
+    pub fn set_conviction_mode(
+        ctx: Context<SetConvictionParams>,
+        conviction_mode: ConvictionMode,
+    ) -> Result<()> {
+        let cv_config = &mut ctx.accounts.cv_config;
+        require!(
+            cv_config.total_staked == 0,
+            CustomError::ConvictionModeLocked
+        );
+        cv_config.conviction_mode = conviction_mode;
+        Ok(())
+    }
 
-        update_conviction_for_proposal(proposal, amount as i128, cv_config, slot)?;
+    /// Sets the Merkle root of verified people allowed to stake; all zeroes
+    /// lifts the gate. Changing it invalidates earlier verifications.
+    pub fn set_personhood_root(
+        ctx: Context<SetConvictionParams>,
+        personhood_root: [u8; 32],
+    ) -> Result<()> {
+        ctx.accounts.cv_config.personhood_root = personhood_root;
+        Ok(())
+    }
+
+    /// Proves the signer is in the personhood set, recording it against the
+    /// current root so later stakes need no proof.
+    pub fn verify_personhood(ctx: Context<VerifyPersonhood>, proof: Vec<[u8; 32]>) -> Result<()> {
+        let root = ctx.accounts.cv_config.personhood_root;
+        require!(root != [0; 32], CustomError::PersonhoodNotRequired);
+        let leaf = hash::hashv(&[ctx.accounts.authority.key().as_ref()]).to_bytes();
+        require!(
+            verify_merkle_proof(proof, root, leaf),
+            CustomError::InvalidPersonhoodProof
+        );
+        let record = &mut ctx.accounts.personhood;
+        record.user = ctx.accounts.authority.key();
+        record.root = root;
+        record.bump = ctx.bumps.personhood;
         Ok(())
     }
 
+    /// While paused, returns a staker's whole position on any proposal,
+    /// whatever its status or lock, and closes the stake account.
+    pub fn emergency_withdraw_stake(ctx: Context<WithdrawStake>) -> Result<()> {
+        require!(ctx.accounts.cv_config.paused, CustomError::ConfigNotPaused);
+        exit_stake(ctx.accounts)
+    }
+
     pub fn unstake_tokens(ctx: Context<UnstakeTokens>, amount: u64) -> Result<()> {
         require!(amount > 0, CustomError::InvalidStakeAmount);
         let stake_account = &mut ctx.accounts.stake_account;
         let proposal = &mut ctx.accounts.proposal;
-        let slot = ctx.accounts.clock.slot;
+        let now = current_time(&ctx.accounts.clock, &ctx.accounts.cv_config);
 
         require!(
             proposal.status == ProposalStatus::Pending,
@@ -113,15 +590,48 @@ pub mod commons_conviction_voting {
             stake_account.staked_amount >= amount,
             CustomError::InsufficientStakedAmount
         );
+        require!(now >= stake_account.locked_until, CustomError::StakeLocked);
+
+        // Release whatever the voter's remaining support no longer carries; in
+        // quadratic mode that is not proportional to `amount`
+        let allocated = voter_allocation(&ctx.accounts.voter_account, &proposal.key())?;
+        let mode = ctx.accounts.cv_config.conviction_mode;
+        let multiplier = stake_account.multiplier;
+        let remaining_weight = voter_weight(
+            stake_account.staked_amount - amount,
+            multiplier,
+            allocated,
+            mode,
+        )?;
+        let weight = voter_weight(stake_account.staked_amount, multiplier, allocated, mode)?
+            .checked_sub(remaining_weight)
+            .ok_or(CustomError::StakeUnderflow)?;
 
         let bump = {
             let cv_config = &mut ctx.accounts.cv_config;
-            update_conviction_for_proposal(proposal, -(amount as i128), cv_config, slot)?;
+            let conviction_before =
+                update_conviction_for_proposal(proposal, -(weight as i128), cv_config, now)?;
 
             cv_config.total_staked = cv_config
                 .total_staked
-                .checked_sub(amount)
+                .checked_sub(weight)
                 .ok_or(CustomError::StakeUnderflow)?;
+            emit!(Unstaked {
+                user: stake_account.user,
+                proposal: proposal.key(),
+                amount,
+                weighted_amount: weight,
+                conviction_before,
+                conviction_after: proposal.current_conviction,
+                threshold: stake_event_threshold(
+                    proposal,
+                    cv_config,
+                    &ctx.accounts.commons_treasury,
+                    ctx.accounts.treasury_registry.as_deref(),
+                    ctx.accounts.abstain_proposal.as_ref(),
+                    now,
+                ),
+            });
 
             cv_config.cv_config_bump
         };
@@ -130,7 +640,15 @@ pub mod commons_conviction_voting {
             .staked_amount
             .checked_sub(amount)
             .ok_or(CustomError::InsufficientStakedAmount)?;
-        stake_account.last_update_slot = slot;
+        stake_account.weighted_amount =
+            remaining_weight - voter_weight(0, multiplier, allocated, mode)?;
+        stake_account.last_update = now;
+        if stake_account.staked_amount == 0 {
+            proposal.stake_count = proposal
+                .stake_count
+                .checked_sub(1)
+                .ok_or(CustomError::StakeUnderflow)?;
+        }
 
         // Transfer Commons tokens from staking vault to user
         let cpi_accounts = Transfer {
@@ -148,23 +666,45 @@ pub mod commons_conviction_voting {
     }
 
     pub fn check_and_execute(ctx: Context<CheckAndExecute>) -> Result<()> {
+        require!(!ctx.accounts.cv_config.paused, CustomError::ConfigPaused);
         let proposal = &mut ctx.accounts.proposal;
+        let slot = ctx.accounts.clock.slot;
         require!(
             proposal.status == ProposalStatus::Pending,
             CustomError::ProposalNotPending
         );
+        require!(
+            !proposal_expired(proposal, slot),
+            CustomError::ProposalExpired
+        );
 
-        let (required, bump) = {
+        let (required, challenge_period_slots) = {
             let cv_config = &ctx.accounts.cv_config;
-            (
-                compute_required_conviction(
-                    proposal.requested_amount,
-                    ctx.accounts.commons_treasury.amount,
-                    cv_config.total_staked,
-                    cv_config,
-                )?,
-                cv_config.cv_config_bump,
-            )
+            require!(
+                proposal.key() != cv_config.abstain_proposal,
+                CustomError::AbstainProposalNotExecutable
+            );
+            check_payout_treasury(
+                proposal,
+                cv_config,
+                ctx.accounts.treasury_registry.as_deref(),
+                &ctx.accounts.commons_treasury,
+            )?;
+            let now = current_time(&ctx.accounts.clock, cv_config);
+            let conviction_before = update_conviction_for_proposal(proposal, 0, cv_config, now)?;
+            emit!(ConvictionUpdated {
+                proposal: proposal.key(),
+                conviction_before,
+                conviction_after: proposal.current_conviction,
+            });
+            let required = required_conviction(
+                proposal,
+                cv_config,
+                ctx.accounts.commons_treasury.amount,
+                ctx.accounts.abstain_proposal.as_deref(),
+                now,
+            )?;
+            (required, cv_config.challenge_period_slots)
         };
 
         require!(
@@ -172,74 +712,608 @@ pub mod commons_conviction_voting {
             CustomError::ThresholdNotReached
         );
 
+        proposal.decided_at_slot = slot;
+        proposal.status = if challenge_period_slots > 0 {
+            ProposalStatus::Challengeable
+        } else {
+            ProposalStatus::Approved
+        };
+        emit!(ProposalExecuted {
+            proposal: proposal.key(),
+            beneficiary: proposal.beneficiary,
+            request_mint: proposal.request_mint,
+            requested_amount: proposal.requested_amount,
+            conviction: proposal.current_conviction,
+            threshold: required,
+            status: proposal.status.clone(),
+        });
+
+        // With a dispute window configured, funds only move via `finalize_proposal`,
+        // which charges the budget; it must have room now as well
+        if challenge_period_slots > 0 {
+            spend_budget(
+                &mut ctx.accounts.cv_config,
+                ctx.accounts.treasury_registry.as_deref_mut(),
+                proposal,
+                slot,
+                false,
+            )?;
+            proposal.challenge_ends_at_slot = slot.saturating_add(challenge_period_slots);
+            return Ok(());
+        }
+
+        release_funds(
+            proposal,
+            &mut ctx.accounts.cv_config,
+            ctx.accounts.treasury_registry.as_deref_mut(),
+            &ctx.accounts.commons_treasury,
+            ctx.accounts.beneficiary.as_ref(),
+            &ctx.accounts.token_program,
+            slot,
+        )
+    }
+
+    /// Permissionless: approves and pays out a passed proposal once its dispute
+    /// window has closed without an outstanding challenge.
+    pub fn finalize_proposal(ctx: Context<FinalizeProposal>) -> Result<()> {
+        require!(!ctx.accounts.cv_config.paused, CustomError::ConfigPaused);
+        let proposal = &mut ctx.accounts.proposal;
+        let slot = ctx.accounts.clock.slot;
+        require!(
+            proposal.status == ProposalStatus::Challengeable,
+            CustomError::ProposalNotChallengeable
+        );
+        require!(
+            challenge_window_closed(proposal, slot),
+            CustomError::ChallengeWindowOpen
+        );
+        check_payout_treasury(
+            proposal,
+            &ctx.accounts.cv_config,
+            ctx.accounts.treasury_registry.as_deref(),
+            &ctx.accounts.commons_treasury,
+        )?;
+
         proposal.status = ProposalStatus::Approved;
-        proposal.last_update_slot = ctx.accounts.clock.slot;
+        proposal.decided_at_slot = slot;
+        release_funds(
+            proposal,
+            &mut ctx.accounts.cv_config,
+            ctx.accounts.treasury_registry.as_deref_mut(),
+            &ctx.accounts.commons_treasury,
+            ctx.accounts.beneficiary.as_ref(),
+            &ctx.accounts.token_program,
+            slot,
+        )
+    }
+
+    /// Any commons token holder can dispute a passed proposal while its window is
+    /// open by escrowing the configured bond. Each proposal can be challenged once.
+    pub fn challenge_proposal(ctx: Context<ChallengeProposal>) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        require!(
+            proposal.status == ProposalStatus::Challengeable,
+            CustomError::ProposalNotChallengeable
+        );
+        require!(
+            !challenge_window_closed(proposal, ctx.accounts.clock.slot),
+            CustomError::ChallengeWindowClosed
+        );
+        proposal.status = ProposalStatus::Disputed;
+
+        let bond = ctx.accounts.cv_config.challenge_bond;
+        let challenge = &mut ctx.accounts.challenge;
+        challenge.proposal = proposal.key();
+        challenge.challenger = ctx.accounts.challenger.key();
+        challenge.refund_account = ctx.accounts.challenger_token_account.key();
+        challenge.bond = bond;
+        challenge.resolved = false;
+        challenge.bump = ctx.bumps.challenge;
+
+        if bond > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.challenger_token_account.to_account_info(),
+                to: ctx.accounts.bond_escrow.to_account_info(),
+                authority: ctx.accounts.challenger.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+            token::transfer(cpi_ctx, bond)?;
+        }
+        Ok(())
+    }
+
+    /// Arbiter ruling on a disputed proposal. Upholding the challenge rejects the
+    /// proposal and refunds the bond; dismissing it slashes the bond to the
+    /// treasury and lets the proposal finalize once its window has closed.
+    pub fn resolve_challenge(ctx: Context<ResolveChallenge>, uphold: bool) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        require!(
+            proposal.status == ProposalStatus::Disputed,
+            CustomError::ProposalNotDisputed
+        );
+        proposal.status = if uphold {
+            ProposalStatus::Rejected
+        } else {
+            ProposalStatus::Challengeable
+        };
+        proposal.decided_at_slot = ctx.accounts.clock.slot;
+        let challenge = &mut ctx.accounts.challenge;
+        challenge.resolved = true;
+
+        let cv_config = &ctx.accounts.cv_config;
+        let seeds = &[b"cv_config".as_ref(), &[cv_config.cv_config_bump]];
+        let signer = &[&seeds[..]];
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        if challenge.bond > 0 {
+            let destination = if uphold {
+                ctx.accounts.challenger_token_account.to_account_info()
+            } else {
+                ctx.accounts.commons_treasury.to_account_info()
+            };
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.bond_escrow.to_account_info(),
+                to: destination,
+                authority: cv_config.to_account_info(), // PDA authority
+            };
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program.clone(), cpi_accounts, signer);
+            token::transfer(cpi_ctx, challenge.bond)?;
+        }
+
+        // Escrow rent goes back to whoever opened the challenge
+        let cpi_accounts = CloseAccount {
+            account: ctx.accounts.bond_escrow.to_account_info(),
+            destination: ctx.accounts.challenger.to_account_info(),
+            authority: cv_config.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::close_account(cpi_ctx)
+    }
+
+    /// Permissionless: opens the stream for an approved streamed proposal. The
+    /// stream starts at the approval slot regardless of when this is called.
+    pub fn open_funding_stream(ctx: Context<OpenFundingStream>) -> Result<()> {
+        let proposal = &ctx.accounts.proposal;
+        require!(
+            proposal.status == ProposalStatus::Approved,
+            CustomError::ProposalNotApproved
+        );
+        require!(
+            proposal.stream_duration_slots > 0,
+            CustomError::ProposalNotStreamed
+        );
+
+        let funding_stream = &mut ctx.accounts.funding_stream;
+        funding_stream.proposal = proposal.key();
+        funding_stream.beneficiary = proposal.beneficiary;
+        funding_stream.total_amount = proposal.requested_amount;
+        funding_stream.claimed_amount = 0;
+        funding_stream.start_slot = proposal.decided_at_slot;
+        funding_stream.end_slot = proposal
+            .decided_at_slot
+            .saturating_add(proposal.stream_duration_slots);
+        funding_stream.cancelled = false;
+        funding_stream.bump = ctx.bumps.funding_stream;
+        Ok(())
+    }
+
+    /// Permissionless: pays whatever has vested so far to the stream beneficiary.
+    pub fn claim_funding_stream(ctx: Context<ClaimFundingStream>) -> Result<()> {
+        require!(!ctx.accounts.cv_config.paused, CustomError::ConfigPaused);
+        let funding_stream = &mut ctx.accounts.funding_stream;
+        let vested = vested_stream_amount(funding_stream, ctx.accounts.clock.slot);
+        let amount = vested.saturating_sub(funding_stream.claimed_amount);
+        require!(amount > 0, CustomError::NothingToClaim);
+
+        funding_stream.claimed_amount = vested;
+        let cv_config = &mut ctx.accounts.cv_config;
+        cv_config.committed_stream_amount = cv_config
+            .committed_stream_amount
+            .checked_sub(amount)
+            .ok_or(CustomError::StreamOverflow)?;
 
-        // Transfer requested_amount from commons_treasury to recipient
         let cpi_accounts = Transfer {
             from: ctx.accounts.commons_treasury.to_account_info(),
-            to: ctx.accounts.recipient_token_account.to_account_info(),
-            authority: ctx.accounts.cv_config.to_account_info(), // PDA authority
+            to: ctx.accounts.beneficiary.to_account_info(),
+            authority: cv_config.to_account_info(), // PDA authority
         };
         let cpi_program = ctx.accounts.token_program.to_account_info();
-        let seeds = &[b"cv_config".as_ref(), &[bump]];
+        let seeds = &[b"cv_config".as_ref(), &[cv_config.cv_config_bump]];
         let signer = &[&seeds[..]];
         let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-        token::transfer(cpi_ctx, proposal.requested_amount)?;
-
+        token::transfer(cpi_ctx, amount)?;
         Ok(())
     }
 
-    pub fn withdraw_stake(ctx: Context<WithdrawStake>) -> Result<()> {
-        let stake_account = &mut ctx.accounts.stake_account;
-        let proposal = &mut ctx.accounts.proposal;
+    /// Stops a stream at the current slot and releases the unvested remainder
+    /// back to the treasury. Requires the CV config PDA as signer, so it can only
+    /// run through an approved "stop funding" proposal.
+    pub fn cancel_funding_stream(ctx: Context<CancelFundingStream>) -> Result<()> {
+        let funding_stream = &mut ctx.accounts.funding_stream;
+        require!(!funding_stream.cancelled, CustomError::StreamCancelled);
         let slot = ctx.accounts.clock.slot;
+        let vested = vested_stream_amount(funding_stream, slot);
+        let released = funding_stream.total_amount.saturating_sub(vested);
 
+        funding_stream.total_amount = vested;
+        funding_stream.end_slot = slot.min(funding_stream.end_slot);
+        funding_stream.cancelled = true;
+        let cv_config = &mut ctx.accounts.cv_config;
+        cv_config.committed_stream_amount = cv_config
+            .committed_stream_amount
+            .checked_sub(released)
+            .ok_or(CustomError::StreamOverflow)?;
+        Ok(())
+    }
+
+    pub fn withdraw_stake(ctx: Context<WithdrawStake>) -> Result<()> {
+        let now = current_time(&ctx.accounts.clock, &ctx.accounts.cv_config);
         require!(
-            proposal.status != ProposalStatus::Pending,
+            ctx.accounts.proposal.status != ProposalStatus::Pending,
             CustomError::ProposalStillPending
         );
+        require!(
+            now >= ctx.accounts.stake_account.locked_until,
+            CustomError::StakeLocked
+        );
+        exit_stake(ctx.accounts)
+    }
 
-        let amount = stake_account.staked_amount;
-        if amount > 0 {
-            let bump = {
-                let cv_config = &mut ctx.accounts.cv_config;
-                update_conviction_for_proposal(proposal, -(amount as i128), cv_config, slot)?;
-
-                cv_config.total_staked = cv_config
-                    .total_staked
-                    .checked_sub(amount)
-                    .ok_or(CustomError::StakeUnderflow)?;
+    /// Permissionless: once a proposal is settled, returns its creation deposit
+    /// to the creator if it was approved or cancelled, or forfeits it to the
+    /// treasury if it expired or was rejected.
+    pub fn settle_proposal_deposit(ctx: Context<SettleProposalDeposit>) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        let refund = match proposal.status {
+            ProposalStatus::Approved | ProposalStatus::Cancelled => true,
+            ProposalStatus::Expired | ProposalStatus::Rejected => false,
+            _ => return err!(CustomError::ProposalDepositLocked),
+        };
+        let amount = proposal.deposit_amount;
+        proposal.deposit_amount = 0;
 
-                cv_config.cv_config_bump
+        let cv_config = &ctx.accounts.cv_config;
+        let seeds = &[b"cv_config".as_ref(), &[cv_config.cv_config_bump]];
+        let signer = &[&seeds[..]];
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        if amount > 0 {
+            let destination = if refund {
+                ctx.accounts.deposit_refund_account.to_account_info()
+            } else {
+                ctx.accounts.commons_treasury.to_account_info()
             };
-
-            // Transfer Commons tokens from staking vault to user
             let cpi_accounts = Transfer {
-                from: ctx.accounts.staking_vault.to_account_info(),
-                to: ctx.accounts.user_commons_token_account.to_account_info(),
-                authority: ctx.accounts.cv_config.to_account_info(), // PDA authority
+                from: ctx.accounts.deposit_escrow.to_account_info(),
+                to: destination,
+                authority: cv_config.to_account_info(), // PDA authority
             };
-            let cpi_program = ctx.accounts.token_program.to_account_info();
-            let seeds = &[b"cv_config".as_ref(), &[bump]];
-            let signer = &[&seeds[..]];
-            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program.clone(), cpi_accounts, signer);
             token::transfer(cpi_ctx, amount)?;
         }
 
-        stake_account.close(ctx.accounts.authority.to_account_info())?;
+        // Escrow rent always goes back to the creator who paid it
+        let cpi_accounts = CloseAccount {
+            account: ctx.accounts.deposit_escrow.to_account_info(),
+            destination: ctx.accounts.creator.to_account_info(),
+            authority: cv_config.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::close_account(cpi_ctx)
+    }
+
+    /// Permissionless: closes a finished proposal once every stake and support
+    /// allocation has left it and nothing else still needs it, returning its
+    /// rent (and that of its instruction set) to the creator. The full record
+    /// is emitted as `ProposalClosed` first.
+    pub fn close_proposal(ctx: Context<CloseProposal>) -> Result<()> {
+        let proposal = &ctx.accounts.proposal;
+        require!(
+            proposal.key() != ctx.accounts.cv_config.abstain_proposal,
+            CustomError::AbstainProposalPermanent
+        );
+        require!(
+            matches!(
+                proposal.status,
+                ProposalStatus::Approved
+                    | ProposalStatus::Rejected
+                    | ProposalStatus::Cancelled
+                    | ProposalStatus::Expired
+            ),
+            CustomError::ProposalNotFinished
+        );
+        require!(proposal.stake_count == 0, CustomError::ProposalHasStakes);
+        require!(
+            proposal.deposit_amount == 0,
+            CustomError::ProposalNotSettled
+        );
+        let approved = proposal.status == ProposalStatus::Approved;
+        // An approved stream is opened from the proposal; claims no longer need it
+        require!(
+            !approved
+                || proposal.stream_duration_slots == 0
+                || !ctx.accounts.funding_stream.data_is_empty(),
+            CustomError::ProposalNotSettled
+        );
+
+        let instructions_info = ctx.accounts.proposal_instructions.to_account_info();
+        if !instructions_info.data_is_empty() {
+            let instructions =
+                ProposalInstructions::try_deserialize(&mut &instructions_info.data.borrow()[..])?;
+            require!(
+                !approved || instructions.executed,
+                CustomError::ProposalNotSettled
+            );
+            close_program_account(&instructions_info, &ctx.accounts.creator)?;
+        }
 
+        emit!(ProposalClosed {
+            proposal: proposal.key(),
+            id: proposal.id,
+            creator: proposal.creator,
+            beneficiary: proposal.beneficiary,
+            request_mint: proposal.request_mint,
+            requested_amount: proposal.requested_amount,
+            metadata_uri: proposal.metadata_uri.clone(),
+            category: proposal.category.clone(),
+            status: proposal.status.clone(),
+            conviction: proposal.current_conviction,
+            decided_at_slot: proposal.decided_at_slot,
+        });
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct Initialize {}
+    /// Permissionless crank: closes zero-balance stake accounts, passed as
+    /// remaining accounts in `(stake, owner)` pairs, refunding each owner's rent.
+    /// Stakes left empty by `unstake_tokens` can be swept whether or not their
+    /// proposal still exists.
+    pub fn close_empty_stakes<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CloseEmptyStakes>,
+    ) -> Result<()> {
+        // `is_multiple_of` needs Rust 1.87, newer than the SBF toolchain
+        #[allow(clippy::manual_is_multiple_of)]
+        let paired = ctx.remaining_accounts.len() % 2 == 0;
+        require!(paired, CustomError::StakeAccountsMismatch);
+        for pair in ctx.remaining_accounts.chunks(2) {
+            let (stake_info, owner_info) = (&pair[0], &pair[1]);
+            let stake_account = Account::<Stake>::try_from(stake_info)?;
+            require!(
+                stake_account.authority == owner_info.key(),
+                CustomError::StakeAccountsMismatch
+            );
+            require!(stake_account.staked_amount == 0, CustomError::StakeNotEmpty);
+            stake_account.close(owner_info.clone())?;
+        }
+        Ok(())
+    }
 
-#[derive(Accounts)]
-#[instruction(decay_rate: u64, max_ratio: u64, weight_exponent: u64, min_threshold: u64)]
-pub struct InitializeCvConfig<'info> {
-    #[account(init, payer = authority, space = 178, seeds = [b"cv_config"], bump)]
+    pub fn cancel_proposal(ctx: Context<CancelProposal>) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        require!(
+            proposal.key() != ctx.accounts.cv_config.abstain_proposal,
+            CustomError::AbstainProposalPermanent
+        );
+        require!(
+            proposal.status == ProposalStatus::Pending,
+            CustomError::ProposalNotPending
+        );
+        proposal.status = ProposalStatus::Cancelled;
+        proposal.decided_at_slot = ctx.accounts.clock.slot;
+        Ok(())
+    }
+
+    pub fn expire_proposal(ctx: Context<ExpireProposal>) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        let slot = ctx.accounts.clock.slot;
+        require!(
+            proposal.status == ProposalStatus::Pending,
+            CustomError::ProposalNotPending
+        );
+        require!(
+            proposal_expired(proposal, slot),
+            CustomError::ProposalNotExpired
+        );
+        proposal.status = ProposalStatus::Expired;
+        proposal.decided_at_slot = slot;
+        Ok(())
+    }
+
+    pub fn reject_proposal(ctx: Context<RejectProposal>) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        require!(
+            proposal.key() != ctx.accounts.cv_config.abstain_proposal,
+            CustomError::AbstainProposalPermanent
+        );
+        require!(
+            proposal.status == ProposalStatus::Pending,
+            CustomError::ProposalNotPending
+        );
+        proposal.status = ProposalStatus::Rejected;
+        proposal.decided_at_slot = ctx.accounts.clock.slot;
+        Ok(())
+    }
+
+    pub fn deposit_tokens(ctx: Context<DepositTokens>, amount: u64) -> Result<()> {
+        require!(amount > 0, CustomError::InvalidStakeAmount);
+        require!(!ctx.accounts.cv_config.paused, CustomError::ConfigPaused);
+        check_personhood(&ctx.accounts.cv_config, ctx.accounts.personhood.as_deref())?;
+
+        // Transfer Commons tokens from user to staking vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.user_commons_token_account.to_account_info(),
+            to: ctx.accounts.staking_vault.to_account_info(),
+            authority: ctx.accounts.authority.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+
+        let voter_account = &mut ctx.accounts.voter_account;
+        voter_account.authority = ctx.accounts.authority.key();
+        voter_account.voter_bump = ctx.bumps.voter_account;
+        voter_account.deposited_amount = voter_account
+            .deposited_amount
+            .checked_add(amount)
+            .ok_or(CustomError::StakeOverflow)?;
+        Ok(())
+    }
+
+    pub fn withdraw_tokens(ctx: Context<WithdrawTokens>, amount: u64) -> Result<()> {
+        require!(amount > 0, CustomError::InvalidStakeAmount);
+        let voter_account = &mut ctx.accounts.voter_account;
+        require!(
+            voter_account.unallocated_amount() >= amount,
+            CustomError::InsufficientUnallocatedDeposit
+        );
+        voter_account.deposited_amount = voter_account
+            .deposited_amount
+            .checked_sub(amount)
+            .ok_or(CustomError::InsufficientUnallocatedDeposit)?;
+
+        // Transfer Commons tokens from staking vault to user
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.staking_vault.to_account_info(),
+            to: ctx.accounts.user_commons_token_account.to_account_info(),
+            authority: ctx.accounts.cv_config.to_account_info(), // PDA authority
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let seeds = &[
+            b"cv_config".as_ref(),
+            &[ctx.accounts.cv_config.cv_config_bump],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+
+        Ok(())
+    }
+
+    /// Sets the voter's support for each listed proposal to an absolute amount.
+    /// Each proposal must be passed as a writable remaining account, followed by
+    /// the owner's stake PDA for it (which need not exist), in the same order as
+    /// `allocations`.
+    pub fn reallocate_support<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ReallocateSupport<'info>>,
+        allocations: Vec<SupportAllocation>,
+    ) -> Result<()> {
+        require!(
+            allocations.len() * 2 == ctx.remaining_accounts.len(),
+            CustomError::AllocationAccountsMismatch
+        );
+        let slot = ctx.accounts.clock.slot;
+        let cv_config = &mut ctx.accounts.cv_config;
+        let now = current_time(&ctx.accounts.clock, cv_config);
+        let voter_account = &mut ctx.accounts.voter_account;
+
+        for (allocation, pair) in allocations.iter().zip(ctx.remaining_accounts.chunks(2)) {
+            let (proposal_info, stake_info) = (&pair[0], &pair[1]);
+            require_keys_eq!(
+                proposal_info.key(),
+                allocation.proposal,
+                CustomError::AllocationAccountsMismatch
+            );
+            let mut proposal = Account::<Proposal>::try_from(proposal_info)?;
+            let mut stake =
+                voter_stake(stake_info, &voter_account.authority, &allocation.proposal)?;
+            set_support(
+                voter_account,
+                cv_config,
+                allocation.proposal,
+                &mut proposal,
+                stake.as_deref_mut(),
+                allocation.amount,
+                slot,
+                now,
+            )?;
+            proposal.exit(&crate::ID)?;
+            if let Some(stake) = stake {
+                stake.exit(&crate::ID)?;
+            }
+        }
+
+        require!(
+            voter_account.allocated_amount()? <= voter_account.deposited_amount,
+            CustomError::InsufficientUnallocatedDeposit
+        );
+        Ok(())
+    }
+
+    /// Hands allocation rights over the owner's deposit to `to`. The tokens stay
+    /// in the owner's voter account and remain withdrawable by them. Existing
+    /// allocations are withdrawn first, so every allocated proposal must be
+    /// passed as a writable remaining account, followed by the owner's stake PDA
+    /// for it.
+    pub fn delegate<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UpdateDelegation<'info>>,
+        to: Pubkey,
+    ) -> Result<()> {
+        require!(
+            to != Pubkey::default() && to != ctx.accounts.authority.key(),
+            CustomError::InvalidDelegate
+        );
+        clear_allocations(
+            &mut ctx.accounts.voter_account,
+            &mut ctx.accounts.cv_config,
+            ctx.remaining_accounts,
+            &ctx.accounts.clock,
+        )?;
+        ctx.accounts.voter_account.delegate = to;
+        Ok(())
+    }
+
+    /// Returns allocation rights to the owner, withdrawing whatever support the
+    /// delegate allocated. Takes the same remaining accounts as `delegate`.
+    pub fn undelegate<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UpdateDelegation<'info>>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.voter_account.delegate != Pubkey::default(),
+            CustomError::NotDelegated
+        );
+        clear_allocations(
+            &mut ctx.accounts.voter_account,
+            &mut ctx.accounts.cv_config,
+            ctx.remaining_accounts,
+            &ctx.accounts.clock,
+        )?;
+        ctx.accounts.voter_account.delegate = Pubkey::default();
+        Ok(())
+    }
+
+    /// Returns the proposal's conviction decayed up to the present.
+    pub fn view_conviction(ctx: Context<ViewProposal>) -> Result<u64> {
+        Ok(ctx.accounts.present_conviction())
+    }
+
+    /// Returns the conviction the proposal currently needs to pass.
+    pub fn view_required_conviction(ctx: Context<ViewProposal>) -> Result<u64> {
+        ctx.accounts.required_conviction()
+    }
+
+    /// Returns the slot at which the proposal passes if its support stays as it
+    /// is, or `None` if it will not.
+    pub fn view_predicted_pass_slot(ctx: Context<ViewProposal>) -> Result<Option<u64>> {
+        let accounts = &ctx.accounts;
+        let slot = accounts.clock.slot;
+        if accounts.proposal.status != ProposalStatus::Pending
+            || proposal_expired(&accounts.proposal, slot)
+        {
+            return Ok(None);
+        }
+        Ok(predict_pass_time(
+            accounts.present_conviction(),
+            accounts.required_conviction()?,
+            slot,
+        ))
+    }
+}
+
+#[derive(Accounts)]
+pub struct Initialize {}
+
+#[derive(Accounts)]
+#[instruction(decay_rate: u64, max_ratio: u64, weight_exponent: u64, min_threshold: u64, proposal_expiry_slots: u64, signaling_threshold: u64, stream_duration_slots: u64, proposal_deposit: u64, time_base: TimeBase)]
+pub struct InitializeCvConfig<'info> {
+    #[account(init, payer = authority, space = 8 + 8 + 8 + 8 + 8 + 32 + 32 + 1 + 32 + 1 + 32 + 8 + 8 + 8 + 8 + 32 + 4 + MAX_ALLOWED_PROGRAMS * 32 + 8 + 8 + 8 + 8 + 32 + 8 + 1 + 8 + 8 + 4 + MAX_LOCK_TIERS * (8 + 8) + 8 + 8 + SPENDING_WINDOW_SPACE + 4 + MAX_FUNDING_CATEGORIES * (4 + MAX_FUNDING_CATEGORY_NAME_LEN + 8 + SPENDING_WINDOW_SPACE) + 32 + 1 + 1 + 32, seeds = [b"cv_config"], bump)]
     pub cv_config: Account<'info, CVConfig>,
     pub commons_treasury: Account<'info, TokenAccount>,
     pub commons_token_mint: Account<'info, Mint>,
@@ -259,25 +1333,224 @@ pub struct InitializeCvConfig<'info> {
 }
 
 #[derive(Accounts)]
-#[instruction(requested_amount: u64, metadata_hash: String)]
+#[instruction(requested_amount: u64, metadata_uri: String, category: String)]
 pub struct CreateProposal<'info> {
-    #[account(init, payer = authority, space = 8 + 32 + 8 + 4 + 32 + 8 + 8, seeds = [b"proposal", authority.key().as_ref(), &requested_amount.to_le_bytes()], bump)]
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(init, payer = authority, space = PROPOSAL_SPACE, seeds = [b"proposal", cv_config.key().as_ref(), &cv_config.proposal_count.to_le_bytes()], bump)]
+    pub proposal: Account<'info, Proposal>,
+    /// Token account that receives the funds if the proposal passes; its mint
+    /// is the asset requested. Omitted for signaling proposals.
+    pub beneficiary: Option<Account<'info, TokenAccount>>,
+    #[account(address = cv_config.commons_token_mint)]
+    pub commons_token_mint: Account<'info, Mint>,
+    /// Pays the creation deposit. Required when `cv_config.proposal_deposit > 0`.
+    #[account(mut, token::mint = commons_token_mint)]
+    pub creator_token_account: Option<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = authority,
+        token::mint = commons_token_mint,
+        token::authority = cv_config,
+        seeds = [b"proposal_deposit", proposal.key().as_ref()],
+        bump
+    )]
+    pub deposit_escrow: Option<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct CreateAbstainProposal<'info> {
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump, has_one = authority)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(init, payer = authority, space = PROPOSAL_SPACE, seeds = [b"proposal", cv_config.key().as_ref(), &cv_config.proposal_count.to_le_bytes()], bump)]
+    pub proposal: Account<'info, Proposal>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+#[instruction(metadata_uri: String, instructions: Vec<ProposalInstruction>)]
+pub struct CreateExecutableProposal<'info> {
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(init, payer = authority, space = PROPOSAL_SPACE, seeds = [b"proposal", cv_config.key().as_ref(), &cv_config.proposal_count.to_le_bytes()], bump)]
     pub proposal: Account<'info, Proposal>,
+    #[account(
+        init,
+        payer = authority,
+        space = ProposalInstructions::space(&instructions),
+        seeds = [b"proposal_instructions", proposal.key().as_ref()],
+        bump
+    )]
+    pub proposal_instructions: Account<'info, ProposalInstructions>,
+    #[account(address = cv_config.commons_token_mint)]
+    pub commons_token_mint: Account<'info, Mint>,
+    /// Pays the creation deposit. Required when `cv_config.proposal_deposit > 0`.
+    #[account(mut, token::mint = commons_token_mint)]
+    pub creator_token_account: Option<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = authority,
+        token::mint = commons_token_mint,
+        token::authority = cv_config,
+        seeds = [b"proposal_deposit", proposal.key().as_ref()],
+        bump
+    )]
+    pub deposit_escrow: Option<Account<'info, TokenAccount>>,
     #[account(mut)]
     pub authority: Signer<'info>,
     pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteProposalInstructions<'info> {
+    /// CHECK: PDA checked by seeds, deserialized in the handler. Unchecked so the
+    /// outer instruction never writes back over updates made by meta-proposal CPIs
+    #[account(mut, seeds = [b"cv_config"], bump)]
+    pub cv_config: UncheckedAccount<'info>,
+    pub proposal: Account<'info, Proposal>,
+    #[account(
+        mut,
+        seeds = [b"proposal_instructions", proposal.key().as_ref()],
+        bump = proposal_instructions.bump,
+        has_one = proposal
+    )]
+    pub proposal_instructions: Account<'info, ProposalInstructions>,
+}
+
+#[derive(Accounts)]
+pub struct SetAllowedPrograms<'info> {
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump, has_one = authority)]
+    pub cv_config: Account<'info, CVConfig>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateCvParams<'info> {
+    #[account(
+        mut,
+        seeds = [b"cv_config"],
+        bump = cv_config.cv_config_bump,
+        constraint = updater.key() == cv_config.authority
+            || updater.key() == cv_config.key() @ CustomError::NotCvGovernance
+    )]
+    pub cv_config: Account<'info, CVConfig>,
+    /// The CV authority, or the CV config PDA signing for a passed meta-proposal.
+    pub updater: Signer<'info>,
     pub clock: Sysvar<'info, Clock>,
 }
 
+#[derive(Accounts)]
+pub struct SetChallengeParams<'info> {
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump, has_one = authority)]
+    pub cv_config: Account<'info, CVConfig>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetGuardian<'info> {
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump, has_one = authority)]
+    pub cv_config: Account<'info, CVConfig>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GuardianAction<'info> {
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump, has_one = guardian @ CustomError::NotGuardian)]
+    pub cv_config: Account<'info, CVConfig>,
+    pub guardian: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetConvictionParams<'info> {
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump, has_one = authority)]
+    pub cv_config: Account<'info, CVConfig>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct VerifyPersonhood<'info> {
+    #[account(seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + 32 + 32 + 1,
+        seeds = [b"personhood", cv_config.key().as_ref(), authority.key().as_ref()],
+        bump
+    )]
+    pub personhood: Account<'info, PersonhoodRecord>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetLockSchedule<'info> {
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump, has_one = authority)]
+    pub cv_config: Account<'info, CVConfig>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetSpendingLimits<'info> {
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump, has_one = authority)]
+    pub cv_config: Account<'info, CVConfig>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterTreasury<'info> {
+    #[account(seeds = [b"cv_config"], bump = cv_config.cv_config_bump, has_one = authority)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + 32 + 4 + MAX_REGISTERED_TREASURIES * (32 + 32 + 8 + SPENDING_WINDOW_SPACE) + 1,
+        seeds = [b"treasury_registry", cv_config.key().as_ref()],
+        bump
+    )]
+    pub treasury_registry: Account<'info, TreasuryRegistry>,
+    #[account(token::authority = cv_config)]
+    pub treasury: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct StakeTokens<'info> {
-    #[account(init_if_needed, payer = authority, space = 8 + 32 + 32 + 8 + 8 + 32, seeds = [b"stake", authority.key().as_ref(), proposal.key().as_ref()], bump)]
+    #[account(init_if_needed, payer = authority, space = 8 + 32 + 32 + 8 + 8 + 32 + 8 + 8 + 8, seeds = [b"stake", authority.key().as_ref(), proposal.key().as_ref()], bump)]
     // Added 32 bytes for authority
     pub stake_account: Account<'info, Stake>,
     #[account(mut)]
     pub cv_config: Account<'info, CVConfig>,
     #[account(mut)]
     pub proposal: Account<'info, Proposal>,
+    /// `cv_config.commons_treasury`, or the registered treasury for the
+    /// proposal's request mint; read for the threshold in the emitted event,
+    /// which leaves it out if this or `abstain_proposal` is not the right one.
+    pub commons_treasury: Account<'info, TokenAccount>,
+    #[account(seeds = [b"treasury_registry", cv_config.key().as_ref()], bump = treasury_registry.bump)]
+    pub treasury_registry: Option<Account<'info, TreasuryRegistry>>,
+    /// `cv_config.abstain_proposal` once the config has one.
+    pub abstain_proposal: Option<Account<'info, Proposal>>,
+    /// CHECK: The staker's voter account PDA, read for their allocation to the
+    /// proposal; it may not exist
+    #[account(seeds = [b"voter", cv_config.key().as_ref(), authority.key().as_ref()], bump)]
+    pub voter_account: UncheckedAccount<'info>,
+    /// Required while `cv_config.personhood_root` is set.
+    #[account(seeds = [b"personhood", cv_config.key().as_ref(), authority.key().as_ref()], bump = personhood.bump)]
+    pub personhood: Option<Account<'info, PersonhoodRecord>>,
     pub commons_token_mint: Account<'info, Mint>,
     #[account(mut)]
     pub user_commons_token_account: Account<'info, TokenAccount>,
@@ -302,7 +1575,19 @@ pub struct UnstakeTokens<'info> {
     pub cv_config: Account<'info, CVConfig>,
     #[account(mut)]
     pub proposal: Account<'info, Proposal>,
-    #[account(mut, has_one = authority)]
+    /// `cv_config.commons_treasury`, or the registered treasury for the
+    /// proposal's request mint; read for the threshold in the emitted event,
+    /// which leaves it out if this or `abstain_proposal` is not the right one.
+    pub commons_treasury: Account<'info, TokenAccount>,
+    #[account(seeds = [b"treasury_registry", cv_config.key().as_ref()], bump = treasury_registry.bump)]
+    pub treasury_registry: Option<Account<'info, TreasuryRegistry>>,
+    /// `cv_config.abstain_proposal` once the config has one.
+    pub abstain_proposal: Option<Account<'info, Proposal>>,
+    /// CHECK: The staker's voter account PDA, read for their allocation to the
+    /// proposal; it may not exist
+    #[account(seeds = [b"voter", cv_config.key().as_ref(), authority.key().as_ref()], bump)]
+    pub voter_account: UncheckedAccount<'info>,
+    #[account(mut, has_one = authority, has_one = proposal)]
     pub stake_account: Account<'info, Stake>,
     #[account(mut)]
     pub user_commons_token_account: Account<'info, TokenAccount>,
@@ -325,196 +1610,1712 @@ pub struct UnstakeTokens<'info> {
 
 #[derive(Accounts)]
 pub struct CheckAndExecute<'info> {
-    #[account(mut, has_one = authority)]
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
     pub cv_config: Account<'info, CVConfig>,
     #[account(mut)]
     pub proposal: Account<'info, Proposal>,
+    /// `cv_config.commons_treasury`, or the registered treasury for the
+    /// proposal's request mint.
     #[account(mut)]
     pub commons_treasury: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub recipient_token_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub authority: Signer<'info>,
+    #[account(mut, seeds = [b"treasury_registry", cv_config.key().as_ref()], bump = treasury_registry.bump)]
+    pub treasury_registry: Option<Account<'info, TreasuryRegistry>>,
+    #[account(mut, address = proposal.beneficiary @ CustomError::BeneficiaryMismatch)]
+    pub beneficiary: Option<Account<'info, TokenAccount>>,
+    /// Required once the config has an abstain proposal.
+    #[account(address = cv_config.abstain_proposal @ CustomError::MissingAbstainProposal)]
+    pub abstain_proposal: Option<Account<'info, Proposal>>,
     pub token_program: Program<'info, Token>,
     pub clock: Sysvar<'info, Clock>,
 }
 
 #[derive(Accounts)]
-pub struct WithdrawStake<'info> {
-    #[account(mut, has_one = authority, close = authority)]
-    pub stake_account: Account<'info, Stake>,
-    #[account(mut)]
+pub struct FinalizeProposal<'info> {
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
     pub cv_config: Account<'info, CVConfig>,
     #[account(mut)]
     pub proposal: Account<'info, Proposal>,
+    /// `cv_config.commons_treasury`, or the registered treasury for the
+    /// proposal's request mint.
     #[account(mut)]
-    pub user_commons_token_account: Account<'info, TokenAccount>,
+    pub commons_treasury: Account<'info, TokenAccount>,
+    #[account(mut, seeds = [b"treasury_registry", cv_config.key().as_ref()], bump = treasury_registry.bump)]
+    pub treasury_registry: Option<Account<'info, TreasuryRegistry>>,
+    #[account(mut, address = proposal.beneficiary @ CustomError::BeneficiaryMismatch)]
+    pub beneficiary: Option<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct ChallengeProposal<'info> {
+    #[account(seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
+    pub cv_config: Account<'info, CVConfig>,
     #[account(mut)]
-    pub commons_token_mint: Account<'info, Mint>,
-    /// CHECK: This is the staking vault PDA
+    pub proposal: Account<'info, Proposal>,
     #[account(
-        mut,
-        seeds = [b"staking_vault", cv_config.key().as_ref()],
-        bump = cv_config.staking_vault_bump,
+        init,
+        payer = challenger,
+        space = 8 + 32 + 32 + 32 + 8 + 1 + 1,
+        seeds = [b"challenge", proposal.key().as_ref()],
+        bump
+    )]
+    pub challenge: Account<'info, Challenge>,
+    #[account(
+        init,
+        payer = challenger,
+        token::mint = commons_token_mint,
         token::authority = cv_config,
-        token::mint = commons_token_mint
+        seeds = [b"challenge_bond", proposal.key().as_ref()],
+        bump
     )]
-    pub staking_vault: Account<'info, TokenAccount>, // Global staking vault
+    pub bond_escrow: Account<'info, TokenAccount>,
+    #[account(address = cv_config.commons_token_mint)]
+    pub commons_token_mint: Account<'info, Mint>,
+    #[account(mut, token::mint = commons_token_mint)]
+    pub challenger_token_account: Account<'info, TokenAccount>,
     #[account(mut)]
-    pub authority: Signer<'info>,
+    pub challenger: Signer<'info>,
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>,
     pub clock: Sysvar<'info, Clock>,
 }
 
-#[account]
-pub struct CVConfig {
-    pub decay_rate: u64,
-    pub max_ratio: u64,
-    pub weight_exponent: u64,
-    pub min_threshold: u64,
-    pub commons_treasury: Pubkey,
-    pub commons_token_mint: Pubkey,
-    pub cv_config_bump: u8,
-    pub staking_vault: Pubkey,
-    pub staking_vault_bump: u8,
+#[derive(Accounts)]
+pub struct ResolveChallenge<'info> {
+    #[account(seeds = [b"cv_config"], bump = cv_config.cv_config_bump, has_one = arbiter @ CustomError::ArbiterMismatch)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(mut)]
+    pub proposal: Account<'info, Proposal>,
+    #[account(
+        mut,
+        seeds = [b"challenge", proposal.key().as_ref()],
+        bump = challenge.bump,
+        has_one = proposal,
+        has_one = challenger
+    )]
+    pub challenge: Account<'info, Challenge>,
+    #[account(mut, seeds = [b"challenge_bond", proposal.key().as_ref()], bump)]
+    pub bond_escrow: Account<'info, TokenAccount>,
+    #[account(mut, address = cv_config.commons_treasury)]
+    pub commons_treasury: Account<'info, TokenAccount>,
+    #[account(mut, address = challenge.refund_account)]
+    pub challenger_token_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub challenger: SystemAccount<'info>,
+    pub arbiter: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct OpenFundingStream<'info> {
+    pub proposal: Account<'info, Proposal>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + 32 + 32 + 8 + 8 + 8 + 8 + 1 + 1,
+        seeds = [b"funding_stream", proposal.key().as_ref()],
+        bump
+    )]
+    pub funding_stream: Account<'info, FundingStream>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimFundingStream<'info> {
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(
+        mut,
+        seeds = [b"funding_stream", funding_stream.proposal.as_ref()],
+        bump = funding_stream.bump,
+        has_one = beneficiary
+    )]
+    pub funding_stream: Account<'info, FundingStream>,
+    #[account(mut, address = cv_config.commons_treasury)]
+    pub commons_treasury: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub beneficiary: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct CancelFundingStream<'info> {
+    #[account(mut, signer, seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(
+        mut,
+        seeds = [b"funding_stream", funding_stream.proposal.as_ref()],
+        bump = funding_stream.bump
+    )]
+    pub funding_stream: Account<'info, FundingStream>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawStake<'info> {
+    #[account(mut, has_one = authority, has_one = proposal, close = authority)]
+    pub stake_account: Account<'info, Stake>,
+    #[account(mut)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(mut)]
+    pub proposal: Account<'info, Proposal>,
+    /// `cv_config.commons_treasury`, or the registered treasury for the
+    /// proposal's request mint; read for the threshold in the emitted event,
+    /// which leaves it out if this or `abstain_proposal` is not the right one.
+    pub commons_treasury: Account<'info, TokenAccount>,
+    #[account(seeds = [b"treasury_registry", cv_config.key().as_ref()], bump = treasury_registry.bump)]
+    pub treasury_registry: Option<Account<'info, TreasuryRegistry>>,
+    /// `cv_config.abstain_proposal` once the config has one.
+    pub abstain_proposal: Option<Account<'info, Proposal>>,
+    /// CHECK: The staker's voter account PDA, read for their allocation to the
+    /// proposal; it may not exist
+    #[account(seeds = [b"voter", cv_config.key().as_ref(), authority.key().as_ref()], bump)]
+    pub voter_account: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub user_commons_token_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub commons_token_mint: Account<'info, Mint>,
+    /// CHECK: This is the staking vault PDA
+    #[account(
+        mut,
+        seeds = [b"staking_vault", cv_config.key().as_ref()],
+        bump = cv_config.staking_vault_bump,
+        token::authority = cv_config,
+        token::mint = commons_token_mint
+    )]
+    pub staking_vault: Account<'info, TokenAccount>, // Global staking vault
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct SettleProposalDeposit<'info> {
+    #[account(seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(mut, has_one = creator)]
+    pub proposal: Account<'info, Proposal>,
+    #[account(mut, seeds = [b"proposal_deposit", proposal.key().as_ref()], bump)]
+    pub deposit_escrow: Account<'info, TokenAccount>,
+    #[account(mut, address = cv_config.commons_treasury)]
+    pub commons_treasury: Account<'info, TokenAccount>,
+    #[account(mut, address = proposal.deposit_refund_account)]
+    pub deposit_refund_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub creator: SystemAccount<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CloseProposal<'info> {
+    #[account(seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(mut, has_one = creator, close = creator)]
+    pub proposal: Account<'info, Proposal>,
+    /// CHECK: Receives the rent; bound to `proposal.creator`
+    #[account(mut)]
+    pub creator: UncheckedAccount<'info>,
+    /// CHECK: The proposal's instruction set PDA, closed with it if it exists
+    #[account(mut, seeds = [b"proposal_instructions", proposal.key().as_ref()], bump)]
+    pub proposal_instructions: UncheckedAccount<'info>,
+    /// CHECK: The proposal's funding stream PDA, only checked for existence
+    #[account(seeds = [b"funding_stream", proposal.key().as_ref()], bump)]
+    pub funding_stream: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseEmptyStakes {}
+
+#[derive(Accounts)]
+pub struct CancelProposal<'info> {
+    #[account(seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(mut, has_one = creator)]
+    pub proposal: Account<'info, Proposal>,
+    pub creator: Signer<'info>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct ExpireProposal<'info> {
+    #[account(mut)]
+    pub proposal: Account<'info, Proposal>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct RejectProposal<'info> {
+    #[account(seeds = [b"cv_config"], bump = cv_config.cv_config_bump, has_one = authority)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(mut)]
+    pub proposal: Account<'info, Proposal>,
+    pub authority: Signer<'info>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct DepositTokens<'info> {
+    #[account(seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + 32 + 8 + 4 + MAX_VOTER_ALLOCATIONS * (32 + 8) + 1 + 32,
+        seeds = [b"voter", cv_config.key().as_ref(), authority.key().as_ref()],
+        bump
+    )]
+    pub voter_account: Account<'info, VoterAccount>,
+    /// Required while `cv_config.personhood_root` is set.
+    #[account(seeds = [b"personhood", cv_config.key().as_ref(), authority.key().as_ref()], bump = personhood.bump)]
+    pub personhood: Option<Account<'info, PersonhoodRecord>>,
+    pub commons_token_mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub user_commons_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"staking_vault", cv_config.key().as_ref()],
+        bump = cv_config.staking_vault_bump,
+        token::authority = cv_config,
+        token::mint = commons_token_mint
+    )]
+    pub staking_vault: Account<'info, TokenAccount>, // Global staking vault
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTokens<'info> {
+    #[account(seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [b"voter", cv_config.key().as_ref(), authority.key().as_ref()],
+        bump = voter_account.voter_bump
+    )]
+    pub voter_account: Account<'info, VoterAccount>,
+    pub commons_token_mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub user_commons_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"staking_vault", cv_config.key().as_ref()],
+        bump = cv_config.staking_vault_bump,
+        token::authority = cv_config,
+        token::mint = commons_token_mint
+    )]
+    pub staking_vault: Account<'info, TokenAccount>, // Global staking vault
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ReallocateSupport<'info> {
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(
+        mut,
+        seeds = [b"voter", cv_config.key().as_ref(), voter_account.authority.as_ref()],
+        bump = voter_account.voter_bump,
+        constraint = voter_account.allocator() == authority.key() @ CustomError::NotVoterAllocator
+    )]
+    pub voter_account: Account<'info, VoterAccount>,
+    /// The owner, or their delegate while one is set.
+    pub authority: Signer<'info>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateDelegation<'info> {
+    #[account(mut, seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
+    pub cv_config: Account<'info, CVConfig>,
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [b"voter", cv_config.key().as_ref(), authority.key().as_ref()],
+        bump = voter_account.voter_bump
+    )]
+    pub voter_account: Account<'info, VoterAccount>,
+    pub authority: Signer<'info>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct ViewProposal<'info> {
+    #[account(seeds = [b"cv_config"], bump = cv_config.cv_config_bump)]
+    pub cv_config: Account<'info, CVConfig>,
+    pub proposal: Account<'info, Proposal>,
+    /// `cv_config.commons_treasury`, or the registered treasury for the
+    /// proposal's request mint.
+    pub commons_treasury: Account<'info, TokenAccount>,
+    #[account(seeds = [b"treasury_registry", cv_config.key().as_ref()], bump = treasury_registry.bump)]
+    pub treasury_registry: Option<Account<'info, TreasuryRegistry>>,
+    /// Required once the config has an abstain proposal.
+    #[account(address = cv_config.abstain_proposal @ CustomError::MissingAbstainProposal)]
+    pub abstain_proposal: Option<Account<'info, Proposal>>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+impl ViewProposal<'_> {
+    fn present_conviction(&self) -> u64 {
+        let proposal = &self.proposal;
+        decay_since(
+            proposal.current_conviction,
+            proposal.last_update,
+            current_time(&self.clock, &self.cv_config),
+            &self.cv_config,
+        )
+        .min(u64::MAX as u128) as u64
+    }
+
+    fn required_conviction(&self) -> Result<u64> {
+        check_payout_treasury(
+            &self.proposal,
+            &self.cv_config,
+            self.treasury_registry.as_deref(),
+            &self.commons_treasury,
+        )?;
+        required_conviction(
+            &self.proposal,
+            &self.cv_config,
+            self.commons_treasury.amount,
+            self.abstain_proposal.as_deref(),
+            current_time(&self.clock, &self.cv_config),
+        )
+    }
+}
+
+#[account]
+pub struct CVConfig {
+    pub decay_rate: u64, // Retained conviction per time_base unit, scaled by CV_SCALE
+    pub max_ratio: u64,
+    pub weight_exponent: u64,
+    pub min_threshold: u64,
+    pub commons_treasury: Pubkey,
+    pub commons_token_mint: Pubkey,
+    pub cv_config_bump: u8,
+    pub staking_vault: Pubkey,
+    pub staking_vault_bump: u8,
     pub authority: Pubkey,
     pub total_staked: u64,
+    pub proposal_count: u64,
+    pub proposal_expiry_slots: u64,               // 0 disables expiry
+    pub signaling_threshold: u64, // Fraction of effective supply, scaled by CV_SCALE
+    pub abstain_proposal: Pubkey, // Pubkey::default() until created
+    pub allowed_programs: Vec<Pubkey>, // Max MAX_ALLOWED_PROGRAMS CPI targets
+    pub stream_duration_slots: u64, // 0 pays approved grants as a lump sum
+    pub committed_stream_amount: u64, // Treasury balance reserved for open streams
+    pub challenge_period_slots: u64, // 0 pays out passed proposals immediately
+    pub challenge_bond: u64,      // Commons tokens a challenger must escrow
+    pub arbiter: Pubkey,          // Signer that resolves challenges
+    pub proposal_deposit: u64,    // Commons tokens escrowed per proposal, 0 disables
+    pub time_base: TimeBase,      // Unit of decay_rate and every last_update
+    pub previous_decay_rate: u64, // Rate in force before decay_rate_updated_at
+    pub decay_rate_updated_at: u64, // In time_base units, 0 if never updated
+    pub lock_schedule: Vec<LockTier>, // Max MAX_LOCK_TIERS, ascending by duration
+    pub spending_window_slots: u64, // 0 disables the spending caps
+    pub spending_cap: u64,        // Max commons tokens paid out per spending window
+    pub spending: SpendingWindow, // Commons tokens paid out recently
+    pub funding_categories: Vec<FundingCategory>, // Max MAX_FUNDING_CATEGORIES
+    pub guardian: Pubkey,         // Can pause the config, Pubkey::default() if unset
+    pub paused: bool,
+    pub conviction_mode: ConvictionMode,
+    pub personhood_root: [u8; 32], // Merkle root of verified stakers, all zeroes disables
 }
 
-#[account]
-pub struct Proposal {
-    pub creator: Pubkey,
-    pub requested_amount: u64,
-    pub metadata_hash: String, // Max 32 bytes for hash
-    pub status: ProposalStatus,
-    pub current_conviction: u64,
-    pub last_update_slot: u64,
+impl CVConfig {
+    pub fn funding_category(&self, name: &str) -> Option<&FundingCategory> {
+        self.funding_categories
+            .iter()
+            .find(|category| category.name == name)
+    }
+}
+
+#[account]
+pub struct Proposal {
+    pub id: u64,
+    pub creator: Pubkey,
+    pub beneficiary: Pubkey,
+    pub requested_amount: u64,
+    pub metadata_uri: String, // Max MAX_METADATA_URI_LEN bytes
+    pub status: ProposalStatus,
+    pub current_conviction: u64,
+    pub last_update: u64,     // In cv_config.time_base units
+    pub decided_at_slot: u64, // Slot of the last status change, 0 while pending
+    pub expires_at_slot: u64, // 0 means the proposal never expires
+    pub stream_duration_slots: u64,
+    pub challenge_ends_at_slot: u64, // Set once the proposal passes with a dispute window
+    pub deposit_amount: u64,         // Creation deposit still held in escrow
+    pub deposit_refund_account: Pubkey,
+    pub category: String,     // Funding category name, empty if uncategorised
+    pub request_mint: Pubkey, // Mint of the requested funds, the commons mint if none
+    pub stake_count: u64,     // Stake accounts and voter allocations holding support
+}
+
+#[account]
+pub struct PersonhoodRecord {
+    pub user: Pubkey,
+    pub root: [u8; 32], // Personhood root the user was verified against
+    pub bump: u8,
+}
+
+#[account]
+pub struct TreasuryRegistry {
+    pub cv_config: Pubkey,
+    pub treasuries: Vec<RegisteredTreasury>, // Max MAX_REGISTERED_TREASURIES, one per mint
+    pub bump: u8,
+}
+
+impl TreasuryRegistry {
+    pub fn treasury_for(&self, mint: &Pubkey) -> Option<Pubkey> {
+        self.treasuries
+            .iter()
+            .find(|entry| entry.mint == *mint)
+            .map(|entry| entry.treasury)
+    }
+}
+
+#[account]
+pub struct Challenge {
+    pub proposal: Pubkey,
+    pub challenger: Pubkey,
+    pub refund_account: Pubkey, // Challenger token account that posted the bond
+    pub bond: u64,
+    pub resolved: bool,
+    pub bump: u8,
+}
+
+#[account]
+pub struct FundingStream {
+    pub proposal: Pubkey,
+    pub beneficiary: Pubkey,
+    pub total_amount: u64,
+    pub claimed_amount: u64,
+    pub start_slot: u64,
+    pub end_slot: u64,
+    pub cancelled: bool,
+    pub bump: u8,
+}
+
+#[account]
+pub struct Stake {
+    pub user: Pubkey,
+    pub proposal: Pubkey,
+    pub staked_amount: u64,
+    pub last_update: u64,     // In cv_config.time_base units
+    pub authority: Pubkey,    // Add this field
+    pub locked_until: u64,    // In cv_config.time_base units, 0 if never locked
+    pub multiplier: u64,      // Lock weight scaled by CV_SCALE
+    pub weighted_amount: u64, // This stake's share of the voter's weight on the proposal
+}
+
+#[account]
+pub struct VoterAccount {
+    pub authority: Pubkey,
+    pub deposited_amount: u64,
+    pub allocations: Vec<SupportAllocation>, // Max MAX_VOTER_ALLOCATIONS entries
+    pub voter_bump: u8,
+    pub delegate: Pubkey, // Pubkey::default() while the owner allocates directly
+}
+
+impl VoterAccount {
+    /// Key allowed to reallocate this account's support.
+    pub fn allocator(&self) -> Pubkey {
+        if self.delegate == Pubkey::default() {
+            self.authority
+        } else {
+            self.delegate
+        }
+    }
+
+    pub fn allocation_for(&self, proposal: &Pubkey) -> u64 {
+        self.allocations
+            .iter()
+            .find(|allocation| allocation.proposal == *proposal)
+            .map_or(0, |allocation| allocation.amount)
+    }
+
+    pub fn allocated_amount(&self) -> Result<u64> {
+        self.allocations.iter().try_fold(0u64, |total, allocation| {
+            total
+                .checked_add(allocation.amount)
+                .ok_or_else(|| error!(CustomError::StakeOverflow))
+        })
+    }
+
+    pub fn unallocated_amount(&self) -> u64 {
+        let allocated = self.allocations.iter().fold(0u64, |total, allocation| {
+            total.saturating_add(allocation.amount)
+        });
+        self.deposited_amount.saturating_sub(allocated)
+    }
+
+    fn set_allocation(&mut self, proposal: Pubkey, amount: u64) -> Result<()> {
+        match self
+            .allocations
+            .iter()
+            .position(|allocation| allocation.proposal == proposal)
+        {
+            Some(index) if amount == 0 => {
+                self.allocations.swap_remove(index);
+            }
+            Some(index) => self.allocations[index].amount = amount,
+            None if amount == 0 => {}
+            None => {
+                require!(
+                    self.allocations.len() < MAX_VOTER_ALLOCATIONS,
+                    CustomError::TooManyAllocations
+                );
+                self.allocations
+                    .push(SupportAllocation { proposal, amount });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[account]
+pub struct ProposalInstructions {
+    pub proposal: Pubkey,
+    pub instructions: Vec<ProposalInstruction>,
+    pub executed: bool,
+    pub bump: u8,
+}
+
+impl ProposalInstructions {
+    pub fn space(instructions: &[ProposalInstruction]) -> usize {
+        let payload: usize = instructions
+            .iter()
+            .map(|instruction| {
+                32 + 4 + instruction.accounts.len() * (32 + 1 + 1) + 4 + instruction.data.len()
+            })
+            .sum();
+        8 + 32 + 4 + payload + 1 + 1
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct ProposalInstruction {
+    pub program_id: Pubkey,
+    pub accounts: Vec<ProposalAccountMeta>,
+    pub data: Vec<u8>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct ProposalAccountMeta {
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct SupportAllocation {
+    pub proposal: Pubkey,
+    pub amount: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct RegisteredTreasury {
+    pub mint: Pubkey,
+    pub treasury: Pubkey,  // Token account owned by the CV config PDA
+    pub spending_cap: u64, // Max paid out per spending window, in this mint
+    pub spending: SpendingWindow,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct FundingCategory {
+    pub name: String, // Max MAX_FUNDING_CATEGORY_NAME_LEN bytes
+    pub cap: u64,     // Max commons tokens paid out per spending window, or in total without one
+    pub spending: SpendingWindow,
+}
+
+/// Payouts over a rolling window, kept as a ring of buckets that each cover a
+/// fixed share of the window. A bucket counts in full for as long as the last
+/// slot it was charged at is inside the window, so the trailing spend is never
+/// underestimated; it overestimates by at most the oldest bucket's spend from
+/// before the window.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, PartialEq, Eq, Debug)]
+pub struct SpendingWindow {
+    pub buckets: [SpendingBucket; SPENDING_BUCKETS], // Oldest first
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct SpendingBucket {
+    pub last_slot: u64, // Latest slot this bucket was charged at
+    pub spent: u64,
+}
+
+impl SpendingBucket {
+    /// Whether any of this bucket's spend falls in the `window_slots` up to
+    /// `slot`. Without a window spend never ages out.
+    fn in_window(&self, slot: u64, window_slots: u64) -> bool {
+        window_slots == 0 || self.last_slot.saturating_add(window_slots) > slot
+    }
+}
+
+impl SpendingWindow {
+    /// Spend over the `window_slots` up to `slot`, or in total without a window.
+    fn trailing_spend(&self, slot: u64, window_slots: u64) -> u128 {
+        self.buckets
+            .iter()
+            .filter(|bucket| bucket.in_window(slot, window_slots))
+            .map(|bucket| bucket.spent as u128)
+            .sum()
+    }
+
+    /// Checks `amount` more at `slot` fits under `cap`.
+    fn check(&self, slot: u64, window_slots: u64, amount: u64, cap: u64) -> bool {
+        self.trailing_spend(slot, window_slots) + amount as u128 <= cap as u128
+    }
+
+    fn charge(&mut self, slot: u64, window_slots: u64, amount: u64) -> Result<()> {
+        // Any window spans at most SPENDING_BUCKETS buckets of this length, so
+        // the bucket making way has normally aged out already
+        let bucket_slots = if window_slots == 0 {
+            u64::MAX
+        } else {
+            window_slots.div_ceil(SPENDING_BUCKETS as u64 - 1)
+        };
+        let newest = SPENDING_BUCKETS - 1;
+        if slot / bucket_slots != self.buckets[newest].last_slot / bucket_slots {
+            // After a window change the oldest bucket may still count, so its
+            // spend carries over rather than being forgotten
+            let oldest = self.buckets[0];
+            if oldest.in_window(slot, window_slots) {
+                let next = &mut self.buckets[1];
+                next.spent = next
+                    .spent
+                    .checked_add(oldest.spent)
+                    .ok_or(CustomError::SpendingBudgetExhausted)?;
+                next.last_slot = next.last_slot.max(oldest.last_slot);
+            }
+            self.buckets.rotate_left(1);
+            self.buckets[newest] = SpendingBucket::default();
+        }
+        let bucket = &mut self.buckets[newest];
+        bucket.spent = bucket
+            .spent
+            .checked_add(amount)
+            .ok_or(CustomError::SpendingBudgetExhausted)?;
+        bucket.last_slot = slot;
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct LockTier {
+    pub duration: u64,   // Minimum lock in time_base units
+    pub multiplier: u64, // Scaled by CV_SCALE, at least 1
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConvictionMode {
+    Linear,
+    Quadratic, // Each position counts with the square root of its weight
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimeBase {
+    Slots,
+    Seconds,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub enum ProposalStatus {
+    Pending,
+    Approved,
+    Rejected,
+    Cancelled,
+    Expired,
+    Challengeable,
+    Disputed,
+}
+
+#[event]
+pub struct ProposalCreated {
+    pub proposal: Pubkey,
+    pub id: u64,
+    pub creator: Pubkey,
+    pub beneficiary: Pubkey,
+    pub request_mint: Pubkey,
+    pub requested_amount: u64,
+    pub metadata_uri: String,
+}
+
+#[event]
+pub struct Staked {
+    pub user: Pubkey,
+    pub proposal: Pubkey,
+    pub amount: u64,
+    pub weighted_amount: u64, // Weight of the whole position after the stake
+    pub locked_until: u64,
+    pub conviction_before: u64, // Decayed to the stake time
+    pub conviction_after: u64,
+    pub threshold: Option<u64>, // Conviction needed to pass after the stake, if the accounts gave it
+}
+
+#[event]
+pub struct Unstaked {
+    pub user: Pubkey,
+    pub proposal: Pubkey,
+    pub amount: u64,
+    pub weighted_amount: u64, // Weight removed from the proposal
+    pub conviction_before: u64,
+    pub conviction_after: u64,
+    pub threshold: Option<u64>,
+}
+
+/// Conviction moved by a voter reallocation, a parameter-change checkpoint or
+/// a threshold check.
+#[event]
+pub struct ConvictionUpdated {
+    pub proposal: Pubkey,
+    pub conviction_before: u64,
+    pub conviction_after: u64,
+}
+
+/// A proposal crossed its threshold; `status` is `Challengeable` when a dispute
+/// window delays the payout.
+#[event]
+pub struct ProposalExecuted {
+    pub proposal: Pubkey,
+    pub beneficiary: Pubkey,
+    pub request_mint: Pubkey,
+    pub requested_amount: u64,
+    pub conviction: u64,
+    pub threshold: u64,
+    pub status: ProposalStatus,
+}
+
+#[event]
+pub struct StakeWithdrawn {
+    pub user: Pubkey,
+    pub proposal: Pubkey,
+    pub amount: u64,
+    pub status: ProposalStatus, // Status of the proposal the stake left
+    pub conviction_before: u64,
+    pub conviction_after: u64,
+    pub threshold: Option<u64>,
+}
+
+#[event]
+pub struct ProposalClosed {
+    pub proposal: Pubkey,
+    pub id: u64,
+    pub creator: Pubkey,
+    pub beneficiary: Pubkey,
+    pub request_mint: Pubkey,
+    pub requested_amount: u64,
+    pub metadata_uri: String,
+    pub category: String,
+    pub status: ProposalStatus,
+    pub conviction: u64, // Conviction when the proposal was last updated
+    pub decided_at_slot: u64,
+}
+
+#[error_code]
+pub enum CustomError {
+    #[msg("Insufficient staked amount")]
+    InsufficientStakedAmount,
+    #[msg("Stake amount must be greater than zero")]
+    InvalidStakeAmount,
+    #[msg("Stake total overflow")]
+    StakeOverflow,
+    #[msg("Stake total underflow")]
+    StakeUnderflow,
+    #[msg("Proposal must remain pending to stake or unstake")]
+    ProposalNotPending,
+    #[msg("Proposal still pending")]
+    ProposalStillPending,
+    #[msg("Requested amount exceeds configured spending limit")]
+    SpendingLimitExceeded,
+    #[msg("Conviction threshold not reached")]
+    ThresholdNotReached,
+    #[msg("Decay rate must be <= 1")]
+    InvalidDecayRate,
+    #[msg("Max ratio must be <= 1")]
+    InvalidMaxRatio,
+    #[msg("Min threshold must be <= 1")]
+    InvalidMinThreshold,
+    #[msg("Treasury has no funds")]
+    EmptyTreasury,
+    #[msg("Recipient does not match the proposal beneficiary")]
+    BeneficiaryMismatch,
+    #[msg("Metadata URI exceeds the maximum length")]
+    MetadataUriTooLong,
+    #[msg("Proposal counter overflow")]
+    ProposalCountOverflow,
+    #[msg("Deposit does not cover the requested allocation or withdrawal")]
+    InsufficientUnallocatedDeposit,
+    #[msg("Voter already supports the maximum number of proposals")]
+    TooManyAllocations,
+    #[msg("Allocations do not match the supplied proposal accounts")]
+    AllocationAccountsMismatch,
+    #[msg("Proposal has passed its expiry slot")]
+    ProposalExpired,
+    #[msg("Proposal has not reached its expiry slot")]
+    ProposalNotExpired,
+    #[msg("Signaling threshold must be <= 1")]
+    InvalidSignalingThreshold,
+    #[msg("Funding proposals require a beneficiary")]
+    MissingBeneficiary,
+    #[msg("Abstain proposal already exists")]
+    AbstainProposalExists,
+    #[msg("Abstain proposal cannot be executed")]
+    AbstainProposalNotExecutable,
+    #[msg("Config abstain proposal must be supplied")]
+    MissingAbstainProposal,
+    #[msg("Too many allowed programs")]
+    TooManyAllowedPrograms,
+    #[msg("Target program is not on the CV allowlist")]
+    ProgramNotAllowed,
+    #[msg("Proposal instructions are empty, too many or touch the staking vault")]
+    InvalidProposalInstructions,
+    #[msg("Proposal has not been approved")]
+    ProposalNotApproved,
+    #[msg("Proposal instructions have already been executed")]
+    InstructionsAlreadyExecuted,
+    #[msg("An account referenced by the proposal instructions was not supplied")]
+    MissingInstructionAccount,
+    #[msg("Proposal does not pay out as a stream")]
+    ProposalNotStreamed,
+    #[msg("Funding stream accounting overflow")]
+    StreamOverflow,
+    #[msg("Nothing has vested since the last claim")]
+    NothingToClaim,
+    #[msg("Funding stream has already been cancelled")]
+    StreamCancelled,
+    #[msg("An arbiter is required while challenges are enabled")]
+    MissingArbiter,
+    #[msg("Signer is not the configured arbiter")]
+    ArbiterMismatch,
+    #[msg("Proposal is not in its challenge window")]
+    ProposalNotChallengeable,
+    #[msg("Challenge window has closed")]
+    ChallengeWindowClosed,
+    #[msg("Challenge window is still open")]
+    ChallengeWindowOpen,
+    #[msg("Proposal is not under dispute")]
+    ProposalNotDisputed,
+    #[msg("Creator token account and deposit escrow are required")]
+    MissingProposalDeposit,
+    #[msg("Proposal deposit is locked until the proposal is settled")]
+    ProposalDepositLocked,
+    #[msg("Signer may not allocate this voter's support")]
+    NotVoterAllocator,
+    #[msg("Delegate must be another, non-default key")]
+    InvalidDelegate,
+    #[msg("Voter has not delegated")]
+    NotDelegated,
+    #[msg("Only the CV authority or a passed meta-proposal can update parameters")]
+    NotCvGovernance,
+    #[msg("Lock schedule must be ascending, at most MAX_LOCK_TIERS long, with multipliers >= 1")]
+    InvalidLockSchedule,
+    #[msg("Stake is still locked")]
+    StakeLocked,
+    #[msg("A new lock cannot end before the existing one")]
+    LockShortened,
+    #[msg("Locked stake positions only grow through stake_tokens_locked")]
+    LockedStakePosition,
+    #[msg("Spending budget for the current window is exhausted")]
+    SpendingBudgetExhausted,
+    #[msg("Funding category budget is exhausted")]
+    FundingCategoryCapExceeded,
+    #[msg("Unknown funding category")]
+    UnknownFundingCategory,
+    #[msg("Too many funding categories")]
+    TooManyFundingCategories,
+    #[msg("Invalid funding category name")]
+    InvalidFundingCategoryName,
+    #[msg("Treasury does not pay this proposal's request mint")]
+    TreasuryMismatch,
+    #[msg("No treasury is registered for this proposal's request mint")]
+    UnregisteredTreasury,
+    #[msg("Commons token requests are paid from commons_treasury")]
+    CommonsMintTreasury,
+    #[msg("Too many registered treasuries")]
+    TooManyTreasuries,
+    #[msg("CV config is paused")]
+    ConfigPaused,
+    #[msg("CV config is not paused")]
+    ConfigNotPaused,
+    #[msg("Signer is not the CV guardian")]
+    NotGuardian,
+    #[msg("Conviction mode can only change while nothing is staked")]
+    ConvictionModeLocked,
+    #[msg("No personhood root is configured")]
+    PersonhoodNotRequired,
+    #[msg("Invalid personhood proof")]
+    InvalidPersonhoodProof,
+    #[msg("Staker has not proven personhood against the current root")]
+    NotVerifiedPerson,
+    #[msg("Proposal is not finished")]
+    ProposalNotFinished,
+    #[msg("Proposal still has stakes or support allocations")]
+    ProposalHasStakes,
+    #[msg("Proposal deposit, stream or instructions are not settled")]
+    ProposalNotSettled,
+    #[msg("Stake account still holds tokens")]
+    StakeNotEmpty,
+    #[msg("Stake accounts must be passed as (stake, owner) pairs")]
+    StakeAccountsMismatch,
+    #[msg("Proposal instructions cannot touch token accounts the CV config controls")]
+    ProtectedTokenAccount,
+    #[msg("Abstain proposal cannot be cancelled, rejected or closed")]
+    AbstainProposalPermanent,
+}
+
+/// Whether `info` is a token account the CV config PDA owns or is delegated on:
+/// the treasury, registered treasuries, the staking vault and every escrow.
+/// Those only move through the funding threshold, never through a payload.
+fn is_cv_controlled_token_account(info: &AccountInfo, cv_config: &Pubkey) -> bool {
+    if info.owner != &token::ID {
+        return false;
+    }
+    match TokenAccount::try_deserialize(&mut &info.data.borrow()[..]) {
+        Ok(account) => account.owner == *cv_config || account.delegate == Some(*cv_config).into(),
+        Err(_) => false,
+    }
+}
+
+/// Current time in the config's time base. Timestamps before the epoch clamp to 0.
+fn current_time(clock: &Clock, config: &CVConfig) -> u64 {
+    match config.time_base {
+        TimeBase::Slots => clock.slot,
+        TimeBase::Seconds => clock.unix_timestamp.max(0) as u64,
+    }
+}
+
+fn validate_cv_params(decay_rate: u64, max_ratio: u64, min_threshold: u64) -> Result<()> {
+    require!(decay_rate <= CV_SCALE_U64, CustomError::InvalidDecayRate);
+    require!(max_ratio <= CV_SCALE_U64, CustomError::InvalidMaxRatio);
+    require!(
+        min_threshold <= CV_SCALE_U64,
+        CustomError::InvalidMinThreshold
+    );
+    Ok(())
+}
+
+fn validate_lock_schedule(schedule: &[LockTier]) -> Result<()> {
+    require!(
+        schedule.len() <= MAX_LOCK_TIERS,
+        CustomError::InvalidLockSchedule
+    );
+    let mut previous = LockTier {
+        duration: 0,
+        multiplier: CV_SCALE_U64,
+    };
+    for tier in schedule.iter() {
+        require!(
+            tier.duration > previous.duration && tier.multiplier >= previous.multiplier,
+            CustomError::InvalidLockSchedule
+        );
+        previous = tier.clone();
+    }
+    Ok(())
+}
+
+fn add_stake(accounts: &mut StakeTokens, amount: u64, lock_duration: Option<u64>) -> Result<()> {
+    require!(amount > 0, CustomError::InvalidStakeAmount);
+    let allocated = voter_allocation(&accounts.voter_account, &accounts.proposal.key())?;
+    let stake_account = &mut accounts.stake_account;
+    let proposal = &mut accounts.proposal;
+    let cv_config = &mut accounts.cv_config;
+    require!(!cv_config.paused, CustomError::ConfigPaused);
+    check_personhood(cv_config, accounts.personhood.as_deref())?;
+    let slot = accounts.clock.slot;
+    let now = current_time(&accounts.clock, cv_config);
+    require!(
+        proposal.status == ProposalStatus::Pending,
+        CustomError::ProposalNotPending
+    );
+    require!(
+        !proposal_expired(proposal, slot),
+        CustomError::ProposalExpired
+    );
+
+    // Fresh positions start unweighted
+    if stake_account.multiplier == 0 {
+        stake_account.multiplier = CV_SCALE_U64;
+    }
+    let multiplier = match lock_duration {
+        None => {
+            require!(
+                stake_account.multiplier == CV_SCALE_U64,
+                CustomError::LockedStakePosition
+            );
+            CV_SCALE_U64
+        }
+        Some(lock_duration) => {
+            let locked_until = now.saturating_add(lock_duration);
+            require!(
+                lock_duration > 0 && locked_until >= stake_account.locked_until,
+                CustomError::LockShortened
+            );
+            stake_account.locked_until = locked_until;
+            lock_multiplier(&cv_config.lock_schedule, lock_duration)
+        }
+    };
+
+    // Transfer Commons tokens from user to staking vault
+    let cpi_accounts = Transfer {
+        from: accounts.user_commons_token_account.to_account_info(),
+        to: accounts.staking_vault.to_account_info(),
+        authority: accounts.authority.to_account_info(),
+    };
+    let cpi_program = accounts.token_program.to_account_info();
+    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+    token::transfer(cpi_ctx, amount)?;
+
+    // Update stake account; a lock reweights the whole position
+    let mode = cv_config.conviction_mode;
+    let weight_before = voter_weight(
+        stake_account.staked_amount,
+        stake_account.multiplier,
+        allocated,
+        mode,
+    )?;
+    if stake_account.staked_amount == 0 {
+        proposal.stake_count = proposal
+            .stake_count
+            .checked_add(1)
+            .ok_or(CustomError::StakeOverflow)?;
+    }
+    stake_account.user = accounts.authority.key();
+    stake_account.proposal = proposal.key();
+    stake_account.staked_amount = stake_account
+        .staked_amount
+        .checked_add(amount)
+        .ok_or(CustomError::StakeOverflow)?;
+    stake_account.last_update = now;
+    stake_account.authority = accounts.authority.key();
+    let weight_after = voter_weight(stake_account.staked_amount, multiplier, allocated, mode)?;
+    let delta = weight_after as i128 - weight_before as i128;
+    let weight = weight_after - voter_weight(0, multiplier, allocated, mode)?;
+    stake_account.multiplier = multiplier;
+    stake_account.weighted_amount = weight;
+
+    cv_config.total_staked = if delta >= 0 {
+        cv_config
+            .total_staked
+            .checked_add(delta as u64)
+            .ok_or(CustomError::StakeOverflow)?
+    } else {
+        cv_config
+            .total_staked
+            .checked_sub((-delta) as u64)
+            .ok_or(CustomError::StakeUnderflow)?
+    };
+
+    let conviction_before = update_conviction_for_proposal(proposal, delta, cv_config, now)?;
+    emit!(Staked {
+        user: stake_account.user,
+        proposal: proposal.key(),
+        amount,
+        weighted_amount: weight,
+        locked_until: stake_account.locked_until,
+        conviction_before,
+        conviction_after: proposal.current_conviction,
+        threshold: stake_event_threshold(
+            proposal,
+            cv_config,
+            &accounts.commons_treasury,
+            accounts.treasury_registry.as_deref(),
+            accounts.abstain_proposal.as_ref(),
+            now,
+        ),
+    });
+    Ok(())
+}
+
+fn emit_proposal_created(proposal: &Account<Proposal>) {
+    emit!(ProposalCreated {
+        proposal: proposal.key(),
+        id: proposal.id,
+        creator: proposal.creator,
+        beneficiary: proposal.beneficiary,
+        request_mint: proposal.request_mint,
+        requested_amount: proposal.requested_amount,
+        metadata_uri: proposal.metadata_uri.clone(),
+    });
+}
+
+/// Returns a stake position's tokens to its owner, removing its weight from
+/// the proposal and the config, and closes the stake account.
+fn exit_stake(accounts: &mut WithdrawStake) -> Result<()> {
+    let stake_account = &mut accounts.stake_account;
+    let proposal = &mut accounts.proposal;
+    let now = current_time(&accounts.clock, &accounts.cv_config);
+
+    let amount = stake_account.staked_amount;
+    let allocated = voter_allocation(&accounts.voter_account, &proposal.key())?;
+    let mode = accounts.cv_config.conviction_mode;
+    let weight = voter_weight(amount, stake_account.multiplier, allocated, mode)?
+        - voter_weight(0, stake_account.multiplier, allocated, mode)?;
+    // An emptied position carries no weight, so this is a plain checkpoint
+    let conviction_before =
+        update_conviction_for_proposal(proposal, -(weight as i128), &accounts.cv_config, now)?;
+    if amount > 0 {
+        proposal.stake_count = proposal
+            .stake_count
+            .checked_sub(1)
+            .ok_or(CustomError::StakeUnderflow)?;
+        let cv_config = &mut accounts.cv_config;
+        cv_config.total_staked = cv_config
+            .total_staked
+            .checked_sub(weight)
+            .ok_or(CustomError::StakeUnderflow)?;
+    }
+    emit!(StakeWithdrawn {
+        user: stake_account.user,
+        proposal: proposal.key(),
+        amount,
+        status: proposal.status.clone(),
+        conviction_before,
+        conviction_after: proposal.current_conviction,
+        threshold: stake_event_threshold(
+            proposal,
+            &accounts.cv_config,
+            &accounts.commons_treasury,
+            accounts.treasury_registry.as_deref(),
+            accounts.abstain_proposal.as_ref(),
+            now,
+        ),
+    });
+    if amount > 0 {
+        // Transfer Commons tokens from staking vault to user
+        let cpi_accounts = Transfer {
+            from: accounts.staking_vault.to_account_info(),
+            to: accounts.user_commons_token_account.to_account_info(),
+            authority: accounts.cv_config.to_account_info(), // PDA authority
+        };
+        let cpi_program = accounts.token_program.to_account_info();
+        let seeds = &[b"cv_config".as_ref(), &[accounts.cv_config.cv_config_bump]];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+    }
+
+    stake_account.close(accounts.authority.to_account_info())?;
+
+    Ok(())
+}
+
+fn check_personhood(cv_config: &CVConfig, record: Option<&PersonhoodRecord>) -> Result<()> {
+    if cv_config.personhood_root == [0; 32] {
+        return Ok(());
+    }
+    let record = record.ok_or(CustomError::NotVerifiedPerson)?;
+    require!(
+        record.root == cv_config.personhood_root,
+        CustomError::NotVerifiedPerson
+    );
+    Ok(())
+}
+
+fn verify_merkle_proof(proof: Vec<[u8; 32]>, root: [u8; 32], leaf: [u8; 32]) -> bool {
+    let mut computed_hash = leaf;
+    for proof_element in proof {
+        computed_hash = if computed_hash <= proof_element {
+            hash::hashv(&[&computed_hash, &proof_element]).to_bytes()
+        } else {
+            hash::hashv(&[&proof_element, &computed_hash]).to_bytes()
+        };
+    }
+    computed_hash == root
+}
+
+/// Closes an account owned by this program that is not loaded as an `Account`,
+/// sending its lamports to `destination`.
+fn close_program_account<'info>(
+    info: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+) -> Result<()> {
+    let lamports = info.lamports();
+    **info.try_borrow_mut_lamports()? = 0;
+    **destination.try_borrow_mut_lamports()? = destination
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(CustomError::StakeOverflow)?;
+    info.assign(&System::id());
+    info.resize(0)?;
+    Ok(())
+}
+
+fn take_proposal_id(cv_config: &mut CVConfig) -> Result<u64> {
+    let id = cv_config.proposal_count;
+    cv_config.proposal_count = id
+        .checked_add(1)
+        .ok_or(CustomError::ProposalCountOverflow)?;
+    Ok(id)
+}
+
+fn proposal_expiry_slot(cv_config: &CVConfig, created_slot: u64) -> u64 {
+    if cv_config.proposal_expiry_slots == 0 {
+        0
+    } else {
+        created_slot.saturating_add(cv_config.proposal_expiry_slots)
+    }
+}
+
+fn proposal_expired(proposal: &Proposal, slot: u64) -> bool {
+    proposal.expires_at_slot != 0 && slot >= proposal.expires_at_slot
+}
+
+fn escrow_proposal_deposit<'info>(
+    proposal: &mut Proposal,
+    amount: u64,
+    creator_token_account: Option<&Account<'info, TokenAccount>>,
+    deposit_escrow: Option<&Account<'info, TokenAccount>>,
+    authority: &Signer<'info>,
+    token_program: &Program<'info, Token>,
+) -> Result<()> {
+    proposal.deposit_amount = amount;
+    proposal.deposit_refund_account = Pubkey::default();
+    if amount == 0 {
+        return Ok(());
+    }
+    let (Some(creator_token_account), Some(deposit_escrow)) =
+        (creator_token_account, deposit_escrow)
+    else {
+        return err!(CustomError::MissingProposalDeposit);
+    };
+    proposal.deposit_refund_account = creator_token_account.key();
+
+    let cpi_accounts = Transfer {
+        from: creator_token_account.to_account_info(),
+        to: deposit_escrow.to_account_info(),
+        authority: authority.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(token_program.to_account_info(), cpi_accounts);
+    token::transfer(cpi_ctx, amount)
 }
 
-#[account]
-pub struct Stake {
-    pub user: Pubkey,
-    pub proposal: Pubkey,
-    pub staked_amount: u64,
-    pub last_update_slot: u64,
-    pub authority: Pubkey, // Add this field
+fn challenge_window_closed(proposal: &Proposal, slot: u64) -> bool {
+    slot >= proposal.challenge_ends_at_slot
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
-pub enum ProposalStatus {
-    Pending,
-    Approved,
-    Rejected,
+/// Pays out a freshly approved proposal: nothing for signaling proposals, a
+/// reservation for streamed grants, otherwise a transfer to the bound beneficiary.
+fn release_funds<'info>(
+    proposal: &Proposal,
+    cv_config: &mut Account<'info, CVConfig>,
+    treasury_registry: Option<&mut TreasuryRegistry>,
+    commons_treasury: &Account<'info, TokenAccount>,
+    beneficiary: Option<&Account<'info, TokenAccount>>,
+    token_program: &Program<'info, Token>,
+    slot: u64,
+) -> Result<()> {
+    if proposal.requested_amount == 0 {
+        return Ok(());
+    }
+    spend_budget(cv_config, treasury_registry, proposal, slot, true)?;
+
+    // Streamed grants stay in the treasury, reserved until claimed via `open_funding_stream`
+    if proposal.stream_duration_slots > 0 {
+        cv_config.committed_stream_amount = cv_config
+            .committed_stream_amount
+            .checked_add(proposal.requested_amount)
+            .ok_or(CustomError::StreamOverflow)?;
+        return Ok(());
+    }
+
+    // Transfer requested_amount from commons_treasury to the bound beneficiary
+    let beneficiary = beneficiary.ok_or(CustomError::MissingBeneficiary)?;
+    let cpi_accounts = Transfer {
+        from: commons_treasury.to_account_info(),
+        to: beneficiary.to_account_info(),
+        authority: cv_config.to_account_info(), // PDA authority
+    };
+    let cpi_program = token_program.to_account_info();
+    let seeds = &[b"cv_config".as_ref(), &[cv_config.cv_config_bump]];
+    let signer = &[&seeds[..]];
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    token::transfer(cpi_ctx, proposal.requested_amount)
 }
 
-#[error_code]
-pub enum CustomError {
-    #[msg("Insufficient staked amount")]
-    InsufficientStakedAmount,
-    #[msg("Stake amount must be greater than zero")]
-    InvalidStakeAmount,
-    #[msg("Stake total overflow")]
-    StakeOverflow,
-    #[msg("Stake total underflow")]
-    StakeUnderflow,
-    #[msg("Proposal must remain pending to stake or unstake")]
-    ProposalNotPending,
-    #[msg("Proposal still pending")]
-    ProposalStillPending,
-    #[msg("Requested amount exceeds configured spending limit")]
-    SpendingLimitExceeded,
-    #[msg("Conviction threshold not reached")]
-    ThresholdNotReached,
-    #[msg("Decay rate must be <= 1")]
-    InvalidDecayRate,
-    #[msg("Max ratio must be <= 1")]
-    InvalidMaxRatio,
-    #[msg("Min threshold must be <= 1")]
-    InvalidMinThreshold,
-    #[msg("Treasury has no funds")]
-    EmptyTreasury,
+fn reset_spending_windows(cv_config: &mut CVConfig) {
+    cv_config.spending = SpendingWindow::default();
+    for category in cv_config.funding_categories.iter_mut() {
+        category.spending = SpendingWindow::default();
+    }
 }
 
-fn compute_required_conviction(
-    requested: u64,
-    treasury_balance: u64,
-    total_staked: u64,
-    config: &CVConfig,
-) -> Result<u64> {
-    require!(treasury_balance > 0, CustomError::EmptyTreasury);
+/// Checks `proposal`'s request against its mint's cap over the rolling
+/// spending window and, for commons requests, its category cap, recording the
+/// spend when `commit` is set. Each mint is capped in its own units; categories
+/// are denominated in commons tokens.
+fn spend_budget(
+    cv_config: &mut CVConfig,
+    treasury_registry: Option<&mut TreasuryRegistry>,
+    proposal: &Proposal,
+    slot: u64,
+    commit: bool,
+) -> Result<()> {
+    let amount = proposal.requested_amount;
+    if amount == 0 {
+        return Ok(());
+    }
+    let window_slots = cv_config.spending_window_slots;
+    if proposal.request_mint != cv_config.commons_token_mint {
+        if window_slots == 0 {
+            return Ok(());
+        }
+        let entry = treasury_registry
+            .and_then(|registry| {
+                registry
+                    .treasuries
+                    .iter_mut()
+                    .find(|entry| entry.mint == proposal.request_mint)
+            })
+            .ok_or(CustomError::UnregisteredTreasury)?;
+        require!(
+            entry
+                .spending
+                .check(slot, window_slots, amount, entry.spending_cap),
+            CustomError::SpendingBudgetExhausted
+        );
+        if commit {
+            entry.spending.charge(slot, window_slots, amount)?;
+        }
+        return Ok(());
+    }
 
-    let max_allowed =
-        (treasury_balance as u128).saturating_mul(config.max_ratio as u128) / CV_SCALE;
-    let max_allowed_u64 = max_allowed.min(u64::MAX as u128) as u64;
-    require!(max_allowed_u64 > 0, CustomError::SpendingLimitExceeded);
+    let spending_cap = cv_config.spending_cap;
     require!(
-        requested <= max_allowed_u64,
-        CustomError::SpendingLimitExceeded
+        window_slots == 0
+            || cv_config
+                .spending
+                .check(slot, window_slots, amount, spending_cap),
+        CustomError::SpendingBudgetExhausted
     );
+    let category = if proposal.category.is_empty() {
+        None
+    } else {
+        let category = cv_config
+            .funding_categories
+            .iter_mut()
+            .find(|category| category.name == proposal.category)
+            .ok_or(CustomError::UnknownFundingCategory)?;
+        let cap = category.cap;
+        require!(
+            category.spending.check(slot, window_slots, amount, cap),
+            CustomError::FundingCategoryCapExceeded
+        );
+        Some(category)
+    };
 
-    let effective_stake = total_staked.max(1);
-    let min_conviction = (effective_stake as u128 * config.min_threshold as u128) / CV_SCALE;
-    let request_ratio = (requested as u128 * CV_SCALE) / treasury_balance as u128;
-    let weighted_ratio = request_ratio.saturating_mul(config.weight_exponent as u128) / CV_SCALE;
-    let dynamic_conviction = weighted_ratio.saturating_mul(effective_stake as u128) / CV_SCALE;
-
-    let required = min_conviction.max(dynamic_conviction);
-    Ok(required.min(u64::MAX as u128) as u64)
+    if commit {
+        if let Some(category) = category {
+            category.spending.charge(slot, window_slots, amount)?;
+        }
+        if window_slots > 0 {
+            cv_config.spending.charge(slot, window_slots, amount)?;
+        }
+    }
+    Ok(())
 }
 
-fn update_conviction_for_proposal(
-    proposal: &mut Proposal,
-    delta: i128,
-    config: &CVConfig,
-    slot: u64,
+/// Checks `treasury` is the account paying `proposal`'s request mint: the
+/// commons treasury for the commons mint, otherwise the registered one.
+fn check_payout_treasury(
+    proposal: &Proposal,
+    cv_config: &CVConfig,
+    treasury_registry: Option<&TreasuryRegistry>,
+    treasury: &Account<TokenAccount>,
 ) -> Result<()> {
-    let elapsed = slot.saturating_sub(proposal.last_update_slot);
-    let decayed = decay_conviction(proposal.current_conviction, config.decay_rate, elapsed);
-    let updated = if delta >= 0 {
-        decayed.saturating_add(delta as u128)
+    let expected = if proposal.request_mint == cv_config.commons_token_mint {
+        Some(cv_config.commons_treasury)
     } else {
-        decayed.saturating_sub((-delta) as u128)
+        treasury_registry.and_then(|registry| registry.treasury_for(&proposal.request_mint))
     };
-    proposal.current_conviction = updated.min(u64::MAX as u128) as u64;
-    proposal.last_update_slot = slot;
+    let expected = expected.ok_or(CustomError::UnregisteredTreasury)?;
+    require_keys_eq!(treasury.key(), expected, CustomError::TreasuryMismatch);
     Ok(())
 }
 
-fn decay_conviction(current: u64, decay_rate: u64, elapsed_slots: u64) -> u128 {
-    if elapsed_slots == 0 || current == 0 {
-        return current as u128;
+fn available_treasury(treasury_balance: u64, config: &CVConfig) -> u64 {
+    treasury_balance.saturating_sub(config.committed_stream_amount)
+}
+
+fn vested_stream_amount(stream: &FundingStream, slot: u64) -> u64 {
+    if slot >= stream.end_slot {
+        return stream.total_amount;
     }
-    if decay_rate == 0 {
+    if slot <= stream.start_slot {
         return 0;
     }
-    let factor = scaled_pow(decay_rate as u128, elapsed_slots);
-    current as u128 * factor / CV_SCALE
+    let elapsed = (slot - stream.start_slot) as u128;
+    let duration = (stream.end_slot - stream.start_slot) as u128;
+    (stream.total_amount as u128 * elapsed / duration) as u64
 }
 
-fn scaled_pow(mut base: u128, mut exp: u64) -> u128 {
-    if exp == 0 {
-        return CV_SCALE;
-    }
-    if base == 0 {
-        return 0;
+/// Conviction `proposal` needs to pass at `now`, counting decayed abstain
+/// conviction into the effective supply.
+fn required_conviction(
+    proposal: &Proposal,
+    cv_config: &CVConfig,
+    treasury_balance: u64,
+    abstain_proposal: Option<&Proposal>,
+    now: u64,
+) -> Result<u64> {
+    // Abstain conviction raises the bar for every other proposal
+    let abstain_conviction = match abstain_proposal {
+        Some(abstain) => decay_since(
+            abstain.current_conviction,
+            abstain.last_update,
+            now,
+            cv_config,
+        )
+        .min(u64::MAX as u128) as u64,
+        None => {
+            require!(
+                cv_config.abstain_proposal == Pubkey::default(),
+                CustomError::MissingAbstainProposal
+            );
+            0
+        }
+    };
+    let effective_supply = cv_config.total_staked.saturating_add(abstain_conviction);
+    if proposal.requested_amount == 0 {
+        Ok(compute_signaling_conviction(effective_supply, cv_config))
+    } else {
+        compute_required_conviction(
+            proposal.requested_amount,
+            // Streams only ever reserve commons treasury funds
+            if proposal.request_mint == cv_config.commons_token_mint {
+                available_treasury(treasury_balance, cv_config)
+            } else {
+                treasury_balance
+            },
+            effective_supply,
+            cv_config,
+        )
     }
-    let mut result = CV_SCALE;
-    while exp > 0 {
-        if exp & 1 == 1 {
-            result = result.saturating_mul(base).saturating_div(CV_SCALE);
+}
+
+/// Threshold reported by stake events, or `u64::MAX` while the treasury cannot
+/// cover the request. Staking the abstain proposal itself moves the abstain
+/// conviction, so the updated proposal is used for it.
+fn stake_event_threshold(
+    proposal: &Account<Proposal>,
+    cv_config: &CVConfig,
+    commons_treasury: &Account<TokenAccount>,
+    treasury_registry: Option<&TreasuryRegistry>,
+    abstain_proposal: Option<&Account<Proposal>>,
+    now: u64,
+) -> Option<u64> {
+    // Event data never gates moving stake, so accounts that cannot give the
+    // threshold leave it out rather than failing the instruction
+    check_payout_treasury(proposal, cv_config, treasury_registry, commons_treasury).ok()?;
+    let abstain_proposal = if cv_config.abstain_proposal == Pubkey::default() {
+        None
+    } else if proposal.key() == cv_config.abstain_proposal {
+        Some(&**proposal)
+    } else {
+        let abstain_proposal = abstain_proposal?;
+        if abstain_proposal.key() != cv_config.abstain_proposal {
+            return None;
         }
-        exp >>= 1;
-        if exp > 0 {
-            base = base.saturating_mul(base).saturating_div(CV_SCALE);
+        Some(&**abstain_proposal)
+    };
+    Some(
+        required_conviction(
+            proposal,
+            cv_config,
+            commons_treasury.amount,
+            abstain_proposal,
+            now,
+        )
+        .unwrap_or(u64::MAX),
+    )
+}
+
+/// Moves one voter's support for `proposal` to `amount`, keeping its conviction,
+/// the config's total stake and the voter's allocation list in step. `stake` is
+/// the voter's stake on the proposal, if any, whose share of the voter's weight
+/// shifts with the allocation in quadratic mode.
+#[allow(clippy::too_many_arguments)]
+fn set_support(
+    voter_account: &mut VoterAccount,
+    cv_config: &mut CVConfig,
+    proposal_key: Pubkey,
+    proposal: &mut Proposal,
+    stake: Option<&mut Stake>,
+    amount: u64,
+    slot: u64,
+    now: u64,
+) -> Result<()> {
+    let current = voter_account.allocation_for(&proposal_key);
+    if amount == current {
+        return Ok(());
+    }
+    // Support can always be pulled, but only pending proposals can gain it
+    if amount > current {
+        require!(!cv_config.paused, CustomError::ConfigPaused);
+        require!(
+            proposal.status == ProposalStatus::Pending,
+            CustomError::ProposalNotPending
+        );
+        require!(
+            !proposal_expired(proposal, slot),
+            CustomError::ProposalExpired
+        );
+    }
+
+    let mode = cv_config.conviction_mode;
+    let (staked, multiplier) = stake.as_ref().map_or((0, CV_SCALE_U64), |stake| {
+        (stake.staked_amount, stake.multiplier)
+    });
+    let weight_after = voter_weight(staked, multiplier, amount, mode)?;
+    let delta = weight_after as i128 - voter_weight(staked, multiplier, current, mode)? as i128;
+    if let Some(stake) = stake {
+        stake.weighted_amount = weight_after - voter_weight(0, multiplier, amount, mode)?;
+    }
+    let conviction_before = update_conviction_for_proposal(proposal, delta, cv_config, now)?;
+    emit!(ConvictionUpdated {
+        proposal: proposal_key,
+        conviction_before,
+        conviction_after: proposal.current_conviction,
+    });
+    cv_config.total_staked = if delta >= 0 {
+        cv_config
+            .total_staked
+            .checked_add(delta as u64)
+            .ok_or(CustomError::StakeOverflow)?
+    } else {
+        cv_config
+            .total_staked
+            .checked_sub((-delta) as u64)
+            .ok_or(CustomError::StakeUnderflow)?
+    };
+    // An allocation is one more position keeping the proposal open
+    if current == 0 {
+        proposal.stake_count = proposal
+            .stake_count
+            .checked_add(1)
+            .ok_or(CustomError::StakeOverflow)?;
+    } else if amount == 0 {
+        proposal.stake_count = proposal
+            .stake_count
+            .checked_sub(1)
+            .ok_or(CustomError::StakeUnderflow)?;
+    }
+    voter_account.set_allocation(proposal_key, amount)
+}
+
+/// Withdraws all of a voter's support. Every allocated proposal must be supplied,
+/// paired with the voter's stake on it.
+fn clear_allocations<'info>(
+    voter_account: &mut VoterAccount,
+    cv_config: &mut CVConfig,
+    remaining_accounts: &'info [AccountInfo<'info>],
+    clock: &Clock,
+) -> Result<()> {
+    // `is_multiple_of` needs Rust 1.87, newer than the SBF toolchain
+    #[allow(clippy::manual_is_multiple_of)]
+    let paired = remaining_accounts.len() % 2 == 0;
+    require!(paired, CustomError::AllocationAccountsMismatch);
+    let now = current_time(clock, cv_config);
+    for pair in remaining_accounts.chunks(2) {
+        let (proposal_info, stake_info) = (&pair[0], &pair[1]);
+        let mut proposal = Account::<Proposal>::try_from(proposal_info)?;
+        let mut stake = voter_stake(stake_info, &voter_account.authority, &proposal_info.key())?;
+        set_support(
+            voter_account,
+            cv_config,
+            proposal_info.key(),
+            &mut proposal,
+            stake.as_deref_mut(),
+            0,
+            clock.slot,
+            now,
+        )?;
+        proposal.exit(&crate::ID)?;
+        if let Some(stake) = stake {
+            stake.exit(&crate::ID)?;
         }
     }
-    result
+    require!(
+        voter_account.allocations.is_empty(),
+        CustomError::AllocationAccountsMismatch
+    );
+    Ok(())
+}
+
+/// The voter's allocation to `proposal`, or 0 if their voter account does not
+/// exist.
+fn voter_allocation(voter_info: &AccountInfo, proposal: &Pubkey) -> Result<u64> {
+    if voter_info.data_is_empty() {
+        return Ok(0);
+    }
+    require_keys_eq!(
+        *voter_info.owner,
+        crate::ID,
+        ErrorCode::AccountOwnedByWrongProgram
+    );
+    let voter_account = VoterAccount::try_deserialize(&mut &voter_info.data.borrow()[..])?;
+    Ok(voter_account.allocation_for(proposal))
+}
+
+/// Loads `owner`'s stake on `proposal` from `stake_info`, which must be its PDA;
+/// `None` if the stake account does not exist.
+fn voter_stake<'info>(
+    stake_info: &'info AccountInfo<'info>,
+    owner: &Pubkey,
+    proposal: &Pubkey,
+) -> Result<Option<Account<'info, Stake>>> {
+    let (expected, _) =
+        Pubkey::find_program_address(&[b"stake", owner.as_ref(), proposal.as_ref()], &crate::ID);
+    require_keys_eq!(
+        stake_info.key(),
+        expected,
+        CustomError::AllocationAccountsMismatch
+    );
+    if stake_info.data_is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Account::try_from(stake_info)?))
+}
+
+/// Brings `proposal`'s conviction up to `now` and applies `delta`, returning the
+/// decayed conviction from before the delta.
+fn update_conviction_for_proposal(
+    proposal: &mut Proposal,
+    delta: i128,
+    config: &CVConfig,
+    now: u64,
+) -> Result<u64> {
+    let decayed = decay_since(
+        proposal.current_conviction,
+        proposal.last_update,
+        now,
+        config,
+    );
+    let updated = if delta >= 0 {
+        decayed.saturating_add(delta as u128)
+    } else {
+        decayed.saturating_sub((-delta) as u128)
+    };
+    proposal.current_conviction = updated.min(u64::MAX as u128) as u64;
+    proposal.last_update = now;
+    Ok(decayed.min(u64::MAX as u128) as u64)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::math::{isqrt, position_weight, scaled_pow, weighted_amount, CV_SCALE};
     use anchor_lang::prelude::Pubkey;
 
     fn base_config() -> CVConfig {
@@ -530,17 +3331,51 @@ mod tests {
             staking_vault_bump: 0,
             authority: Pubkey::default(),
             total_staked: 0,
+            proposal_count: 0,
+            proposal_expiry_slots: 0,
+            signaling_threshold: CV_SCALE_U64 / 4,
+            abstain_proposal: Pubkey::default(),
+            allowed_programs: Vec::new(),
+            stream_duration_slots: 0,
+            committed_stream_amount: 0,
+            challenge_period_slots: 0,
+            challenge_bond: 0,
+            arbiter: Pubkey::default(),
+            proposal_deposit: 0,
+            time_base: TimeBase::Slots,
+            previous_decay_rate: CV_SCALE_U64 / 2,
+            decay_rate_updated_at: 0,
+            lock_schedule: Vec::new(),
+            spending_window_slots: 0,
+            spending_cap: 0,
+            spending: SpendingWindow::default(),
+            funding_categories: Vec::new(),
+            guardian: Pubkey::default(),
+            paused: false,
+            conviction_mode: ConvictionMode::Linear,
+            personhood_root: [0; 32],
         }
     }
 
     fn base_proposal() -> Proposal {
         Proposal {
+            id: 0,
             creator: Pubkey::default(),
+            beneficiary: Pubkey::default(),
             requested_amount: 0,
-            metadata_hash: String::new(),
+            metadata_uri: String::new(),
             status: ProposalStatus::Pending,
             current_conviction: 0,
-            last_update_slot: 0,
+            last_update: 0,
+            decided_at_slot: 0,
+            expires_at_slot: 0,
+            stream_duration_slots: 0,
+            challenge_ends_at_slot: 0,
+            deposit_amount: 0,
+            deposit_refund_account: Pubkey::default(),
+            category: String::new(),
+            request_mint: Pubkey::default(),
+            stake_count: 0,
         }
     }
 
@@ -572,6 +3407,14 @@ mod tests {
         assert_eq!(required, 800);
     }
 
+    #[test]
+    fn compute_signaling_conviction_is_fixed_fraction_of_supply() {
+        let config = base_config();
+        assert_eq!(compute_signaling_conviction(1_000, &config), 250);
+        // Abstain conviction folded into the supply raises the bar proportionally
+        assert_eq!(compute_signaling_conviction(1_000 + 600, &config), 400);
+    }
+
     #[test]
     fn update_conviction_applies_decay_and_delta() {
         let mut proposal = base_proposal();
@@ -585,4 +3428,463 @@ mod tests {
         update_conviction_for_proposal(&mut proposal, -20, &config, 2).unwrap();
         assert!(proposal.current_conviction < peak);
     }
+
+    #[test]
+    fn proposal_expiry_respects_disabled_and_boundary() {
+        let mut proposal = base_proposal();
+        assert!(!proposal_expired(&proposal, u64::MAX));
+
+        proposal.expires_at_slot = 100;
+        assert!(!proposal_expired(&proposal, 99));
+        assert!(proposal_expired(&proposal, 100));
+    }
+
+    #[test]
+    fn vested_stream_amount_is_linear_and_capped() {
+        let mut stream = FundingStream {
+            proposal: Pubkey::default(),
+            beneficiary: Pubkey::default(),
+            total_amount: 1_000,
+            claimed_amount: 0,
+            start_slot: 100,
+            end_slot: 200,
+            cancelled: false,
+            bump: 0,
+        };
+        assert_eq!(vested_stream_amount(&stream, 50), 0);
+        assert_eq!(vested_stream_amount(&stream, 100), 0);
+        assert_eq!(vested_stream_amount(&stream, 125), 250);
+        assert_eq!(vested_stream_amount(&stream, 200), 1_000);
+        assert_eq!(vested_stream_amount(&stream, 10_000), 1_000);
+
+        // Cancelling at the start slot leaves nothing vested without dividing by zero
+        stream.total_amount = 0;
+        stream.end_slot = 100;
+        assert_eq!(vested_stream_amount(&stream, 100), 0);
+    }
+
+    #[test]
+    fn available_treasury_excludes_committed_streams() {
+        let mut config = base_config();
+        config.committed_stream_amount = 400;
+        assert_eq!(available_treasury(1_000, &config), 600);
+        assert_eq!(available_treasury(300, &config), 0);
+    }
+
+    #[test]
+    fn proposal_instructions_space_matches_serialized_len() {
+        let instructions = vec![
+            ProposalInstruction {
+                program_id: Pubkey::new_unique(),
+                accounts: vec![
+                    ProposalAccountMeta {
+                        pubkey: Pubkey::new_unique(),
+                        is_signer: true,
+                        is_writable: true,
+                    };
+                    3
+                ],
+                data: vec![7; 41],
+            },
+            ProposalInstruction {
+                program_id: Pubkey::new_unique(),
+                accounts: Vec::new(),
+                data: Vec::new(),
+            },
+        ];
+        let account = ProposalInstructions {
+            proposal: Pubkey::new_unique(),
+            instructions: instructions.clone(),
+            executed: false,
+            bump: 255,
+        };
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+        assert_eq!(data.len(), ProposalInstructions::space(&instructions));
+    }
+
+    #[test]
+    fn voter_allocations_update_in_place_and_drop_zeroes() {
+        let mut voter = VoterAccount {
+            authority: Pubkey::default(),
+            deposited_amount: 1_000,
+            allocations: Vec::new(),
+            voter_bump: 0,
+            delegate: Pubkey::default(),
+        };
+        let first = Pubkey::new_unique();
+        let second = Pubkey::new_unique();
+
+        voter.set_allocation(first, 600).unwrap();
+        voter.set_allocation(second, 400).unwrap();
+        assert_eq!(voter.allocated_amount().unwrap(), 1_000);
+        assert_eq!(voter.unallocated_amount(), 0);
+
+        voter.set_allocation(first, 100).unwrap();
+        assert_eq!(voter.allocation_for(&first), 100);
+        assert_eq!(voter.unallocated_amount(), 500);
+
+        voter.set_allocation(second, 0).unwrap();
+        assert_eq!(voter.allocations.len(), 1);
+        assert_eq!(voter.allocation_for(&second), 0);
+    }
+
+    #[test]
+    fn voter_allocations_are_capped() {
+        let mut voter = VoterAccount {
+            authority: Pubkey::default(),
+            deposited_amount: u64::MAX,
+            allocations: Vec::new(),
+            voter_bump: 0,
+            delegate: Pubkey::default(),
+        };
+        for _ in 0..MAX_VOTER_ALLOCATIONS {
+            voter.set_allocation(Pubkey::new_unique(), 1).unwrap();
+        }
+        assert!(voter.set_allocation(Pubkey::new_unique(), 1).is_err());
+    }
+
+    #[test]
+    fn challenge_window_closes_at_end_slot() {
+        let mut proposal = base_proposal();
+        proposal.status = ProposalStatus::Challengeable;
+        proposal.challenge_ends_at_slot = 150;
+        assert!(!challenge_window_closed(&proposal, 100));
+        assert!(!challenge_window_closed(&proposal, 149));
+        assert!(challenge_window_closed(&proposal, 150));
+    }
+
+    #[test]
+    fn current_time_follows_config_time_base() {
+        let mut config = base_config();
+        let clock = Clock {
+            slot: 1_000,
+            unix_timestamp: 1_700_000_000,
+            ..Clock::default()
+        };
+        assert_eq!(current_time(&clock, &config), 1_000);
+        config.time_base = TimeBase::Seconds;
+        assert_eq!(current_time(&clock, &config), 1_700_000_000);
+        let before_epoch = Clock {
+            unix_timestamp: -5,
+            ..clock
+        };
+        assert_eq!(current_time(&before_epoch, &config), 0);
+    }
+
+    #[test]
+    fn voter_allocator_switches_to_delegate() {
+        let owner = Pubkey::new_unique();
+        let steward = Pubkey::new_unique();
+        let mut voter = VoterAccount {
+            authority: owner,
+            deposited_amount: 0,
+            allocations: Vec::new(),
+            voter_bump: 0,
+            delegate: Pubkey::default(),
+        };
+        assert_eq!(voter.allocator(), owner);
+        voter.delegate = steward;
+        assert_eq!(voter.allocator(), steward);
+    }
+
+    #[test]
+    fn decay_since_applies_previous_rate_before_change() {
+        let mut config = base_config();
+        config.previous_decay_rate = CV_SCALE_U64 / 2;
+        config.decay_rate = CV_SCALE_U64;
+        config.decay_rate_updated_at = 12;
+
+        // Two units at one half, then no decay at all under the new rate.
+        assert_eq!(decay_since(1_000, 10, 20, &config), 250);
+        // Updates after the change only see the new rate.
+        assert_eq!(decay_since(1_000, 12, 20, &config), 1_000);
+        // Reads between the change and the last update never go negative.
+        assert_eq!(decay_since(1_000, 10, 11, &config), 500);
+    }
+
+    #[test]
+    fn validate_cv_params_matches_initialization_bounds() {
+        assert!(validate_cv_params(CV_SCALE_U64, CV_SCALE_U64, CV_SCALE_U64).is_ok());
+        assert!(validate_cv_params(CV_SCALE_U64 + 1, 0, 0).is_err());
+        assert!(validate_cv_params(0, CV_SCALE_U64 + 1, 0).is_err());
+        assert!(validate_cv_params(0, 0, CV_SCALE_U64 + 1).is_err());
+    }
+
+    #[test]
+    fn lock_multiplier_picks_longest_reached_tier() {
+        let schedule = vec![
+            LockTier {
+                duration: 100,
+                multiplier: 1_500_000,
+            },
+            LockTier {
+                duration: 1_000,
+                multiplier: 3_000_000,
+            },
+        ];
+        assert!(validate_lock_schedule(&schedule).is_ok());
+        assert_eq!(lock_multiplier(&schedule, 99), CV_SCALE_U64);
+        assert_eq!(lock_multiplier(&schedule, 100), 1_500_000);
+        assert_eq!(lock_multiplier(&schedule, 5_000), 3_000_000);
+        assert_eq!(weighted_amount(1_000, 1_500_000).unwrap(), 1_500);
+    }
+
+    #[test]
+    fn lock_schedule_rejects_unordered_or_discounting_tiers() {
+        let tier = |duration, multiplier| LockTier {
+            duration,
+            multiplier,
+        };
+        assert!(validate_lock_schedule(&[tier(100, 900_000)]).is_err());
+        assert!(validate_lock_schedule(&[tier(0, 2_000_000)]).is_err());
+        assert!(validate_lock_schedule(&[tier(200, 2_000_000), tier(100, 3_000_000)]).is_err());
+        assert!(validate_lock_schedule(&[tier(100, 3_000_000), tier(200, 2_000_000)]).is_err());
+        assert!(validate_lock_schedule(&vec![tier(1, CV_SCALE_U64); MAX_LOCK_TIERS + 1]).is_err());
+    }
+
+    #[test]
+    fn predicted_pass_is_now_or_never_at_current_support() {
+        let config = base_config();
+        let mut proposal = base_proposal();
+        proposal.current_conviction = 1_000;
+        assert_eq!(
+            predict_pass_time(proposal.current_conviction, 800, 7),
+            Some(7)
+        );
+        assert_eq!(
+            predict_pass_time(proposal.current_conviction, 1_000, 7),
+            Some(7)
+        );
+
+        // Without new support conviction only decays, so a short proposal stays short
+        let later = decay_since(proposal.current_conviction, 0, 10, &config) as u64;
+        assert!(later < proposal.current_conviction);
+        assert_eq!(predict_pass_time(later, 1_000, 10), None);
+    }
+
+    #[test]
+    fn spending_window_caps_total_and_category_spend() {
+        let mut config = base_config();
+        config.spending_window_slots = 100;
+        config.spending_cap = 1_000;
+        config.funding_categories.push(FundingCategory {
+            name: "events".to_string(),
+            cap: 300,
+            spending: SpendingWindow::default(),
+        });
+        let mut proposal = base_proposal();
+        proposal.requested_amount = 600;
+
+        // A dry run leaves the budget untouched
+        assert!(spend_budget(&mut config, None, &proposal, 10, false).is_ok());
+        assert_eq!(config.spending, SpendingWindow::default());
+        assert!(spend_budget(&mut config, None, &proposal, 10, true).is_ok());
+        assert!(spend_budget(&mut config, None, &proposal, 20, true).is_err());
+
+        proposal.requested_amount = 200;
+        proposal.category = "events".to_string();
+        assert!(spend_budget(&mut config, None, &proposal, 30, true).is_ok());
+        assert!(spend_budget(&mut config, None, &proposal, 40, true).is_err());
+        assert_eq!(config.spending.trailing_spend(40, 100), 800);
+
+        // The category's charge at slot 30 counts in full until it leaves the
+        // window at slot 130
+        assert!(spend_budget(&mut config, None, &proposal, 110, true).is_err());
+        assert!(spend_budget(&mut config, None, &proposal, 129, true).is_err());
+        assert!(spend_budget(&mut config, None, &proposal, 130, true).is_ok());
+        // Slots 10 and 30 shared a bucket, which aged out with its last charge
+        assert_eq!(config.spending.trailing_spend(130, 100), 200);
+        assert_eq!(
+            config.funding_categories[0]
+                .spending
+                .trailing_spend(130, 100),
+            200
+        );
+
+        // Once the window has passed, nothing from before counts
+        proposal.category.clear();
+        proposal.requested_amount = 1_000;
+        assert!(spend_budget(&mut config, None, &proposal, 229, true).is_err());
+        assert!(spend_budget(&mut config, None, &proposal, 230, true).is_ok());
+
+        proposal.category = "unknown".to_string();
+        assert!(spend_budget(&mut config, None, &proposal, 500, true).is_err());
+    }
+
+    #[test]
+    fn spending_window_bounds_every_trailing_window() {
+        let mut window = SpendingWindow::default();
+        assert!(window.check(99, 100, 100, 100));
+        window.charge(99, 100, 100).unwrap();
+        // Slot 99 stays inside every window up to slot 198
+        assert!(!window.check(150, 100, 50, 100));
+        assert!(!window.check(198, 100, 1, 100));
+        assert!(window.check(199, 100, 100, 100));
+
+        // A steady trickle never adds up to more than the cap in any window
+        let mut window = SpendingWindow::default();
+        let mut paid = Vec::new();
+        for slot in (0..1_000).step_by(7) {
+            if window.check(slot, 100, 10, 100) {
+                window.charge(slot, 100, 10).unwrap();
+                paid.push(slot);
+            }
+        }
+        for end in 0..1_000u64 {
+            let in_window = paid.iter().filter(|&&slot| slot + 100 > end && slot <= end);
+            assert!(in_window.count() * 10 <= 100);
+        }
+
+        // After the window grows, the bucket making way still counts and
+        // carries over into the next one
+        let mut window = SpendingWindow::default();
+        for slot in [0, 134, 268, 402] {
+            window.charge(slot, 400, 10).unwrap();
+        }
+        window.charge(700, 1_000, 10).unwrap();
+        assert_eq!(
+            window.buckets[0],
+            SpendingBucket {
+                last_slot: 134,
+                spent: 20,
+            }
+        );
+        assert_eq!(window.trailing_spend(700, 1_000), 50);
+
+        // Without a window the spend is a running total
+        let mut window = SpendingWindow::default();
+        window.charge(10, 0, 30).unwrap();
+        window.charge(1_000_000, 0, 30).unwrap();
+        assert_eq!(window.trailing_spend(u64::MAX, 0), 60);
+    }
+
+    #[test]
+    fn spending_window_caps_each_mint_in_its_own_units() {
+        let mut config = base_config();
+        config.spending_window_slots = 100;
+        config.spending_cap = 1_000;
+        let mint = Pubkey::new_unique();
+        let mut registry = TreasuryRegistry {
+            cv_config: Pubkey::default(),
+            treasuries: vec![RegisteredTreasury {
+                mint,
+                treasury: Pubkey::new_unique(),
+                spending_cap: 300,
+                spending: SpendingWindow::default(),
+            }],
+            bump: 0,
+        };
+        let mut proposal = base_proposal();
+        proposal.request_mint = mint;
+        proposal.requested_amount = 200;
+
+        assert!(spend_budget(&mut config, None, &proposal, 10, true).is_err());
+        assert!(spend_budget(&mut config, Some(&mut registry), &proposal, 10, true).is_ok());
+        assert!(spend_budget(&mut config, Some(&mut registry), &proposal, 20, true).is_err());
+        assert_eq!(registry.treasuries[0].spending.trailing_spend(20, 100), 200);
+        // The commons budget is untouched
+        assert_eq!(config.spending, SpendingWindow::default());
+    }
+
+    #[test]
+    fn quadratic_weight_is_square_root_of_stake() {
+        assert_eq!(isqrt(0), 0);
+        assert_eq!(isqrt(15), 3);
+        assert_eq!(isqrt(16), 4);
+        assert_eq!(isqrt(u128::MAX), u64::MAX as u128);
+
+        let linear = position_weight(400, CV_SCALE_U64, ConvictionMode::Linear).unwrap();
+        assert_eq!(linear, 400);
+        let quadratic = position_weight(400, CV_SCALE_U64, ConvictionMode::Quadratic).unwrap();
+        assert_eq!(quadratic, 20 * CV_SCALE_U64);
+        // Lock multipliers apply before the root
+        let locked = position_weight(100, 4 * CV_SCALE_U64, ConvictionMode::Quadratic).unwrap();
+        assert_eq!(locked, quadratic);
+        // Splitting a stake across wallets does not beat the root of the sum per wallet
+        let split = position_weight(200, CV_SCALE_U64, ConvictionMode::Quadratic).unwrap();
+        assert!(split < quadratic);
+        // Nor does splitting one voter's support between a stake and an allocation
+        let combined = voter_weight(100, 2 * CV_SCALE_U64, 200, ConvictionMode::Quadratic).unwrap();
+        assert_eq!(combined, quadratic);
+    }
+
+    #[test]
+    fn personhood_proof_checks_sorted_pairs() {
+        let user = Pubkey::new_unique();
+        let other = hash::hashv(&[Pubkey::new_unique().as_ref()]).to_bytes();
+        let leaf = hash::hashv(&[user.as_ref()]).to_bytes();
+        let root = if leaf <= other {
+            hash::hashv(&[&leaf, &other]).to_bytes()
+        } else {
+            hash::hashv(&[&other, &leaf]).to_bytes()
+        };
+        assert!(verify_merkle_proof(vec![other], root, leaf));
+        assert!(!verify_merkle_proof(vec![leaf], root, other.map(|b| !b)));
+
+        let mut config = base_config();
+        assert!(check_personhood(&config, None).is_ok());
+        config.personhood_root = root;
+        assert!(check_personhood(&config, None).is_err());
+        let record = PersonhoodRecord {
+            user,
+            root,
+            bump: 0,
+        };
+        assert!(check_personhood(&config, Some(&record)).is_ok());
+        config.personhood_root = [1; 32];
+        assert!(check_personhood(&config, Some(&record)).is_err());
+    }
+
+    #[test]
+    fn support_allocations_count_as_open_positions() {
+        let mut config = base_config();
+        let mut proposal = base_proposal();
+        let proposal_key = Pubkey::new_unique();
+        let mut voter = VoterAccount {
+            authority: Pubkey::default(),
+            deposited_amount: 1_000,
+            allocations: Vec::new(),
+            voter_bump: 0,
+            delegate: Pubkey::default(),
+        };
+
+        set_support(
+            &mut voter,
+            &mut config,
+            proposal_key,
+            &mut proposal,
+            None,
+            600,
+            0,
+            0,
+        )
+        .unwrap();
+        assert_eq!(proposal.stake_count, 1);
+        set_support(
+            &mut voter,
+            &mut config,
+            proposal_key,
+            &mut proposal,
+            None,
+            300,
+            0,
+            0,
+        )
+        .unwrap();
+        assert_eq!(proposal.stake_count, 1);
+        set_support(
+            &mut voter,
+            &mut config,
+            proposal_key,
+            &mut proposal,
+            None,
+            0,
+            0,
+            0,
+        )
+        .unwrap();
+        assert_eq!(proposal.stake_count, 0);
+        assert_eq!(config.total_staked, 0);
+    }
 }
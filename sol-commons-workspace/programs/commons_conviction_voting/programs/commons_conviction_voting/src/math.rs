@@ -0,0 +1,162 @@
+//! Fixed-point conviction math shared by the on-chain instructions and
+//! off-chain clients estimating thresholds and time to pass.
+
+use anchor_lang::prelude::*;
+
+use crate::{CVConfig, ConvictionMode, CustomError, LockTier};
+
+/// Fixed-point scale for rates, ratios and multipliers (1.0 == `CV_SCALE`)
+pub const CV_SCALE: u128 = 1_000_000;
+pub const CV_SCALE_U64: u64 = 1_000_000;
+
+/// Multiplier of the longest tier `lock_duration` reaches, or 1 if none.
+pub fn lock_multiplier(schedule: &[LockTier], lock_duration: u64) -> u64 {
+    schedule
+        .iter()
+        .filter(|tier| tier.duration <= lock_duration)
+        .map(|tier| tier.multiplier)
+        .max()
+        .unwrap_or(CV_SCALE_U64)
+}
+
+pub fn weighted_amount(amount: u64, multiplier: u64) -> Result<u64> {
+    let weighted = amount as u128 * multiplier as u128 / CV_SCALE;
+    u64::try_from(weighted).map_err(|_| error!(CustomError::StakeOverflow))
+}
+
+/// Contribution of one voter's position to a proposal's conviction: the
+/// lock-weighted amount, or in quadratic mode its square root scaled by
+/// `CV_SCALE`.
+pub fn position_weight(amount: u64, multiplier: u64, mode: ConvictionMode) -> Result<u64> {
+    let weighted = weighted_amount(amount, multiplier)?;
+    match mode {
+        ConvictionMode::Linear => Ok(weighted),
+        ConvictionMode::Quadratic => {
+            let root = isqrt(weighted as u128 * CV_SCALE * CV_SCALE);
+            u64::try_from(root).map_err(|_| error!(CustomError::StakeOverflow))
+        }
+    }
+}
+
+/// Contribution of one voter's whole support for a proposal: a stake of
+/// `staked` at `multiplier` plus an `allocated` deposit. Quadratic mode roots
+/// the voter's total, so splitting support between a stake and an allocation
+/// gains nothing.
+pub fn voter_weight(
+    staked: u64,
+    multiplier: u64,
+    allocated: u64,
+    mode: ConvictionMode,
+) -> Result<u64> {
+    let total = weighted_amount(staked, multiplier)?
+        .checked_add(allocated)
+        .ok_or(CustomError::StakeOverflow)?;
+    position_weight(total, CV_SCALE_U64, mode)
+}
+
+/// Floor of the square root of `value`.
+pub fn isqrt(value: u128) -> u128 {
+    if value < 2 {
+        return value;
+    }
+    // Newton's method from an initial guess at or above the root
+    let mut x = 1u128 << (128 - value.leading_zeros()).div_ceil(2);
+    loop {
+        let next = (x + value / x) / 2;
+        if next >= x {
+            return x;
+        }
+        x = next;
+    }
+}
+
+pub fn compute_signaling_conviction(effective_supply: u64, config: &CVConfig) -> u64 {
+    let effective_stake = effective_supply.max(1);
+    let required = (effective_stake as u128 * config.signaling_threshold as u128) / CV_SCALE;
+    required.min(u64::MAX as u128) as u64
+}
+
+pub fn compute_required_conviction(
+    requested: u64,
+    treasury_balance: u64,
+    effective_supply: u64,
+    config: &CVConfig,
+) -> Result<u64> {
+    require!(treasury_balance > 0, CustomError::EmptyTreasury);
+
+    let max_allowed =
+        (treasury_balance as u128).saturating_mul(config.max_ratio as u128) / CV_SCALE;
+    let max_allowed_u64 = max_allowed.min(u64::MAX as u128) as u64;
+    require!(max_allowed_u64 > 0, CustomError::SpendingLimitExceeded);
+    require!(
+        requested <= max_allowed_u64,
+        CustomError::SpendingLimitExceeded
+    );
+
+    let effective_stake = effective_supply.max(1);
+    let min_conviction = (effective_stake as u128 * config.min_threshold as u128) / CV_SCALE;
+    let request_ratio = (requested as u128 * CV_SCALE) / treasury_balance as u128;
+    let weighted_ratio = request_ratio.saturating_mul(config.weight_exponent as u128) / CV_SCALE;
+    let dynamic_conviction = weighted_ratio.saturating_mul(effective_stake as u128) / CV_SCALE;
+
+    let required = min_conviction.max(dynamic_conviction);
+    Ok(required.min(u64::MAX as u128) as u64)
+}
+
+/// Decays conviction last updated at `last_update` up to `now`, applying the
+/// previous decay rate to any span before the last `decay_rate` change.
+pub fn decay_since(current: u64, last_update: u64, now: u64, config: &CVConfig) -> u128 {
+    let changed_at = config.decay_rate_updated_at;
+    if last_update >= changed_at {
+        return decay_conviction(current, config.decay_rate, now.saturating_sub(last_update));
+    }
+    let at_change = decay_conviction(
+        current,
+        config.previous_decay_rate,
+        changed_at.min(now) - last_update,
+    )
+    .min(u64::MAX as u128) as u64;
+    decay_conviction(at_change, config.decay_rate, now.saturating_sub(changed_at))
+}
+
+pub fn decay_conviction(current: u64, decay_rate: u64, elapsed: u64) -> u128 {
+    if elapsed == 0 || current == 0 {
+        return current as u128;
+    }
+    if decay_rate == 0 {
+        return 0;
+    }
+    let factor = scaled_pow(decay_rate as u128, elapsed);
+    current as u128 * factor / CV_SCALE
+}
+
+pub fn scaled_pow(mut base: u128, mut exp: u64) -> u128 {
+    if exp == 0 {
+        return CV_SCALE;
+    }
+    if base == 0 {
+        return 0;
+    }
+    let mut result = CV_SCALE;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result.saturating_mul(base).saturating_div(CV_SCALE);
+        }
+        exp >>= 1;
+        if exp > 0 {
+            base = base.saturating_mul(base).saturating_div(CV_SCALE);
+        }
+    }
+    result
+}
+
+/// Predicts the time at which a proposal with `conviction` passes a threshold
+/// of `required`, both brought up to `now`, if its support stays unchanged.
+///
+/// Support adds to conviction when it is staked and conviction only decays
+/// afterwards, while the threshold scales with the effective supply whose
+/// abstain share decays at the same rate. Without new support a proposal
+/// therefore passes now or not at all.
+pub fn predict_pass_time(conviction: u64, required: u64, now: u64) -> Option<u64> {
+    (conviction >= required).then_some(now)
+}
@@ -1,28 +1,78 @@
 #![cfg(test)]
 
-use anchor_lang::error::AnchorErrorCode;
+use anchor_lang::solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    hash::hashv,
+    program_pack::Pack,
+    program_stubs::{self, SyscallStubs},
+    pubkey::Pubkey,
+    sysvar,
+};
+use anchor_lang::{
+    error::ErrorCode as AnchorErrorCode, AccountDeserialize, AnchorDeserialize, Event,
+    InstructionData, ToAccountMetas,
+};
+use base64::Engine;
 use commons_conviction_voting::{
-    self, accounts as cv_accounts, instruction as cv_instruction, CustomError, ID as CV_ID,
-    Proposal, ProposalStatus,
+    self, accounts as cv_accounts, instruction as cv_instruction, CVConfig, ConvictionMode,
+    ConvictionUpdated, CustomError, LockTier, Proposal, ProposalAccountMeta, ProposalClosed,
+    ProposalCreated, ProposalExecuted, ProposalInstruction, ProposalInstructions, ProposalStatus,
+    StakeWithdrawn, Staked, SupportAllocation, TimeBase, TreasuryRegistry, Unstaked, VoterAccount,
+    ID as CV_ID, SPENDING_BUCKETS,
 };
-use solana_program::{
-    pubkey::Pubkey,
-    system_instruction,
-    sysvar::{self, clock},
+use solana_program_test::{
+    processor, BanksClientError, ProgramTest, ProgramTestBanksClientExt, ProgramTestContext,
 };
-use solana_program_test::{processor, ProgramTest};
 use solana_sdk::{
-    instruction::{Instruction, InstructionError},
+    clock::Clock,
+    instruction::{AccountMeta, Instruction, InstructionError},
     signature::Keypair,
-    signature::Signer,
-    transaction::Transaction,
-    transport::TransportError,
+    signature::{Signature, Signer},
+    transaction::{Transaction, TransactionError},
 };
-use spl_associated_token_account::{
-    create_associated_token_account, get_associated_token_address, id as associated_token_program_id,
+use solana_system_interface::{instruction as system_instruction, program as system_program};
+use spl_token::{
+    instruction as token_instruction,
+    state::{Account as TokenAccountState, Mint},
 };
-use spl_token::{instruction as token_instruction, state::{Account as TokenAccountState, Mint}};
-use solana_sdk::account::Account as SolanaAccount;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::sync::Once;
+
+thread_local! {
+    // Each `#[tokio::test]` runs on its own thread, so this is per test
+    static SENT_SIGNATURES: RefCell<HashSet<Signature>> = RefCell::new(HashSet::new());
+}
+
+/// Signs with a blockhash this exact transaction has not been sent under yet.
+/// The bank answers a resend with the first attempt's status instead of
+/// executing it again, so repeats wait for the next blockhash.
+async fn signed_transaction(
+    banks_client: &mut solana_program_test::BanksClient,
+    payer: &Keypair,
+    instructions: &[Instruction],
+    signers: Vec<&Keypair>,
+) -> Transaction {
+    let mut all_signers = vec![payer];
+    all_signers.extend(signers);
+    let mut recent_blockhash = banks_client.get_latest_blockhash().await.unwrap();
+    loop {
+        let tx = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&payer.pubkey()),
+            &all_signers,
+            recent_blockhash,
+        );
+        if SENT_SIGNATURES.with(|sent| sent.borrow_mut().insert(tx.signatures[0])) {
+            return tx;
+        }
+        recent_blockhash = banks_client
+            .get_new_latest_blockhash(&recent_blockhash)
+            .await
+            .unwrap();
+    }
+}
 
 async fn process_transaction(
     banks_client: &mut solana_program_test::BanksClient,
@@ -30,15 +80,7 @@ async fn process_transaction(
     instructions: Vec<Instruction>,
     signers: Vec<&Keypair>,
 ) {
-    let recent_blockhash = banks_client.get_latest_blockhash().await.unwrap();
-    let mut all_signers = vec![payer];
-    all_signers.extend(signers);
-    let tx = Transaction::new_signed_with_payer(
-        &instructions,
-        Some(&payer.pubkey()),
-        &all_signers,
-        recent_blockhash,
-    );
+    let tx = signed_transaction(banks_client, payer, &instructions, signers).await;
     banks_client.process_transaction(tx).await.unwrap();
 }
 
@@ -57,14 +99,9 @@ async fn create_mint(
         Mint::LEN as u64,
         &spl_token::id(),
     );
-    let init_mint = token_instruction::initialize_mint(
-        &spl_token::id(),
-        &mint.pubkey(),
-        authority,
-        None,
-        6,
-    )
-    .unwrap();
+    let init_mint =
+        token_instruction::initialize_mint(&spl_token::id(), &mint.pubkey(), authority, None, 6)
+            .unwrap();
     process_transaction(
         banks_client,
         payer,
@@ -81,11 +118,27 @@ async fn create_token_account(
     owner: &Pubkey,
     mint: &Pubkey,
 ) -> Pubkey {
-    let ata = get_associated_token_address(owner, mint);
-    let create_ix =
-        create_associated_token_account(&payer.pubkey(), owner, mint);
-    process_transaction(banks_client, payer, vec![create_ix], vec![]).await;
-    ata
+    // A fresh account per call, so one owner can hold several accounts of a mint
+    let account = Keypair::new();
+    let rent = banks_client.get_rent().await.unwrap();
+    let create_account = system_instruction::create_account(
+        &payer.pubkey(),
+        &account.pubkey(),
+        rent.minimum_balance(TokenAccountState::LEN),
+        TokenAccountState::LEN as u64,
+        &spl_token::id(),
+    );
+    let init_account =
+        token_instruction::initialize_account3(&spl_token::id(), &account.pubkey(), mint, owner)
+            .unwrap();
+    process_transaction(
+        banks_client,
+        payer,
+        vec![create_account, init_account],
+        vec![&account],
+    )
+    .await;
+    account.pubkey()
 }
 
 async fn mint_to_account(
@@ -115,20 +168,13 @@ async fn expect_cv_error(
     signers: Vec<&Keypair>,
     expected: CustomError,
 ) {
-    let recent_blockhash = banks_client.get_latest_blockhash().await.unwrap();
-    let mut all_signers = vec![payer];
-    all_signers.extend(signers);
-    let tx = Transaction::new_signed_with_payer(
-        &instructions,
-        Some(&payer.pubkey()),
-        &all_signers,
-        recent_blockhash,
-    );
+    let tx = signed_transaction(banks_client, payer, &instructions, signers).await;
     let err = banks_client.process_transaction(tx).await.unwrap_err();
     match err {
-        TransportError::TransactionError(tx_err) => match tx_err {
+        BanksClientError::TransactionError(tx_err)
+        | BanksClientError::SimulationError { err: tx_err, .. } => match tx_err {
             TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
-                assert_eq!(code, expected as u32);
+                assert_eq!(code, u32::from(expected));
             }
             _ => panic!("unexpected transaction error: {:?}", tx_err),
         },
@@ -136,52 +182,30 @@ async fn expect_cv_error(
     }
 }
 
-async fn expect_anchor_account_constraint_has_one(
-    banks_client: &mut solana_program_test::BanksClient,
-    payer: &Keypair,
-    instructions: Vec<Instruction>,
-    signers: Vec<&Keypair>,
-) {
-    let recent_blockhash = banks_client.get_latest_blockhash().await.unwrap();
-    let mut all_signers = vec![payer];
-    all_signers.extend(signers);
-    let tx = Transaction::new_signed_with_payer(
-        &instructions,
-        Some(&payer.pubkey()),
-        &all_signers,
-        recent_blockhash,
-    );
-    let err = banks_client.process_transaction(tx).await.unwrap_err();
-    match err {
-        TransportError::TransactionError(tx_err) => match tx_err {
-            TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
-                assert_eq!(code, AnchorErrorCode::AccountConstraintHasOne as u32);
-            }
-            _ => panic!("unexpected transaction error: {:?}", tx_err),
-        },
-        _ => panic!("expected transaction failure, got {:?}", err),
+fn default_cv_params() -> cv_instruction::InitializeCvConfig {
+    cv_instruction::InitializeCvConfig {
+        decay_rate: 500_000,
+        max_ratio: 750_000,
+        weight_exponent: 1_000_000,
+        min_threshold: 200_000,
+        proposal_expiry_slots: 0,
+        signaling_threshold: 250_000,
+        stream_duration_slots: 0,
+        proposal_deposit: 0,
+        time_base: TimeBase::Slots,
     }
 }
 
-async fn setup_conviction_env() -> (
-    solana_program_test::BanksClient,
-    Keypair,
-    Pubkey,
-    Pubkey,
-    Pubkey,
-    Pubkey,
-) {
-    let mut program = ProgramTest::new(
-        "commons_conviction_voting",
-        CV_ID,
-        processor!(commons_conviction_voting::entry),
-    );
-    let (mut banks_client, payer, _) = program.start().await;
-
-    let commons_token_mint = create_mint(&mut banks_client, &payer, &payer.pubkey()).await;
-    let commons_treasury =
-        create_token_account(&mut banks_client, &payer, &payer.pubkey(), &commons_token_mint).await;
+async fn initialize_cv(
+    banks_client: &mut solana_program_test::BanksClient,
+    payer: &Keypair,
+    params: cv_instruction::InitializeCvConfig,
+) -> (Pubkey, Pubkey, Pubkey, Pubkey) {
+    let commons_token_mint = create_mint(banks_client, payer, &payer.pubkey()).await;
     let cv_config = Pubkey::find_program_address(&[b"cv_config"], &CV_ID).0;
+    // The treasury must be controlled by the CV config PDA for payouts to sign
+    let commons_treasury =
+        create_token_account(banks_client, payer, &cv_config, &commons_token_mint).await;
     let staking_vault =
         Pubkey::find_program_address(&[b"staking_vault", cv_config.as_ref()], &CV_ID).0;
 
@@ -198,17 +222,67 @@ async fn setup_conviction_env() -> (
     let init_ix = Instruction {
         program_id: CV_ID,
         accounts: init_accounts.to_account_metas(None),
-        data: cv_instruction::InitializeCvConfig {
-            decay_rate: 500_000,
-            max_ratio: 750_000,
-            weight_exponent: 1_000_000,
-            min_threshold: 200_000,
-        }
-        .data(),
+        data: params.data(),
     };
-    process_transaction(&mut banks_client, &payer, vec![init_ix], vec![]).await;
+    process_transaction(banks_client, payer, vec![init_ix], vec![]).await;
+
+    (
+        commons_token_mint,
+        commons_treasury,
+        cv_config,
+        staking_vault,
+    )
+}
+
+// Anchor's `entry` ties the account slice to the accounts' own lifetime,
+// which `processor!` cannot express, so the shim leaks a copy of the slice
+fn cv_processor(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    commons_conviction_voting::entry(program_id, accounts, data)
+}
+
+fn conviction_program_test() -> ProgramTest {
+    ProgramTest::new("commons_conviction_voting", CV_ID, processor!(cv_processor))
+}
+
+async fn setup_conviction_env() -> (
+    solana_program_test::BanksClient,
+    Keypair,
+    Pubkey,
+    Pubkey,
+    Pubkey,
+    Pubkey,
+) {
+    let (mut banks_client, payer, _) = conviction_program_test().start().await;
+    let (commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        initialize_cv(&mut banks_client, &payer, default_cv_params()).await;
+
+    (
+        banks_client,
+        payer,
+        commons_token_mint,
+        commons_treasury,
+        cv_config,
+        staking_vault,
+    )
+}
+
+/// Like `setup_conviction_env`, but keeps the `ProgramTestContext` so tests can warp the clock.
+async fn setup_conviction_context(
+    params: cv_instruction::InitializeCvConfig,
+) -> (ProgramTestContext, Pubkey, Pubkey, Pubkey, Pubkey) {
+    let mut context = conviction_program_test().start_with_context().await;
+    let payer = context.payer.insecure_clone();
+    let (commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        initialize_cv(&mut context.banks_client, &payer, params).await;
 
-    (banks_client, payer, commons_token_mint, commons_treasury, cv_config, staking_vault)
+    (
+        context,
+        commons_token_mint,
+        commons_treasury,
+        cv_config,
+        staking_vault,
+    )
 }
 
 #[tokio::test]
@@ -220,66 +294,56 @@ async fn check_and_execute_requires_threshold() {
     let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
     process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
 
-    let commons_token_mint = create_mint(&mut banks_client, &payer, &payer.pubkey()).await;
-    let commons_treasury =
-        create_token_account(&mut banks_client, &payer, &payer.pubkey(), &commons_token_mint).await;
-    let authority_account = Pubkey::find_program_address(&[b"cv_config"], &CV_ID).0;
-    let staking_vault = Pubkey::find_program_address(&[b"staking_vault", authority_account.as_ref()], &CV_ID).0;
-
-    let init_accounts = cv_accounts::InitializeCvConfig {
-        cv_config: authority_account,
-        commons_treasury,
-        commons_token_mint,
-        staking_vault,
-        authority: payer.pubkey(),
-        system_program: system_program::ID,
-        token_program: spl_token::id(),
-        rent: sysvar::rent::ID,
-    };
-    let init_ix = Instruction {
-        program_id: CV_ID,
-        accounts: init_accounts.to_account_metas(None),
-        data: cv_instruction::InitializeCvConfig {
-            decay_rate: 500_000,
-            max_ratio: 750_000,
-            weight_exponent: 1_000_000,
-            min_threshold: 200_000,
-        }
-        .data(),
-    };
-    process_transaction(&mut banks_client, &payer, vec![init_ix], vec![]).await;
-
+    let recipient_token_account = create_token_account(
+        &mut banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
     let proposal = Pubkey::find_program_address(
-        &[b"proposal", payer.pubkey().as_ref(), &200_000u64.to_le_bytes()],
+        &[b"proposal", cv_config.as_ref(), &0u64.to_le_bytes()],
         &CV_ID,
     )
     .0;
     let create_proposal_accounts = cv_accounts::CreateProposal {
         proposal,
+        cv_config,
+        beneficiary: Some(recipient_token_account),
+        commons_token_mint,
+        creator_token_account: None,
+        deposit_escrow: None,
         authority: payer.pubkey(),
         system_program: system_program::ID,
+        token_program: spl_token::id(),
         clock: sysvar::clock::ID,
     };
     let create_proposal_ix = Instruction {
         program_id: CV_ID,
         accounts: create_proposal_accounts.to_account_metas(None),
         data: cv_instruction::CreateProposal {
-            requested_amount: 200_000,
-            metadata_hash: "test".to_string(),
+            requested_amount: 50_000,
+            metadata_uri: "ipfs://test".to_string(),
+            category: String::new(),
         }
         .data(),
     };
     process_transaction(&mut banks_client, &payer, vec![create_proposal_ix], vec![]).await;
 
-    let user_commons_account =
-        create_token_account(&mut banks_client, &payer, &user.pubkey(), &commons_token_mint).await;
+    let user_commons_account = create_token_account(
+        &mut banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
     mint_to_account(
         &mut banks_client,
         &payer,
         &commons_token_mint,
         &user_commons_account,
         &payer,
-        10_000,
+        100_000,
     )
     .await;
 
@@ -292,6 +356,15 @@ async fn check_and_execute_requires_threshold() {
         stake_account,
         cv_config,
         proposal,
+        commons_treasury,
+        treasury_registry: None,
+        abstain_proposal: None,
+        voter_account: Pubkey::find_program_address(
+            &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+            &CV_ID,
+        )
+        .0,
+        personhood: None,
         commons_token_mint,
         user_commons_token_account: user_commons_account,
         staking_vault,
@@ -307,8 +380,28 @@ async fn check_and_execute_requires_threshold() {
     };
     process_transaction(&mut banks_client, &user, vec![stake_ix], vec![&user]).await;
 
-    let recipient_token_account =
-        create_token_account(&mut banks_client, &payer, &payer.pubkey(), &commons_token_mint).await;
+    // Stake elsewhere grows the supply the threshold is measured against
+    let other_proposal = create_proposal_with_id(
+        &mut banks_client,
+        &payer,
+        cv_config,
+        recipient_token_account,
+        1,
+        1_000,
+    )
+    .await;
+    stake_on_proposal(
+        &mut banks_client,
+        &user,
+        cv_config,
+        other_proposal,
+        commons_token_mint,
+        user_commons_account,
+        staking_vault,
+        90_000,
+    )
+    .await;
+
     mint_to_account(
         &mut banks_client,
         &payer,
@@ -323,8 +416,9 @@ async fn check_and_execute_requires_threshold() {
         cv_config,
         proposal,
         commons_treasury,
-        recipient_token_account,
-        authority: payer.pubkey(),
+        treasury_registry: None,
+        beneficiary: Some(recipient_token_account),
+        abstain_proposal: None,
         token_program: spl_token::id(),
         clock: sysvar::clock::ID,
     };
@@ -338,14 +432,14 @@ async fn check_and_execute_requires_threshold() {
         &mut banks_client,
         &payer,
         vec![check_ix],
-        vec![&payer],
+        vec![],
         CustomError::ThresholdNotReached,
     )
     .await;
 }
 
 #[tokio::test]
-async fn check_and_execute_transfers_treasury_amount_with_authorized_key() {
+async fn check_and_execute_pays_bound_beneficiary_without_authority() {
     let (mut banks_client, payer, commons_token_mint, commons_treasury, cv_config, staking_vault) =
         setup_conviction_env().await;
     let user = Keypair::new();
@@ -353,15 +447,28 @@ async fn check_and_execute_transfers_treasury_amount_with_authorized_key() {
     let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
     process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
 
+    let recipient_token_account = create_token_account(
+        &mut banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
     let proposal = Pubkey::find_program_address(
-        &[b"proposal", payer.pubkey().as_ref(), &40_000u64.to_le_bytes()],
+        &[b"proposal", cv_config.as_ref(), &0u64.to_le_bytes()],
         &CV_ID,
     )
     .0;
     let create_proposal_accounts = cv_accounts::CreateProposal {
         proposal,
+        cv_config,
+        beneficiary: Some(recipient_token_account),
+        commons_token_mint,
+        creator_token_account: None,
+        deposit_escrow: None,
         authority: payer.pubkey(),
         system_program: system_program::ID,
+        token_program: spl_token::id(),
         clock: sysvar::clock::ID,
     };
     let create_proposal_ix = Instruction {
@@ -369,14 +476,20 @@ async fn check_and_execute_transfers_treasury_amount_with_authorized_key() {
         accounts: create_proposal_accounts.to_account_metas(None),
         data: cv_instruction::CreateProposal {
             requested_amount: 40_000,
-            metadata_hash: "success".to_string(),
+            metadata_uri: "ipfs://success".to_string(),
+            category: String::new(),
         }
         .data(),
     };
     process_transaction(&mut banks_client, &payer, vec![create_proposal_ix], vec![]).await;
 
-    let user_commons_account =
-        create_token_account(&mut banks_client, &payer, &user.pubkey(), &commons_token_mint).await;
+    let user_commons_account = create_token_account(
+        &mut banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
     mint_to_account(
         &mut banks_client,
         &payer,
@@ -396,6 +509,15 @@ async fn check_and_execute_transfers_treasury_amount_with_authorized_key() {
         stake_account,
         cv_config,
         proposal,
+        commons_treasury,
+        treasury_registry: None,
+        abstain_proposal: None,
+        voter_account: Pubkey::find_program_address(
+            &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+            &CV_ID,
+        )
+        .0,
+        personhood: None,
         commons_token_mint,
         user_commons_token_account: user_commons_account,
         staking_vault,
@@ -411,8 +533,6 @@ async fn check_and_execute_transfers_treasury_amount_with_authorized_key() {
     };
     process_transaction(&mut banks_client, &user, vec![stake_ix], vec![&user]).await;
 
-    let recipient_token_account =
-        create_token_account(&mut banks_client, &payer, &payer.pubkey(), &commons_token_mint).await;
     mint_to_account(
         &mut banks_client,
         &payer,
@@ -427,8 +547,9 @@ async fn check_and_execute_transfers_treasury_amount_with_authorized_key() {
         cv_config,
         proposal,
         commons_treasury,
-        recipient_token_account,
-        authority: payer.pubkey(),
+        treasury_registry: None,
+        beneficiary: Some(recipient_token_account),
+        abstain_proposal: None,
         token_program: spl_token::id(),
         clock: sysvar::clock::ID,
     };
@@ -438,7 +559,7 @@ async fn check_and_execute_transfers_treasury_amount_with_authorized_key() {
         data: cv_instruction::CheckAndExecute {}.data(),
     };
 
-    process_transaction(&mut banks_client, &payer, vec![check_ix], vec![&payer]).await;
+    process_transaction(&mut banks_client, &user, vec![check_ix], vec![]).await;
 
     let treasury_account = banks_client
         .get_account(commons_treasury)
@@ -467,7 +588,7 @@ async fn check_and_execute_transfers_treasury_amount_with_authorized_key() {
 }
 
 #[tokio::test]
-async fn check_and_execute_requires_authorized_signer() {
+async fn check_and_execute_rejects_redirected_recipient() {
     let (mut banks_client, payer, commons_token_mint, commons_treasury, cv_config, staking_vault) =
         setup_conviction_env().await;
     let user = Keypair::new();
@@ -475,15 +596,28 @@ async fn check_and_execute_requires_authorized_signer() {
     let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
     process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
 
+    let recipient_token_account = create_token_account(
+        &mut banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
     let proposal = Pubkey::find_program_address(
-        &[b"proposal", payer.pubkey().as_ref(), &50_000u64.to_le_bytes()],
+        &[b"proposal", cv_config.as_ref(), &0u64.to_le_bytes()],
         &CV_ID,
     )
     .0;
     let create_proposal_accounts = cv_accounts::CreateProposal {
         proposal,
+        cv_config,
+        beneficiary: Some(recipient_token_account),
+        commons_token_mint,
+        creator_token_account: None,
+        deposit_escrow: None,
         authority: payer.pubkey(),
         system_program: system_program::ID,
+        token_program: spl_token::id(),
         clock: sysvar::clock::ID,
     };
     let create_proposal_ix = Instruction {
@@ -491,14 +625,20 @@ async fn check_and_execute_requires_authorized_signer() {
         accounts: create_proposal_accounts.to_account_metas(None),
         data: cv_instruction::CreateProposal {
             requested_amount: 50_000,
-            metadata_hash: "secure".to_string(),
+            metadata_uri: "ipfs://secure".to_string(),
+            category: String::new(),
         }
         .data(),
     };
     process_transaction(&mut banks_client, &payer, vec![create_proposal_ix], vec![]).await;
 
-    let user_commons_account =
-        create_token_account(&mut banks_client, &payer, &user.pubkey(), &commons_token_mint).await;
+    let user_commons_account = create_token_account(
+        &mut banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
     mint_to_account(
         &mut banks_client,
         &payer,
@@ -518,6 +658,15 @@ async fn check_and_execute_requires_authorized_signer() {
         stake_account,
         cv_config,
         proposal,
+        commons_treasury,
+        treasury_registry: None,
+        abstain_proposal: None,
+        voter_account: Pubkey::find_program_address(
+            &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+            &CV_ID,
+        )
+        .0,
+        personhood: None,
         commons_token_mint,
         user_commons_token_account: user_commons_account,
         staking_vault,
@@ -533,8 +682,6 @@ async fn check_and_execute_requires_authorized_signer() {
     };
     process_transaction(&mut banks_client, &user, vec![stake_ix], vec![&user]).await;
 
-    let recipient_token_account =
-        create_token_account(&mut banks_client, &payer, &payer.pubkey(), &commons_token_mint).await;
     mint_to_account(
         &mut banks_client,
         &payer,
@@ -545,13 +692,21 @@ async fn check_and_execute_requires_authorized_signer() {
     )
     .await;
 
-    let wrong_authority = Keypair::new();
+    let attacker = Keypair::new();
+    let attacker_token_account = create_token_account(
+        &mut banks_client,
+        &payer,
+        &attacker.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
     let check_accounts = cv_accounts::CheckAndExecute {
         cv_config,
         proposal,
         commons_treasury,
-        recipient_token_account,
-        authority: wrong_authority.pubkey(),
+        treasury_registry: None,
+        beneficiary: Some(attacker_token_account),
+        abstain_proposal: None,
         token_program: spl_token::id(),
         clock: sysvar::clock::ID,
     };
@@ -561,11 +716,4612 @@ async fn check_and_execute_requires_authorized_signer() {
         data: cv_instruction::CheckAndExecute {}.data(),
     };
 
-    expect_anchor_account_constraint_has_one(
+    expect_cv_error(
         &mut banks_client,
         &payer,
         vec![check_ix],
-        vec![&wrong_authority],
+        vec![],
+        CustomError::BeneficiaryMismatch,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn create_proposal_assigns_sequential_ids() {
+    let (mut banks_client, payer, commons_token_mint, _commons_treasury, cv_config, _staking_vault) =
+        setup_conviction_env().await;
+    let beneficiary = create_token_account(
+        &mut banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
     )
     .await;
+    let metadata_uri =
+        "ipfs://bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi".to_string();
+
+    let mut proposals = Vec::new();
+    for id in 0u64..2 {
+        let proposal = Pubkey::find_program_address(
+            &[b"proposal", cv_config.as_ref(), &id.to_le_bytes()],
+            &CV_ID,
+        )
+        .0;
+        let create_proposal_accounts = cv_accounts::CreateProposal {
+            proposal,
+            cv_config,
+            beneficiary: Some(beneficiary),
+            commons_token_mint,
+            creator_token_account: None,
+            deposit_escrow: None,
+            authority: payer.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        };
+        // Same creator and same amount twice: ids keep the PDAs distinct.
+        let create_proposal_ix = Instruction {
+            program_id: CV_ID,
+            accounts: create_proposal_accounts.to_account_metas(None),
+            data: cv_instruction::CreateProposal {
+                requested_amount: 10_000,
+                metadata_uri: metadata_uri.clone(),
+                category: String::new(),
+            }
+            .data(),
+        };
+        process_transaction(&mut banks_client, &payer, vec![create_proposal_ix], vec![]).await;
+        proposals.push(proposal);
+    }
+
+    for (id, proposal) in proposals.into_iter().enumerate() {
+        let proposal_account = banks_client
+            .get_account(proposal)
+            .await
+            .unwrap()
+            .expect("proposal missing");
+        let mut proposal_data: &[u8] = &proposal_account.data;
+        let proposal_state = Proposal::try_deserialize(&mut proposal_data).unwrap();
+        assert_eq!(proposal_state.id, id as u64);
+        assert_eq!(proposal_state.metadata_uri, metadata_uri);
+    }
+
+    let config_account = banks_client
+        .get_account(cv_config)
+        .await
+        .unwrap()
+        .expect("cv config missing");
+    let mut config_data: &[u8] = &config_account.data;
+    let config_state = CVConfig::try_deserialize(&mut config_data).unwrap();
+    assert_eq!(config_state.proposal_count, 2);
+}
+
+async fn create_proposal_with_id(
+    banks_client: &mut solana_program_test::BanksClient,
+    payer: &Keypair,
+    cv_config: Pubkey,
+    beneficiary: Pubkey,
+    id: u64,
+    requested_amount: u64,
+) -> Pubkey {
+    create_proposal_in_category(
+        banks_client,
+        payer,
+        cv_config,
+        beneficiary,
+        id,
+        requested_amount,
+        "",
+    )
+    .await
+}
+
+async fn create_proposal_in_category(
+    banks_client: &mut solana_program_test::BanksClient,
+    payer: &Keypair,
+    cv_config: Pubkey,
+    beneficiary: Pubkey,
+    id: u64,
+    requested_amount: u64,
+    category: &str,
+) -> Pubkey {
+    let commons_token_mint = fetch_cv_config(banks_client, cv_config)
+        .await
+        .commons_token_mint;
+    let proposal = Pubkey::find_program_address(
+        &[b"proposal", cv_config.as_ref(), &id.to_le_bytes()],
+        &CV_ID,
+    )
+    .0;
+    let create_proposal_accounts = cv_accounts::CreateProposal {
+        proposal,
+        cv_config,
+        beneficiary: Some(beneficiary),
+        commons_token_mint,
+        creator_token_account: None,
+        deposit_escrow: None,
+        authority: payer.pubkey(),
+        system_program: system_program::ID,
+        token_program: spl_token::id(),
+        clock: sysvar::clock::ID,
+    };
+    let create_proposal_ix = Instruction {
+        program_id: CV_ID,
+        accounts: create_proposal_accounts.to_account_metas(None),
+        data: cv_instruction::CreateProposal {
+            requested_amount,
+            metadata_uri: format!("ipfs://proposal-{id}"),
+            category: category.to_string(),
+        }
+        .data(),
+    };
+    process_transaction(banks_client, payer, vec![create_proposal_ix], vec![]).await;
+    proposal
+}
+
+async fn fetch_cv_config(
+    banks_client: &mut solana_program_test::BanksClient,
+    cv_config: Pubkey,
+) -> CVConfig {
+    let config_account = banks_client
+        .get_account(cv_config)
+        .await
+        .unwrap()
+        .expect("cv config missing");
+    let mut config_data: &[u8] = &config_account.data;
+    CVConfig::try_deserialize(&mut config_data).unwrap()
+}
+
+async fn fetch_proposal(
+    banks_client: &mut solana_program_test::BanksClient,
+    proposal: Pubkey,
+) -> Proposal {
+    let proposal_account = banks_client
+        .get_account(proposal)
+        .await
+        .unwrap()
+        .expect("proposal missing");
+    let mut proposal_data: &[u8] = &proposal_account.data;
+    Proposal::try_deserialize(&mut proposal_data).unwrap()
+}
+
+#[tokio::test]
+async fn reallocate_support_splits_one_deposit_across_proposals() {
+    let (mut banks_client, payer, commons_token_mint, _commons_treasury, cv_config, staking_vault) =
+        setup_conviction_env().await;
+    let user = Keypair::new();
+
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
+
+    let beneficiary = create_token_account(
+        &mut banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    let first =
+        create_proposal_with_id(&mut banks_client, &payer, cv_config, beneficiary, 0, 10_000).await;
+    let second =
+        create_proposal_with_id(&mut banks_client, &payer, cv_config, beneficiary, 1, 20_000).await;
+
+    let user_commons_account = create_token_account(
+        &mut banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        1_000,
+    )
+    .await;
+
+    let voter_account = Pubkey::find_program_address(
+        &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+        &CV_ID,
+    )
+    .0;
+    let deposit_accounts = cv_accounts::DepositTokens {
+        cv_config,
+        voter_account,
+        personhood: None,
+        commons_token_mint,
+        user_commons_token_account: user_commons_account,
+        staking_vault,
+        authority: user.pubkey(),
+        system_program: system_program::ID,
+        token_program: spl_token::id(),
+    };
+    let deposit_ix = Instruction {
+        program_id: CV_ID,
+        accounts: deposit_accounts.to_account_metas(None),
+        data: cv_instruction::DepositTokens { amount: 1_000 }.data(),
+    };
+    process_transaction(&mut banks_client, &user, vec![deposit_ix], vec![&user]).await;
+
+    let reallocate_ix = |allocations: Vec<SupportAllocation>| {
+        let mut accounts = cv_accounts::ReallocateSupport {
+            cv_config,
+            voter_account,
+            authority: user.pubkey(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None);
+        accounts.extend(allocations.iter().flat_map(|allocation| {
+            let stake = Pubkey::find_program_address(
+                &[
+                    b"stake",
+                    user.pubkey().as_ref(),
+                    allocation.proposal.as_ref(),
+                ],
+                &CV_ID,
+            )
+            .0;
+            [
+                AccountMeta::new(allocation.proposal, false),
+                AccountMeta::new(stake, false),
+            ]
+        }));
+        Instruction {
+            program_id: CV_ID,
+            accounts,
+            data: cv_instruction::ReallocateSupport { allocations }.data(),
+        }
+    };
+
+    process_transaction(
+        &mut banks_client,
+        &user,
+        vec![reallocate_ix(vec![
+            SupportAllocation {
+                proposal: first,
+                amount: 600,
+            },
+            SupportAllocation {
+                proposal: second,
+                amount: 400,
+            },
+        ])],
+        vec![&user],
+    )
+    .await;
+    assert_eq!(
+        fetch_proposal(&mut banks_client, first)
+            .await
+            .current_conviction,
+        600
+    );
+    assert_eq!(
+        fetch_proposal(&mut banks_client, second)
+            .await
+            .current_conviction,
+        400
+    );
+
+    // Allocations beyond the single deposit are rejected.
+    expect_cv_error(
+        &mut banks_client,
+        &user,
+        vec![reallocate_ix(vec![SupportAllocation {
+            proposal: second,
+            amount: 500,
+        }])],
+        vec![&user],
+        CustomError::InsufficientUnallocatedDeposit,
+    )
+    .await;
+
+    // Moving support off one proposal frees it for the other in the same instruction.
+    process_transaction(
+        &mut banks_client,
+        &user,
+        vec![reallocate_ix(vec![
+            SupportAllocation {
+                proposal: first,
+                amount: 0,
+            },
+            SupportAllocation {
+                proposal: second,
+                amount: 1_000,
+            },
+        ])],
+        vec![&user],
+    )
+    .await;
+    assert!(
+        fetch_proposal(&mut banks_client, first)
+            .await
+            .current_conviction
+            < 600
+    );
+    assert!(
+        fetch_proposal(&mut banks_client, second)
+            .await
+            .current_conviction
+            > 400
+    );
+
+    let voter_state = banks_client
+        .get_account(voter_account)
+        .await
+        .unwrap()
+        .expect("voter account missing");
+    let mut voter_data: &[u8] = &voter_state.data;
+    let voter_state = VoterAccount::try_deserialize(&mut voter_data).unwrap();
+    assert_eq!(voter_state.deposited_amount, 1_000);
+    assert_eq!(voter_state.allocations.len(), 1);
+    assert_eq!(voter_state.allocation_for(&second), 1_000);
+}
+
+#[tokio::test]
+async fn cancelled_proposal_releases_stakes() {
+    let (mut banks_client, payer, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_env().await;
+    let user = Keypair::new();
+
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
+
+    let beneficiary = create_token_account(
+        &mut banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    let proposal =
+        create_proposal_with_id(&mut banks_client, &payer, cv_config, beneficiary, 0, 10_000).await;
+
+    let user_commons_account = create_token_account(
+        &mut banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        5_000,
+    )
+    .await;
+
+    let stake_account = Pubkey::find_program_address(
+        &[b"stake", user.pubkey().as_ref(), proposal.as_ref()],
+        &CV_ID,
+    )
+    .0;
+    let stake_accounts = cv_accounts::StakeTokens {
+        stake_account,
+        cv_config,
+        proposal,
+        commons_treasury,
+        treasury_registry: None,
+        abstain_proposal: None,
+        voter_account: Pubkey::find_program_address(
+            &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+            &CV_ID,
+        )
+        .0,
+        personhood: None,
+        commons_token_mint,
+        user_commons_token_account: user_commons_account,
+        staking_vault,
+        authority: user.pubkey(),
+        system_program: system_program::ID,
+        token_program: spl_token::id(),
+        clock: sysvar::clock::ID,
+    };
+    let stake_ix = Instruction {
+        program_id: CV_ID,
+        accounts: stake_accounts.to_account_metas(None),
+        data: cv_instruction::StakeTokens { amount: 5_000 }.data(),
+    };
+    process_transaction(&mut banks_client, &user, vec![stake_ix], vec![&user]).await;
+
+    // Only the creator may cancel.
+    let cancel_ix = |creator: Pubkey| Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CancelProposal {
+            cv_config,
+            proposal,
+            creator,
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CancelProposal {}.data(),
+    };
+    let tx = signed_transaction(
+        &mut banks_client,
+        &user,
+        &[cancel_ix(user.pubkey())],
+        vec![],
+    )
+    .await;
+    let err = banks_client.process_transaction(tx).await.unwrap_err();
+    match err {
+        BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) => assert_eq!(code, AnchorErrorCode::ConstraintHasOne as u32),
+        _ => panic!("expected has_one failure, got {:?}", err),
+    }
+
+    process_transaction(
+        &mut banks_client,
+        &payer,
+        vec![cancel_ix(payer.pubkey())],
+        vec![],
+    )
+    .await;
+    assert_eq!(
+        fetch_proposal(&mut banks_client, proposal).await.status,
+        ProposalStatus::Cancelled
+    );
+
+    let withdraw_accounts = cv_accounts::WithdrawStake {
+        stake_account,
+        cv_config,
+        proposal,
+        commons_treasury,
+        treasury_registry: None,
+        abstain_proposal: None,
+        voter_account: Pubkey::find_program_address(
+            &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+            &CV_ID,
+        )
+        .0,
+        user_commons_token_account: user_commons_account,
+        commons_token_mint,
+        staking_vault,
+        authority: user.pubkey(),
+        system_program: system_program::ID,
+        token_program: spl_token::id(),
+        clock: sysvar::clock::ID,
+    };
+    let withdraw_ix = Instruction {
+        program_id: CV_ID,
+        accounts: withdraw_accounts.to_account_metas(None),
+        data: cv_instruction::WithdrawStake {}.data(),
+    };
+    process_transaction(&mut banks_client, &user, vec![withdraw_ix], vec![&user]).await;
+
+    let user_account = banks_client
+        .get_account(user_commons_account)
+        .await
+        .unwrap()
+        .expect("user token account missing");
+    let user_state = TokenAccountState::unpack(&user_account.data).unwrap();
+    assert_eq!(user_state.amount, 5_000);
+}
+
+#[tokio::test]
+async fn reject_proposal_requires_cv_authority() {
+    let (mut banks_client, payer, commons_token_mint, _commons_treasury, cv_config, _staking_vault) =
+        setup_conviction_env().await;
+    let beneficiary = create_token_account(
+        &mut banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    let proposal =
+        create_proposal_with_id(&mut banks_client, &payer, cv_config, beneficiary, 0, 10_000).await;
+
+    let outsider = Keypair::new();
+    let reject_ix = |authority: Pubkey| Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::RejectProposal {
+            cv_config,
+            proposal,
+            authority,
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::RejectProposal {}.data(),
+    };
+    let tx = signed_transaction(
+        &mut banks_client,
+        &payer,
+        &[reject_ix(outsider.pubkey())],
+        vec![&outsider],
+    )
+    .await;
+    assert!(banks_client.process_transaction(tx).await.is_err());
+
+    process_transaction(
+        &mut banks_client,
+        &payer,
+        vec![reject_ix(payer.pubkey())],
+        vec![],
+    )
+    .await;
+    assert_eq!(
+        fetch_proposal(&mut banks_client, proposal).await.status,
+        ProposalStatus::Rejected
+    );
+}
+
+#[tokio::test]
+async fn abstain_proposal_cannot_be_cancelled_rejected_or_closed() {
+    let (
+        mut banks_client,
+        payer,
+        _commons_token_mint,
+        _commons_treasury,
+        cv_config,
+        _staking_vault,
+    ) = setup_conviction_env().await;
+    let abstain_proposal = Pubkey::find_program_address(
+        &[b"proposal", cv_config.as_ref(), &0u64.to_le_bytes()],
+        &CV_ID,
+    )
+    .0;
+    let create_abstain_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CreateAbstainProposal {
+            cv_config,
+            proposal: abstain_proposal,
+            authority: payer.pubkey(),
+            system_program: system_program::ID,
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CreateAbstainProposal {}.data(),
+    };
+    process_transaction(&mut banks_client, &payer, vec![create_abstain_ix], vec![]).await;
+
+    let cancel_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CancelProposal {
+            cv_config,
+            proposal: abstain_proposal,
+            creator: payer.pubkey(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CancelProposal {}.data(),
+    };
+    let reject_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::RejectProposal {
+            cv_config,
+            proposal: abstain_proposal,
+            authority: payer.pubkey(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::RejectProposal {}.data(),
+    };
+    let close_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CloseProposal {
+            cv_config,
+            proposal: abstain_proposal,
+            creator: payer.pubkey(),
+            proposal_instructions: Pubkey::find_program_address(
+                &[b"proposal_instructions", abstain_proposal.as_ref()],
+                &CV_ID,
+            )
+            .0,
+            funding_stream: Pubkey::find_program_address(
+                &[b"funding_stream", abstain_proposal.as_ref()],
+                &CV_ID,
+            )
+            .0,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CloseProposal {}.data(),
+    };
+    for ix in [cancel_ix, reject_ix, close_ix] {
+        expect_cv_error(
+            &mut banks_client,
+            &payer,
+            vec![ix],
+            vec![],
+            CustomError::AbstainProposalPermanent,
+        )
+        .await;
+    }
+    assert_eq!(
+        fetch_proposal(&mut banks_client, abstain_proposal)
+            .await
+            .status,
+        ProposalStatus::Pending
+    );
+}
+
+#[tokio::test]
+async fn signaling_proposal_is_approved_without_transfer() {
+    let (mut banks_client, payer, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_env().await;
+    let user = Keypair::new();
+
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
+
+    let abstain_proposal = Pubkey::find_program_address(
+        &[b"proposal", cv_config.as_ref(), &0u64.to_le_bytes()],
+        &CV_ID,
+    )
+    .0;
+    let create_abstain_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CreateAbstainProposal {
+            cv_config,
+            proposal: abstain_proposal,
+            authority: payer.pubkey(),
+            system_program: system_program::ID,
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CreateAbstainProposal {}.data(),
+    };
+    process_transaction(&mut banks_client, &payer, vec![create_abstain_ix], vec![]).await;
+
+    // Signaling proposals carry no beneficiary and no requested amount.
+    let proposal = Pubkey::find_program_address(
+        &[b"proposal", cv_config.as_ref(), &1u64.to_le_bytes()],
+        &CV_ID,
+    )
+    .0;
+    let create_proposal_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CreateProposal {
+            proposal,
+            cv_config,
+            beneficiary: None,
+            commons_token_mint,
+            creator_token_account: None,
+            deposit_escrow: None,
+            authority: payer.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CreateProposal {
+            requested_amount: 0,
+            metadata_uri: "ipfs://next-grant-round".to_string(),
+            category: String::new(),
+        }
+        .data(),
+    };
+    process_transaction(&mut banks_client, &payer, vec![create_proposal_ix], vec![]).await;
+
+    let user_commons_account = create_token_account(
+        &mut banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        1_000,
+    )
+    .await;
+    let stake_ix = |target: Pubkey, amount: u64| Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::StakeTokens {
+            stake_account: Pubkey::find_program_address(
+                &[b"stake", user.pubkey().as_ref(), target.as_ref()],
+                &CV_ID,
+            )
+            .0,
+            cv_config,
+            proposal: target,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: Some(abstain_proposal),
+            voter_account: Pubkey::find_program_address(
+                &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                &CV_ID,
+            )
+            .0,
+            personhood: None,
+            commons_token_mint,
+            user_commons_token_account: user_commons_account,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::StakeTokens { amount }.data(),
+    };
+    process_transaction(
+        &mut banks_client,
+        &user,
+        vec![stake_ix(proposal, 300), stake_ix(abstain_proposal, 700)],
+        vec![&user],
+    )
+    .await;
+
+    let check_ix = |abstain: Option<Pubkey>| Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CheckAndExecute {
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            beneficiary: None,
+            abstain_proposal: abstain,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CheckAndExecute {}.data(),
+    };
+
+    // The abstain proposal cannot be left out once it exists.
+    expect_cv_error(
+        &mut banks_client,
+        &payer,
+        vec![check_ix(None)],
+        vec![],
+        CustomError::MissingAbstainProposal,
+    )
+    .await;
+
+    // 300 of (1_000 staked + 700 abstain conviction) is below the 25% bar.
+    expect_cv_error(
+        &mut banks_client,
+        &payer,
+        vec![check_ix(Some(abstain_proposal))],
+        vec![],
+        CustomError::ThresholdNotReached,
+    )
+    .await;
+
+    let unstake_abstain_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::UnstakeTokens {
+            cv_config,
+            proposal: abstain_proposal,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: Some(abstain_proposal),
+            voter_account: Pubkey::find_program_address(
+                &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                &CV_ID,
+            )
+            .0,
+            stake_account: Pubkey::find_program_address(
+                &[b"stake", user.pubkey().as_ref(), abstain_proposal.as_ref()],
+                &CV_ID,
+            )
+            .0,
+            user_commons_token_account: user_commons_account,
+            commons_token_mint,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::UnstakeTokens { amount: 700 }.data(),
+    };
+    process_transaction(
+        &mut banks_client,
+        &user,
+        vec![unstake_abstain_ix],
+        vec![&user],
+    )
+    .await;
+
+    process_transaction(
+        &mut banks_client,
+        &payer,
+        vec![check_ix(Some(abstain_proposal))],
+        vec![],
+    )
+    .await;
+    assert_eq!(
+        fetch_proposal(&mut banks_client, proposal).await.status,
+        ProposalStatus::Approved
+    );
+
+    let treasury_account = banks_client
+        .get_account(commons_treasury)
+        .await
+        .unwrap()
+        .expect("treasury missing");
+    let treasury_state = TokenAccountState::unpack(&treasury_account.data).unwrap();
+    assert_eq!(treasury_state.amount, 0);
+}
+
+#[tokio::test]
+async fn approved_proposal_executes_allowlisted_cpi() {
+    let (mut banks_client, payer, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_env().await;
+    let user = Keypair::new();
+
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
+
+    // A meta-proposal: the config PDA signs for its own parameter update
+    let update_metas = cv_accounts::UpdateCvParams {
+        cv_config,
+        updater: cv_config,
+        clock: sysvar::clock::ID,
+    }
+    .to_account_metas(Some(true));
+    let instructions = vec![ProposalInstruction {
+        program_id: CV_ID,
+        accounts: update_metas
+            .iter()
+            .map(|meta| ProposalAccountMeta {
+                pubkey: meta.pubkey,
+                is_signer: meta.is_signer,
+                is_writable: meta.is_writable,
+            })
+            .collect(),
+        data: cv_instruction::UpdateCvParams {
+            decay_rate: 600_000,
+            max_ratio: 700_000,
+            weight_exponent: 2_000_000,
+            min_threshold: 100_000,
+        }
+        .data(),
+    }];
+
+    let proposal = Pubkey::find_program_address(
+        &[b"proposal", cv_config.as_ref(), &0u64.to_le_bytes()],
+        &CV_ID,
+    )
+    .0;
+    let proposal_instructions =
+        Pubkey::find_program_address(&[b"proposal_instructions", proposal.as_ref()], &CV_ID).0;
+    let create_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CreateExecutableProposal {
+            cv_config,
+            proposal,
+            proposal_instructions,
+            commons_token_mint,
+            creator_token_account: None,
+            deposit_escrow: None,
+            authority: payer.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CreateExecutableProposal {
+            metadata_uri: "ipfs://payload".to_string(),
+            instructions: instructions.clone(),
+        }
+        .data(),
+    };
+
+    // The CV program itself is not yet allowlisted.
+    expect_cv_error(
+        &mut banks_client,
+        &payer,
+        vec![create_ix.clone()],
+        vec![],
+        CustomError::ProgramNotAllowed,
+    )
+    .await;
+
+    let allow_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::SetAllowedPrograms {
+            cv_config,
+            authority: payer.pubkey(),
+        }
+        .to_account_metas(None),
+        data: cv_instruction::SetAllowedPrograms {
+            allowed_programs: vec![CV_ID],
+        }
+        .data(),
+    };
+    process_transaction(&mut banks_client, &payer, vec![allow_ix, create_ix], vec![]).await;
+
+    let user_commons_account = create_token_account(
+        &mut banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        1_000,
+    )
+    .await;
+    let stake_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::StakeTokens {
+            stake_account: Pubkey::find_program_address(
+                &[b"stake", user.pubkey().as_ref(), proposal.as_ref()],
+                &CV_ID,
+            )
+            .0,
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: None,
+            voter_account: Pubkey::find_program_address(
+                &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                &CV_ID,
+            )
+            .0,
+            personhood: None,
+            commons_token_mint,
+            user_commons_token_account: user_commons_account,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::StakeTokens { amount: 1_000 }.data(),
+    };
+    process_transaction(&mut banks_client, &user, vec![stake_ix], vec![&user]).await;
+
+    let execute_ix = {
+        let mut accounts = cv_accounts::ExecuteProposalInstructions {
+            cv_config,
+            proposal,
+            proposal_instructions,
+        }
+        .to_account_metas(None);
+        accounts.push(AccountMeta::new_readonly(sysvar::clock::ID, false));
+        accounts.push(AccountMeta::new_readonly(CV_ID, false));
+        Instruction {
+            program_id: CV_ID,
+            accounts,
+            data: cv_instruction::ExecuteProposalInstructions {}.data(),
+        }
+    };
+    expect_cv_error(
+        &mut banks_client,
+        &payer,
+        vec![execute_ix.clone()],
+        vec![],
+        CustomError::ProposalNotApproved,
+    )
+    .await;
+
+    let check_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CheckAndExecute {
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            beneficiary: None,
+            abstain_proposal: None,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CheckAndExecute {}.data(),
+    };
+    process_transaction(&mut banks_client, &payer, vec![check_ix], vec![]).await;
+    process_transaction(&mut banks_client, &payer, vec![execute_ix.clone()], vec![]).await;
+
+    // The update made through CPI survives the outer instruction
+    let config = fetch_cv_config(&mut banks_client, cv_config).await;
+    assert_eq!(config.decay_rate, 600_000);
+    assert_eq!(config.previous_decay_rate, 500_000);
+    assert_eq!(config.max_ratio, 700_000);
+    assert_eq!(config.weight_exponent, 2_000_000);
+    assert_eq!(config.min_threshold, 100_000);
+
+    let instructions_account = banks_client
+        .get_account(proposal_instructions)
+        .await
+        .unwrap()
+        .expect("proposal instructions missing");
+    let mut instructions_data: &[u8] = &instructions_account.data;
+    let instructions_state = ProposalInstructions::try_deserialize(&mut instructions_data).unwrap();
+    assert!(instructions_state.executed);
+    assert_eq!(instructions_state.instructions, instructions);
+
+    expect_cv_error(
+        &mut banks_client,
+        &payer,
+        vec![execute_ix],
+        vec![],
+        CustomError::InstructionsAlreadyExecuted,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn executable_proposal_cannot_drain_cv_token_accounts() {
+    let (mut banks_client, payer, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_env().await;
+    let user = Keypair::new();
+
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
+
+    mint_to_account(
+        &mut banks_client,
+        &payer,
+        &commons_token_mint,
+        &commons_treasury,
+        &payer,
+        1_000,
+    )
+    .await;
+    let recipient = create_token_account(
+        &mut banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+
+    // The payload tries to move treasury funds past the funding threshold
+    let token_transfer = token_instruction::transfer(
+        &spl_token::id(),
+        &commons_treasury,
+        &recipient,
+        &cv_config,
+        &[],
+        1_000,
+    )
+    .unwrap();
+    let instructions = vec![ProposalInstruction {
+        program_id: token_transfer.program_id,
+        accounts: token_transfer
+            .accounts
+            .iter()
+            .map(|meta| ProposalAccountMeta {
+                pubkey: meta.pubkey,
+                is_signer: meta.is_signer,
+                is_writable: meta.is_writable,
+            })
+            .collect(),
+        data: token_transfer.data.clone(),
+    }];
+
+    let proposal = Pubkey::find_program_address(
+        &[b"proposal", cv_config.as_ref(), &0u64.to_le_bytes()],
+        &CV_ID,
+    )
+    .0;
+    let proposal_instructions =
+        Pubkey::find_program_address(&[b"proposal_instructions", proposal.as_ref()], &CV_ID).0;
+    let allow_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::SetAllowedPrograms {
+            cv_config,
+            authority: payer.pubkey(),
+        }
+        .to_account_metas(None),
+        data: cv_instruction::SetAllowedPrograms {
+            allowed_programs: vec![spl_token::id()],
+        }
+        .data(),
+    };
+    let create_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CreateExecutableProposal {
+            cv_config,
+            proposal,
+            proposal_instructions,
+            commons_token_mint,
+            creator_token_account: None,
+            deposit_escrow: None,
+            authority: payer.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CreateExecutableProposal {
+            metadata_uri: "ipfs://payload".to_string(),
+            instructions,
+        }
+        .data(),
+    };
+    process_transaction(&mut banks_client, &payer, vec![allow_ix, create_ix], vec![]).await;
+
+    let user_commons_account = create_token_account(
+        &mut banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        1_000,
+    )
+    .await;
+    stake_on_proposal(
+        &mut banks_client,
+        &user,
+        cv_config,
+        proposal,
+        commons_token_mint,
+        user_commons_account,
+        staking_vault,
+        1_000,
+    )
+    .await;
+
+    let check_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CheckAndExecute {
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            beneficiary: None,
+            abstain_proposal: None,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CheckAndExecute {}.data(),
+    };
+    process_transaction(&mut banks_client, &payer, vec![check_ix], vec![]).await;
+
+    let mut accounts = cv_accounts::ExecuteProposalInstructions {
+        cv_config,
+        proposal,
+        proposal_instructions,
+    }
+    .to_account_metas(None);
+    accounts.push(AccountMeta::new(commons_treasury, false));
+    accounts.push(AccountMeta::new(recipient, false));
+    accounts.push(AccountMeta::new_readonly(spl_token::id(), false));
+    let execute_ix = Instruction {
+        program_id: CV_ID,
+        accounts,
+        data: cv_instruction::ExecuteProposalInstructions {}.data(),
+    };
+    expect_cv_error(
+        &mut banks_client,
+        &payer,
+        vec![execute_ix],
+        vec![],
+        CustomError::ProtectedTokenAccount,
+    )
+    .await;
+
+    let treasury_account = banks_client
+        .get_account(commons_treasury)
+        .await
+        .unwrap()
+        .expect("treasury missing");
+    let treasury_state = TokenAccountState::unpack(&treasury_account.data).unwrap();
+    assert_eq!(treasury_state.amount, 1_000);
+}
+
+#[tokio::test]
+async fn streamed_grant_vests_linearly_and_can_be_stopped() {
+    let (mut context, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_context(cv_instruction::InitializeCvConfig {
+            stream_duration_slots: 1_000,
+            ..default_cv_params()
+        })
+        .await;
+    let payer = context.payer.insecure_clone();
+    let user = Keypair::new();
+
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut context.banks_client, &payer, vec![transfer], vec![]).await;
+
+    let beneficiary = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &commons_treasury,
+        &payer,
+        100_000,
+    )
+    .await;
+    let proposal = create_proposal_with_id(
+        &mut context.banks_client,
+        &payer,
+        cv_config,
+        beneficiary,
+        0,
+        40_000,
+    )
+    .await;
+
+    let user_commons_account = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        400_000,
+    )
+    .await;
+    let stake_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::StakeTokens {
+            stake_account: Pubkey::find_program_address(
+                &[b"stake", user.pubkey().as_ref(), proposal.as_ref()],
+                &CV_ID,
+            )
+            .0,
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: None,
+            voter_account: Pubkey::find_program_address(
+                &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                &CV_ID,
+            )
+            .0,
+            personhood: None,
+            commons_token_mint,
+            user_commons_token_account: user_commons_account,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::StakeTokens { amount: 400_000 }.data(),
+    };
+    process_transaction(
+        &mut context.banks_client,
+        &user,
+        vec![stake_ix],
+        vec![&user],
+    )
+    .await;
+
+    let check_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CheckAndExecute {
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            beneficiary: Some(beneficiary),
+            abstain_proposal: None,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CheckAndExecute {}.data(),
+    };
+    // Checked in the slot it was staked in, before any conviction decays
+    process_transaction(&mut context.banks_client, &payer, vec![check_ix], vec![]).await;
+    let approved_slot = fetch_proposal(&mut context.banks_client, proposal)
+        .await
+        .decided_at_slot;
+
+    let funding_stream =
+        Pubkey::find_program_address(&[b"funding_stream", proposal.as_ref()], &CV_ID).0;
+    let open_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::OpenFundingStream {
+            proposal,
+            funding_stream,
+            payer: payer.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::OpenFundingStream {}.data(),
+    };
+    process_transaction(&mut context.banks_client, &payer, vec![open_ix], vec![]).await;
+
+    let claim_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::ClaimFundingStream {
+            cv_config,
+            funding_stream,
+            commons_treasury,
+            beneficiary,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::ClaimFundingStream {}.data(),
+    };
+
+    // A quarter of the way through the stream, a quarter has vested.
+    context.warp_to_slot(approved_slot + 250).unwrap();
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![claim_ix.clone()],
+        vec![],
+    )
+    .await;
+    let beneficiary_account = context
+        .banks_client
+        .get_account(beneficiary)
+        .await
+        .unwrap()
+        .expect("beneficiary missing");
+    let beneficiary_state = TokenAccountState::unpack(&beneficiary_account.data).unwrap();
+    assert_eq!(beneficiary_state.amount, 10_000);
+
+    // A "stop funding" proposal is an executable proposal calling back into CV.
+    let allow_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::SetAllowedPrograms {
+            cv_config,
+            authority: payer.pubkey(),
+        }
+        .to_account_metas(None),
+        data: cv_instruction::SetAllowedPrograms {
+            allowed_programs: vec![CV_ID],
+        }
+        .data(),
+    };
+    let cancel_metas = cv_accounts::CancelFundingStream {
+        cv_config,
+        funding_stream,
+        clock: sysvar::clock::ID,
+    }
+    .to_account_metas(Some(true));
+    let stop_proposal = Pubkey::find_program_address(
+        &[b"proposal", cv_config.as_ref(), &1u64.to_le_bytes()],
+        &CV_ID,
+    )
+    .0;
+    let stop_instructions =
+        Pubkey::find_program_address(&[b"proposal_instructions", stop_proposal.as_ref()], &CV_ID).0;
+    let create_stop_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CreateExecutableProposal {
+            cv_config,
+            proposal: stop_proposal,
+            proposal_instructions: stop_instructions,
+            commons_token_mint,
+            creator_token_account: None,
+            deposit_escrow: None,
+            authority: payer.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CreateExecutableProposal {
+            metadata_uri: "ipfs://stop-funding".to_string(),
+            instructions: vec![ProposalInstruction {
+                program_id: CV_ID,
+                accounts: cancel_metas
+                    .iter()
+                    .map(|meta| ProposalAccountMeta {
+                        pubkey: meta.pubkey,
+                        is_signer: meta.is_signer,
+                        is_writable: meta.is_writable,
+                    })
+                    .collect(),
+                data: cv_instruction::CancelFundingStream {}.data(),
+            }],
+        }
+        .data(),
+    };
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![allow_ix, create_stop_ix],
+        vec![],
+    )
+    .await;
+
+    let reallocate_stake_ix = |target: Pubkey, amount: u64, unstake: bool| {
+        let stake_account = Pubkey::find_program_address(
+            &[b"stake", user.pubkey().as_ref(), target.as_ref()],
+            &CV_ID,
+        )
+        .0;
+        if unstake {
+            Instruction {
+                program_id: CV_ID,
+                accounts: cv_accounts::WithdrawStake {
+                    stake_account,
+                    cv_config,
+                    proposal: target,
+                    commons_treasury,
+                    treasury_registry: None,
+                    abstain_proposal: None,
+                    voter_account: Pubkey::find_program_address(
+                        &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                        &CV_ID,
+                    )
+                    .0,
+                    user_commons_token_account: user_commons_account,
+                    commons_token_mint,
+                    staking_vault,
+                    authority: user.pubkey(),
+                    system_program: system_program::ID,
+                    token_program: spl_token::id(),
+                    clock: sysvar::clock::ID,
+                }
+                .to_account_metas(None),
+                data: cv_instruction::WithdrawStake {}.data(),
+            }
+        } else {
+            Instruction {
+                program_id: CV_ID,
+                accounts: cv_accounts::StakeTokens {
+                    stake_account,
+                    cv_config,
+                    proposal: target,
+                    commons_treasury,
+                    treasury_registry: None,
+                    abstain_proposal: None,
+                    voter_account: Pubkey::find_program_address(
+                        &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                        &CV_ID,
+                    )
+                    .0,
+                    personhood: None,
+                    commons_token_mint,
+                    user_commons_token_account: user_commons_account,
+                    staking_vault,
+                    authority: user.pubkey(),
+                    system_program: system_program::ID,
+                    token_program: spl_token::id(),
+                    clock: sysvar::clock::ID,
+                }
+                .to_account_metas(None),
+                data: cv_instruction::StakeTokens { amount }.data(),
+            }
+        }
+    };
+    context.warp_to_slot(approved_slot + 500).unwrap();
+    process_transaction(
+        &mut context.banks_client,
+        &user,
+        vec![
+            reallocate_stake_ix(proposal, 0, true),
+            reallocate_stake_ix(stop_proposal, 400_000, false),
+        ],
+        vec![&user],
+    )
+    .await;
+
+    let check_stop_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CheckAndExecute {
+            cv_config,
+            proposal: stop_proposal,
+            commons_treasury,
+            treasury_registry: None,
+            beneficiary: None,
+            abstain_proposal: None,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CheckAndExecute {}.data(),
+    };
+    let execute_stop_ix = {
+        let mut accounts = cv_accounts::ExecuteProposalInstructions {
+            cv_config,
+            proposal: stop_proposal,
+            proposal_instructions: stop_instructions,
+        }
+        .to_account_metas(None);
+        accounts.push(AccountMeta::new(funding_stream, false));
+        accounts.push(AccountMeta::new_readonly(sysvar::clock::ID, false));
+        accounts.push(AccountMeta::new_readonly(CV_ID, false));
+        Instruction {
+            program_id: CV_ID,
+            accounts,
+            data: cv_instruction::ExecuteProposalInstructions {}.data(),
+        }
+    };
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![check_stop_ix, execute_stop_ix],
+        vec![],
+    )
+    .await;
+
+    // Half vested at cancellation; the remaining half is released back to the treasury.
+    context.warp_to_slot(approved_slot + 900).unwrap();
+    process_transaction(&mut context.banks_client, &payer, vec![claim_ix], vec![]).await;
+    let beneficiary_account = context
+        .banks_client
+        .get_account(beneficiary)
+        .await
+        .unwrap()
+        .expect("beneficiary missing");
+    let beneficiary_state = TokenAccountState::unpack(&beneficiary_account.data).unwrap();
+    assert_eq!(beneficiary_state.amount, 20_000);
+
+    let config_account = context
+        .banks_client
+        .get_account(cv_config)
+        .await
+        .unwrap()
+        .expect("cv config missing");
+    let mut config_data: &[u8] = &config_account.data;
+    let config_state = CVConfig::try_deserialize(&mut config_data).unwrap();
+    assert_eq!(config_state.committed_stream_amount, 0);
+}
+
+/// The payout treasury, treasury registry and abstain proposal that stake
+/// instructions read to report `proposal`'s threshold.
+async fn threshold_accounts(
+    banks_client: &mut solana_program_test::BanksClient,
+    cv_config: Pubkey,
+    proposal: Pubkey,
+) -> (Pubkey, Option<Pubkey>, Option<Pubkey>) {
+    let config = fetch_cv_config(banks_client, cv_config).await;
+    let request_mint = fetch_proposal(banks_client, proposal).await.request_mint;
+    let abstain_proposal =
+        (config.abstain_proposal != Pubkey::default()).then_some(config.abstain_proposal);
+    if request_mint == config.commons_token_mint {
+        return (config.commons_treasury, None, abstain_proposal);
+    }
+    let treasury_registry =
+        Pubkey::find_program_address(&[b"treasury_registry", cv_config.as_ref()], &CV_ID).0;
+    let registry_account = banks_client
+        .get_account(treasury_registry)
+        .await
+        .unwrap()
+        .expect("treasury registry missing");
+    let registry = TreasuryRegistry::try_deserialize(&mut &registry_account.data[..]).unwrap();
+    let treasury = registry
+        .treasury_for(&request_mint)
+        .expect("request mint has no registered treasury");
+    (treasury, Some(treasury_registry), abstain_proposal)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn stake_on_proposal(
+    banks_client: &mut solana_program_test::BanksClient,
+    user: &Keypair,
+    cv_config: Pubkey,
+    proposal: Pubkey,
+    commons_token_mint: Pubkey,
+    user_commons_account: Pubkey,
+    staking_vault: Pubkey,
+    amount: u64,
+) {
+    let (commons_treasury, treasury_registry, abstain_proposal) =
+        threshold_accounts(banks_client, cv_config, proposal).await;
+    let stake_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::StakeTokens {
+            stake_account: Pubkey::find_program_address(
+                &[b"stake", user.pubkey().as_ref(), proposal.as_ref()],
+                &CV_ID,
+            )
+            .0,
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry,
+            abstain_proposal,
+            voter_account: Pubkey::find_program_address(
+                &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                &CV_ID,
+            )
+            .0,
+            personhood: None,
+            commons_token_mint,
+            user_commons_token_account: user_commons_account,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::StakeTokens { amount }.data(),
+    };
+    process_transaction(banks_client, user, vec![stake_ix], vec![]).await;
+}
+
+async fn token_balance(
+    banks_client: &mut solana_program_test::BanksClient,
+    account: Pubkey,
+) -> u64 {
+    let account = banks_client
+        .get_account(account)
+        .await
+        .unwrap()
+        .expect("token account missing");
+    TokenAccountState::unpack(&account.data).unwrap().amount
+}
+
+/// Funds a user, the treasury and a beneficiary, then passes a 40_000 request
+/// with the dispute window enabled. Returns the Challengeable proposal.
+async fn pass_challengeable_proposal(
+    context: &mut ProgramTestContext,
+    commons_token_mint: Pubkey,
+    commons_treasury: Pubkey,
+    cv_config: Pubkey,
+    staking_vault: Pubkey,
+    arbiter: &Keypair,
+) -> (Pubkey, Pubkey) {
+    let payer = context.payer.insecure_clone();
+    let set_params_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::SetChallengeParams {
+            cv_config,
+            authority: payer.pubkey(),
+        }
+        .to_account_metas(None),
+        data: cv_instruction::SetChallengeParams {
+            challenge_period_slots: 200,
+            challenge_bond: 5_000,
+            arbiter: arbiter.pubkey(),
+        }
+        .data(),
+    };
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![set_params_ix],
+        vec![],
+    )
+    .await;
+
+    let beneficiary = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &commons_treasury,
+        &payer,
+        100_000,
+    )
+    .await;
+    let proposal = create_proposal_with_id(
+        &mut context.banks_client,
+        &payer,
+        cv_config,
+        beneficiary,
+        0,
+        40_000,
+    )
+    .await;
+
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut context.banks_client, &payer, vec![transfer], vec![]).await;
+    let user_commons_account = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        400_000,
+    )
+    .await;
+    stake_on_proposal(
+        &mut context.banks_client,
+        &user,
+        cv_config,
+        proposal,
+        commons_token_mint,
+        user_commons_account,
+        staking_vault,
+        400_000,
+    )
+    .await;
+
+    let check_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CheckAndExecute {
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            beneficiary: Some(beneficiary),
+            abstain_proposal: None,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CheckAndExecute {}.data(),
+    };
+    process_transaction(&mut context.banks_client, &payer, vec![check_ix], vec![]).await;
+
+    (proposal, beneficiary)
+}
+
+async fn challenge(
+    context: &mut ProgramTestContext,
+    cv_config: Pubkey,
+    commons_token_mint: Pubkey,
+    proposal: Pubkey,
+) -> (Keypair, Pubkey) {
+    let payer = context.payer.insecure_clone();
+    let challenger = Keypair::new();
+    let transfer =
+        system_instruction::transfer(&payer.pubkey(), &challenger.pubkey(), 1_000_000_000);
+    process_transaction(&mut context.banks_client, &payer, vec![transfer], vec![]).await;
+    let challenger_token_account = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &challenger.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &challenger_token_account,
+        &payer,
+        5_000,
+    )
+    .await;
+
+    let challenge_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::ChallengeProposal {
+            cv_config,
+            proposal,
+            challenge: Pubkey::find_program_address(&[b"challenge", proposal.as_ref()], &CV_ID).0,
+            bond_escrow: Pubkey::find_program_address(
+                &[b"challenge_bond", proposal.as_ref()],
+                &CV_ID,
+            )
+            .0,
+            commons_token_mint,
+            challenger_token_account,
+            challenger: challenger.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::ChallengeProposal {}.data(),
+    };
+    process_transaction(
+        &mut context.banks_client,
+        &challenger,
+        vec![challenge_ix],
+        vec![],
+    )
+    .await;
+    (challenger, challenger_token_account)
+}
+
+fn resolve_challenge_ix(
+    cv_config: Pubkey,
+    commons_treasury: Pubkey,
+    proposal: Pubkey,
+    challenger: Pubkey,
+    challenger_token_account: Pubkey,
+    arbiter: Pubkey,
+    uphold: bool,
+) -> Instruction {
+    Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::ResolveChallenge {
+            cv_config,
+            proposal,
+            challenge: Pubkey::find_program_address(&[b"challenge", proposal.as_ref()], &CV_ID).0,
+            bond_escrow: Pubkey::find_program_address(
+                &[b"challenge_bond", proposal.as_ref()],
+                &CV_ID,
+            )
+            .0,
+            commons_treasury,
+            challenger_token_account,
+            challenger,
+            arbiter,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::ResolveChallenge { uphold }.data(),
+    }
+}
+
+#[tokio::test]
+async fn dismissed_challenge_slashes_bond_and_proposal_finalizes() {
+    let (mut context, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_context(default_cv_params()).await;
+    let payer = context.payer.insecure_clone();
+    let arbiter = Keypair::new();
+    let (proposal, beneficiary) = pass_challengeable_proposal(
+        &mut context,
+        commons_token_mint,
+        commons_treasury,
+        cv_config,
+        staking_vault,
+        &arbiter,
+    )
+    .await;
+    assert_eq!(
+        fetch_proposal(&mut context.banks_client, proposal)
+            .await
+            .status,
+        ProposalStatus::Challengeable
+    );
+    assert_eq!(
+        token_balance(&mut context.banks_client, beneficiary).await,
+        0
+    );
+
+    let finalize_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::FinalizeProposal {
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            beneficiary: Some(beneficiary),
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::FinalizeProposal {}.data(),
+    };
+    expect_cv_error(
+        &mut context.banks_client,
+        &payer,
+        vec![finalize_ix.clone()],
+        vec![],
+        CustomError::ChallengeWindowOpen,
+    )
+    .await;
+
+    let (challenger, challenger_token_account) =
+        challenge(&mut context, cv_config, commons_token_mint, proposal).await;
+    assert_eq!(
+        fetch_proposal(&mut context.banks_client, proposal)
+            .await
+            .status,
+        ProposalStatus::Disputed
+    );
+
+    // Only the configured arbiter can rule
+    let impostor = Keypair::new();
+    expect_cv_error(
+        &mut context.banks_client,
+        &payer,
+        vec![resolve_challenge_ix(
+            cv_config,
+            commons_treasury,
+            proposal,
+            challenger.pubkey(),
+            challenger_token_account,
+            impostor.pubkey(),
+            false,
+        )],
+        vec![&impostor],
+        CustomError::ArbiterMismatch,
+    )
+    .await;
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![resolve_challenge_ix(
+            cv_config,
+            commons_treasury,
+            proposal,
+            challenger.pubkey(),
+            challenger_token_account,
+            arbiter.pubkey(),
+            false,
+        )],
+        vec![&arbiter],
+    )
+    .await;
+    assert_eq!(
+        token_balance(&mut context.banks_client, challenger_token_account).await,
+        0
+    );
+    assert_eq!(
+        token_balance(&mut context.banks_client, commons_treasury).await,
+        105_000
+    );
+
+    context.warp_to_slot(300).unwrap();
+    process_transaction(&mut context.banks_client, &payer, vec![finalize_ix], vec![]).await;
+    assert_eq!(
+        fetch_proposal(&mut context.banks_client, proposal)
+            .await
+            .status,
+        ProposalStatus::Approved
+    );
+    assert_eq!(
+        token_balance(&mut context.banks_client, beneficiary).await,
+        40_000
+    );
+}
+
+#[tokio::test]
+async fn upheld_challenge_rejects_proposal_and_refunds_bond() {
+    let (mut context, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_context(default_cv_params()).await;
+    let payer = context.payer.insecure_clone();
+    let arbiter = Keypair::new();
+    let (proposal, beneficiary) = pass_challengeable_proposal(
+        &mut context,
+        commons_token_mint,
+        commons_treasury,
+        cv_config,
+        staking_vault,
+        &arbiter,
+    )
+    .await;
+
+    let (challenger, challenger_token_account) =
+        challenge(&mut context, cv_config, commons_token_mint, proposal).await;
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![resolve_challenge_ix(
+            cv_config,
+            commons_treasury,
+            proposal,
+            challenger.pubkey(),
+            challenger_token_account,
+            arbiter.pubkey(),
+            true,
+        )],
+        vec![&arbiter],
+    )
+    .await;
+
+    assert_eq!(
+        fetch_proposal(&mut context.banks_client, proposal)
+            .await
+            .status,
+        ProposalStatus::Rejected
+    );
+    assert_eq!(
+        token_balance(&mut context.banks_client, challenger_token_account).await,
+        5_000
+    );
+    assert_eq!(
+        token_balance(&mut context.banks_client, commons_treasury).await,
+        100_000
+    );
+    assert_eq!(
+        token_balance(&mut context.banks_client, beneficiary).await,
+        0
+    );
+    let escrow = Pubkey::find_program_address(&[b"challenge_bond", proposal.as_ref()], &CV_ID).0;
+    assert!(context
+        .banks_client
+        .get_account(escrow)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn proposal_deposit_is_refunded_on_cancel_and_forfeited_on_reject() {
+    let (mut context, commons_token_mint, commons_treasury, cv_config, _staking_vault) =
+        setup_conviction_context(cv_instruction::InitializeCvConfig {
+            proposal_deposit: 1_000,
+            ..default_cv_params()
+        })
+        .await;
+    let payer = context.payer.insecure_clone();
+    let beneficiary = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    let creator_token_account = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &creator_token_account,
+        &payer,
+        2_000,
+    )
+    .await;
+
+    let create_ix = |id: u64, with_deposit: bool| {
+        let proposal = Pubkey::find_program_address(
+            &[b"proposal", cv_config.as_ref(), &id.to_le_bytes()],
+            &CV_ID,
+        )
+        .0;
+        let deposit_escrow =
+            Pubkey::find_program_address(&[b"proposal_deposit", proposal.as_ref()], &CV_ID).0;
+        let ix = Instruction {
+            program_id: CV_ID,
+            accounts: cv_accounts::CreateProposal {
+                cv_config,
+                proposal,
+                beneficiary: Some(beneficiary),
+                commons_token_mint,
+                creator_token_account: with_deposit.then_some(creator_token_account),
+                deposit_escrow: with_deposit.then_some(deposit_escrow),
+                authority: payer.pubkey(),
+                system_program: system_program::ID,
+                token_program: spl_token::id(),
+                clock: sysvar::clock::ID,
+            }
+            .to_account_metas(None),
+            data: cv_instruction::CreateProposal {
+                requested_amount: 1_000,
+                metadata_uri: format!("ipfs://deposit-{id}"),
+                category: String::new(),
+            }
+            .data(),
+        };
+        (proposal, deposit_escrow, ix)
+    };
+    let settle_ix = |proposal: Pubkey, deposit_escrow: Pubkey| Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::SettleProposalDeposit {
+            cv_config,
+            proposal,
+            deposit_escrow,
+            commons_treasury,
+            deposit_refund_account: creator_token_account,
+            creator: payer.pubkey(),
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None),
+        data: cv_instruction::SettleProposalDeposit {}.data(),
+    };
+
+    // The deposit cannot be skipped once configured
+    let (_, _, missing_deposit_ix) = create_ix(0, false);
+    expect_cv_error(
+        &mut context.banks_client,
+        &payer,
+        vec![missing_deposit_ix],
+        vec![],
+        CustomError::MissingProposalDeposit,
+    )
+    .await;
+
+    let (cancelled, cancelled_escrow, ix) = create_ix(0, true);
+    process_transaction(&mut context.banks_client, &payer, vec![ix], vec![]).await;
+    let (rejected, rejected_escrow, ix) = create_ix(1, true);
+    process_transaction(&mut context.banks_client, &payer, vec![ix], vec![]).await;
+    assert_eq!(
+        token_balance(&mut context.banks_client, creator_token_account).await,
+        0
+    );
+
+    // Pending proposals keep their deposit locked
+    expect_cv_error(
+        &mut context.banks_client,
+        &payer,
+        vec![settle_ix(cancelled, cancelled_escrow)],
+        vec![],
+        CustomError::ProposalDepositLocked,
+    )
+    .await;
+
+    let cancel_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CancelProposal {
+            cv_config,
+            proposal: cancelled,
+            creator: payer.pubkey(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CancelProposal {}.data(),
+    };
+    let reject_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::RejectProposal {
+            cv_config,
+            proposal: rejected,
+            authority: payer.pubkey(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::RejectProposal {}.data(),
+    };
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![
+            cancel_ix,
+            reject_ix,
+            settle_ix(cancelled, cancelled_escrow),
+            settle_ix(rejected, rejected_escrow),
+        ],
+        vec![],
+    )
+    .await;
+
+    assert_eq!(
+        token_balance(&mut context.banks_client, creator_token_account).await,
+        1_000
+    );
+    assert_eq!(
+        token_balance(&mut context.banks_client, commons_treasury).await,
+        1_000
+    );
+    assert_eq!(
+        fetch_proposal(&mut context.banks_client, rejected)
+            .await
+            .deposit_amount,
+        0
+    );
+    assert!(context
+        .banks_client
+        .get_account(rejected_escrow)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn seconds_time_base_decays_by_elapsed_seconds() {
+    let (mut context, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_context(cv_instruction::InitializeCvConfig {
+            decay_rate: 900_000,
+            time_base: TimeBase::Seconds,
+            ..default_cv_params()
+        })
+        .await;
+    let payer = context.payer.insecure_clone();
+    let beneficiary = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    let proposal = create_proposal_with_id(
+        &mut context.banks_client,
+        &payer,
+        cv_config,
+        beneficiary,
+        0,
+        1_000,
+    )
+    .await;
+
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut context.banks_client, &payer, vec![transfer], vec![]).await;
+    let user_commons_account = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        1_000_000,
+    )
+    .await;
+    stake_on_proposal(
+        &mut context.banks_client,
+        &user,
+        cv_config,
+        proposal,
+        commons_token_mint,
+        user_commons_account,
+        staking_vault,
+        1_000_000,
+    )
+    .await;
+    let staked_at: Clock = context.banks_client.get_sysvar().await.unwrap();
+    let proposal_state = fetch_proposal(&mut context.banks_client, proposal).await;
+    assert_eq!(proposal_state.last_update, staked_at.unix_timestamp as u64);
+    assert_eq!(proposal_state.current_conviction, 1_000_000);
+
+    // Many slots but only two seconds pass: decay must follow the timestamp
+    context.warp_to_slot(staked_at.slot + 1_000).unwrap();
+    let mut clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = staked_at.unix_timestamp + 2;
+    context.set_sysvar(&clock);
+
+    let unstake_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::UnstakeTokens {
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: None,
+            voter_account: Pubkey::find_program_address(
+                &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                &CV_ID,
+            )
+            .0,
+            stake_account: Pubkey::find_program_address(
+                &[b"stake", user.pubkey().as_ref(), proposal.as_ref()],
+                &CV_ID,
+            )
+            .0,
+            user_commons_token_account: user_commons_account,
+            commons_token_mint,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::UnstakeTokens { amount: 1 }.data(),
+    };
+    process_transaction(&mut context.banks_client, &user, vec![unstake_ix], vec![]).await;
+
+    let proposal_state = fetch_proposal(&mut context.banks_client, proposal).await;
+    assert_eq!(proposal_state.last_update, clock.unix_timestamp as u64);
+    // 1_000_000 * 0.9^2 - 1
+    assert_eq!(proposal_state.current_conviction, 809_999);
+}
+
+#[tokio::test]
+async fn delegate_allocates_owner_deposit_until_undelegated() {
+    let (mut banks_client, payer, commons_token_mint, _commons_treasury, cv_config, staking_vault) =
+        setup_conviction_env().await;
+    let owner = Keypair::new();
+    let steward = Keypair::new();
+
+    let transfer = system_instruction::transfer(&payer.pubkey(), &owner.pubkey(), 5_000_000_000);
+    process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
+
+    let beneficiary = create_token_account(
+        &mut banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    let proposal =
+        create_proposal_with_id(&mut banks_client, &payer, cv_config, beneficiary, 0, 10_000).await;
+
+    let owner_commons_account = create_token_account(
+        &mut banks_client,
+        &payer,
+        &owner.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut banks_client,
+        &payer,
+        &commons_token_mint,
+        &owner_commons_account,
+        &payer,
+        1_000,
+    )
+    .await;
+
+    let voter_account = Pubkey::find_program_address(
+        &[b"voter", cv_config.as_ref(), owner.pubkey().as_ref()],
+        &CV_ID,
+    )
+    .0;
+    let deposit_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::DepositTokens {
+            cv_config,
+            voter_account,
+            personhood: None,
+            commons_token_mint,
+            user_commons_token_account: owner_commons_account,
+            staking_vault,
+            authority: owner.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None),
+        data: cv_instruction::DepositTokens { amount: 1_000 }.data(),
+    };
+    let delegate_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::UpdateDelegation {
+            cv_config,
+            voter_account,
+            authority: owner.pubkey(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::Delegate {
+            to: steward.pubkey(),
+        }
+        .data(),
+    };
+    process_transaction(
+        &mut banks_client,
+        &owner,
+        vec![deposit_ix, delegate_ix],
+        vec![],
+    )
+    .await;
+
+    let owner_stake = Pubkey::find_program_address(
+        &[b"stake", owner.pubkey().as_ref(), proposal.as_ref()],
+        &CV_ID,
+    )
+    .0;
+    let reallocate_ix = |allocator: Pubkey, amount: u64| {
+        let mut accounts = cv_accounts::ReallocateSupport {
+            cv_config,
+            voter_account,
+            authority: allocator,
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None);
+        accounts.push(AccountMeta::new(proposal, false));
+        accounts.push(AccountMeta::new(owner_stake, false));
+        Instruction {
+            program_id: CV_ID,
+            accounts,
+            data: cv_instruction::ReallocateSupport {
+                allocations: vec![SupportAllocation { proposal, amount }],
+            }
+            .data(),
+        }
+    };
+
+    // While delegated only the steward allocates, and only up to the owner's deposit.
+    expect_cv_error(
+        &mut banks_client,
+        &owner,
+        vec![reallocate_ix(owner.pubkey(), 500)],
+        vec![],
+        CustomError::NotVoterAllocator,
+    )
+    .await;
+    expect_cv_error(
+        &mut banks_client,
+        &payer,
+        vec![reallocate_ix(steward.pubkey(), 1_001)],
+        vec![&steward],
+        CustomError::InsufficientUnallocatedDeposit,
+    )
+    .await;
+    process_transaction(
+        &mut banks_client,
+        &payer,
+        vec![reallocate_ix(steward.pubkey(), 1_000)],
+        vec![&steward],
+    )
+    .await;
+    assert_eq!(
+        fetch_proposal(&mut banks_client, proposal)
+            .await
+            .current_conviction,
+        1_000
+    );
+
+    // Undelegating pulls the steward's support, after which the owner can withdraw.
+    let mut undelegate_accounts = cv_accounts::UpdateDelegation {
+        cv_config,
+        voter_account,
+        authority: owner.pubkey(),
+        clock: sysvar::clock::ID,
+    }
+    .to_account_metas(None);
+    undelegate_accounts.push(AccountMeta::new(proposal, false));
+    undelegate_accounts.push(AccountMeta::new(owner_stake, false));
+    let undelegate_ix = Instruction {
+        program_id: CV_ID,
+        accounts: undelegate_accounts,
+        data: cv_instruction::Undelegate {}.data(),
+    };
+    let withdraw_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::WithdrawTokens {
+            cv_config,
+            voter_account,
+            commons_token_mint,
+            user_commons_token_account: owner_commons_account,
+            staking_vault,
+            authority: owner.pubkey(),
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None),
+        data: cv_instruction::WithdrawTokens { amount: 1_000 }.data(),
+    };
+    process_transaction(
+        &mut banks_client,
+        &owner,
+        vec![undelegate_ix, withdraw_ix],
+        vec![],
+    )
+    .await;
+
+    assert_eq!(
+        fetch_proposal(&mut banks_client, proposal)
+            .await
+            .current_conviction,
+        0
+    );
+    assert_eq!(
+        token_balance(&mut banks_client, owner_commons_account).await,
+        1_000
+    );
+    let config_state = fetch_cv_config(&mut banks_client, cv_config).await;
+    assert_eq!(config_state.total_staked, 0);
+}
+
+#[tokio::test]
+async fn update_cv_params_requires_governance_and_valid_values() {
+    let (
+        mut banks_client,
+        payer,
+        _commons_token_mint,
+        _commons_treasury,
+        cv_config,
+        _staking_vault,
+    ) = setup_conviction_env().await;
+    let stranger = Keypair::new();
+
+    let update_ix = |updater: Pubkey, decay_rate: u64| Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::UpdateCvParams {
+            cv_config,
+            updater,
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::UpdateCvParams {
+            decay_rate,
+            max_ratio: 500_000,
+            weight_exponent: 2_000_000,
+            min_threshold: 100_000,
+        }
+        .data(),
+    };
+
+    expect_cv_error(
+        &mut banks_client,
+        &payer,
+        vec![update_ix(stranger.pubkey(), 900_000)],
+        vec![&stranger],
+        CustomError::NotCvGovernance,
+    )
+    .await;
+    expect_cv_error(
+        &mut banks_client,
+        &payer,
+        vec![update_ix(payer.pubkey(), 1_000_001)],
+        vec![],
+        CustomError::InvalidDecayRate,
+    )
+    .await;
+
+    process_transaction(
+        &mut banks_client,
+        &payer,
+        vec![update_ix(payer.pubkey(), 900_000)],
+        vec![],
+    )
+    .await;
+    let config_state = fetch_cv_config(&mut banks_client, cv_config).await;
+    assert_eq!(config_state.decay_rate, 900_000);
+    assert_eq!(
+        config_state.previous_decay_rate,
+        default_cv_params().decay_rate
+    );
+    assert_eq!(config_state.max_ratio, 500_000);
+    assert_eq!(config_state.weight_exponent, 2_000_000);
+    assert_eq!(config_state.min_threshold, 100_000);
+}
+
+#[tokio::test]
+async fn locked_stake_is_weighted_and_cannot_leave_early() {
+    let (mut context, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_context(default_cv_params()).await;
+    let payer = context.payer.insecure_clone();
+
+    let schedule_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::SetLockSchedule {
+            cv_config,
+            authority: payer.pubkey(),
+        }
+        .to_account_metas(None),
+        data: cv_instruction::SetLockSchedule {
+            lock_schedule: vec![
+                LockTier {
+                    duration: 100,
+                    multiplier: 1_500_000,
+                },
+                LockTier {
+                    duration: 1_000,
+                    multiplier: 3_000_000,
+                },
+            ],
+        }
+        .data(),
+    };
+    process_transaction(&mut context.banks_client, &payer, vec![schedule_ix], vec![]).await;
+
+    let beneficiary = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    let proposal = create_proposal_with_id(
+        &mut context.banks_client,
+        &payer,
+        cv_config,
+        beneficiary,
+        0,
+        1_000,
+    )
+    .await;
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut context.banks_client, &payer, vec![transfer], vec![]).await;
+    let user_commons_account = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        1_000,
+    )
+    .await;
+
+    let stake_account = Pubkey::find_program_address(
+        &[b"stake", user.pubkey().as_ref(), proposal.as_ref()],
+        &CV_ID,
+    )
+    .0;
+    let stake_accounts = cv_accounts::StakeTokens {
+        stake_account,
+        cv_config,
+        proposal,
+        commons_treasury,
+        treasury_registry: None,
+        abstain_proposal: None,
+        voter_account: Pubkey::find_program_address(
+            &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+            &CV_ID,
+        )
+        .0,
+        personhood: None,
+        commons_token_mint,
+        user_commons_token_account: user_commons_account,
+        staking_vault,
+        authority: user.pubkey(),
+        system_program: system_program::ID,
+        token_program: spl_token::id(),
+        clock: sysvar::clock::ID,
+    };
+    let lock_ix = Instruction {
+        program_id: CV_ID,
+        accounts: stake_accounts.to_account_metas(None),
+        data: cv_instruction::StakeTokensLocked {
+            amount: 1_000,
+            lock_duration: 150,
+        }
+        .data(),
+    };
+    process_transaction(&mut context.banks_client, &user, vec![lock_ix], vec![]).await;
+    let locked_at: Clock = context.banks_client.get_sysvar().await.unwrap();
+
+    // 150 slots reaches the 1.5x tier
+    assert_eq!(
+        fetch_proposal(&mut context.banks_client, proposal)
+            .await
+            .current_conviction,
+        1_500
+    );
+    assert_eq!(
+        fetch_cv_config(&mut context.banks_client, cv_config)
+            .await
+            .total_staked,
+        1_500
+    );
+
+    let unstake_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::UnstakeTokens {
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: None,
+            voter_account: Pubkey::find_program_address(
+                &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                &CV_ID,
+            )
+            .0,
+            stake_account,
+            user_commons_token_account: user_commons_account,
+            commons_token_mint,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::UnstakeTokens { amount: 1_000 }.data(),
+    };
+    expect_cv_error(
+        &mut context.banks_client,
+        &user,
+        vec![unstake_ix.clone()],
+        vec![],
+        CustomError::StakeLocked,
+    )
+    .await;
+
+    context.warp_to_slot(locked_at.slot + 150).unwrap();
+    process_transaction(&mut context.banks_client, &user, vec![unstake_ix], vec![]).await;
+    assert_eq!(
+        token_balance(&mut context.banks_client, user_commons_account).await,
+        1_000
+    );
+    assert_eq!(
+        fetch_cv_config(&mut context.banks_client, cv_config)
+            .await
+            .total_staked,
+        0
+    );
+}
+
+async fn view<T: AnchorDeserialize>(
+    banks_client: &mut solana_program_test::BanksClient,
+    payer: &Keypair,
+    instruction: Instruction,
+) -> T {
+    let recent_blockhash = banks_client.get_latest_blockhash().await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_blockhash,
+    );
+    let simulation = banks_client.simulate_transaction(tx).await.unwrap();
+    let return_data = simulation
+        .simulation_details
+        .and_then(|details| details.return_data)
+        .expect("view returned no data");
+    assert_eq!(return_data.program_id, CV_ID);
+    T::try_from_slice(&return_data.data).unwrap()
+}
+
+#[tokio::test]
+async fn views_report_present_conviction_threshold_and_pass_slot() {
+    let (mut context, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_context(default_cv_params()).await;
+    let payer = context.payer.insecure_clone();
+
+    let beneficiary = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &commons_treasury,
+        &payer,
+        100_000,
+    )
+    .await;
+    let proposal = create_proposal_with_id(
+        &mut context.banks_client,
+        &payer,
+        cv_config,
+        beneficiary,
+        0,
+        40_000,
+    )
+    .await;
+
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut context.banks_client, &payer, vec![transfer], vec![]).await;
+    let user_commons_account = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        400_000,
+    )
+    .await;
+    stake_on_proposal(
+        &mut context.banks_client,
+        &user,
+        cv_config,
+        proposal,
+        commons_token_mint,
+        user_commons_account,
+        staking_vault,
+        400_000,
+    )
+    .await;
+
+    let accounts = cv_accounts::ViewProposal {
+        cv_config,
+        proposal,
+        commons_treasury,
+        treasury_registry: None,
+        abstain_proposal: None,
+        clock: sysvar::clock::ID,
+    }
+    .to_account_metas(None);
+    let view_ix = |data: Vec<u8>| Instruction {
+        program_id: CV_ID,
+        accounts: accounts.clone(),
+        data,
+    };
+    let conviction_ix = view_ix(cv_instruction::ViewConviction {}.data());
+    let required_ix = view_ix(cv_instruction::ViewRequiredConviction {}.data());
+    let pass_slot_ix = view_ix(cv_instruction::ViewPredictedPassSlot {}.data());
+
+    // 40% of the treasury at weight 1.0 needs 40% of the 400_000 staked
+    let staked_at: Clock = context.banks_client.get_sysvar().await.unwrap();
+    let required: u64 = view(&mut context.banks_client, &payer, required_ix.clone()).await;
+    assert_eq!(required, 160_000);
+    let conviction: u64 = view(&mut context.banks_client, &payer, conviction_ix.clone()).await;
+    assert_eq!(conviction, 400_000);
+    // Already past its threshold, it passes at the current slot
+    let pass_slot: Option<u64> =
+        view(&mut context.banks_client, &payer, pass_slot_ix.clone()).await;
+    assert_eq!(pass_slot, Some(staked_at.slot));
+
+    // Two slots at a 0.5 decay rate leave a quarter, below the threshold for good
+    context.warp_to_slot(staked_at.slot + 2).unwrap();
+    let conviction: u64 = view(&mut context.banks_client, &payer, conviction_ix).await;
+    assert_eq!(conviction, 100_000);
+    let required: u64 = view(&mut context.banks_client, &payer, required_ix).await;
+    assert_eq!(required, 160_000);
+    let pass_slot: Option<u64> = view(&mut context.banks_client, &payer, pass_slot_ix).await;
+    assert_eq!(pass_slot, None);
+}
+
+/// Forwards to program-test's syscall stubs, except that `emit!` output is
+/// written to the transaction logs instead of stdout. Natively built
+/// programs have no `sol_log_data` syscall, so the logs would otherwise
+/// carry no events.
+struct EventLogStubs(Box<dyn SyscallStubs>);
+
+impl SyscallStubs for EventLogStubs {
+    fn sol_log(&self, message: &str) {
+        self.0.sol_log(message)
+    }
+    fn sol_log_compute_units(&self) {
+        self.0.sol_log_compute_units()
+    }
+    fn sol_remaining_compute_units(&self) -> u64 {
+        self.0.sol_remaining_compute_units()
+    }
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        self.0
+            .sol_invoke_signed(instruction, account_infos, signers_seeds)
+    }
+    fn sol_get_sysvar(
+        &self,
+        sysvar_id_addr: *const u8,
+        var_addr: *mut u8,
+        offset: u64,
+        length: u64,
+    ) -> u64 {
+        self.0
+            .sol_get_sysvar(sysvar_id_addr, var_addr, offset, length)
+    }
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_clock_sysvar(var_addr)
+    }
+    fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_epoch_schedule_sysvar(var_addr)
+    }
+    fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_fees_sysvar(var_addr)
+    }
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_rent_sysvar(var_addr)
+    }
+    fn sol_get_epoch_rewards_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_epoch_rewards_sysvar(var_addr)
+    }
+    fn sol_get_last_restart_slot(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_last_restart_slot(var_addr)
+    }
+    fn sol_get_epoch_stake(&self, vote_address: *const u8) -> u64 {
+        self.0.sol_get_epoch_stake(vote_address)
+    }
+    unsafe fn sol_memcpy(&self, dst: *mut u8, src: *const u8, n: usize) {
+        self.0.sol_memcpy(dst, src, n)
+    }
+    unsafe fn sol_memmove(&self, dst: *mut u8, src: *const u8, n: usize) {
+        self.0.sol_memmove(dst, src, n)
+    }
+    unsafe fn sol_memcmp(&self, s1: *const u8, s2: *const u8, n: usize, result: *mut i32) {
+        self.0.sol_memcmp(s1, s2, n, result)
+    }
+    unsafe fn sol_memset(&self, s: *mut u8, c: u8, n: usize) {
+        self.0.sol_memset(s, c, n)
+    }
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        self.0.sol_get_return_data()
+    }
+    fn sol_set_return_data(&self, data: &[u8]) {
+        self.0.sol_set_return_data(data)
+    }
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        let encoded: Vec<String> = fields
+            .iter()
+            .map(|field| base64::engine::general_purpose::STANDARD.encode(field))
+            .collect();
+        self.0
+            .sol_log(&format!("Program data: {}", encoded.join(" ")))
+    }
+    fn sol_get_processed_sibling_instruction(&self, index: usize) -> Option<Instruction> {
+        self.0.sol_get_processed_sibling_instruction(index)
+    }
+    fn sol_get_stack_height(&self) -> u64 {
+        self.0.sol_get_stack_height()
+    }
+}
+
+struct DefaultStubs;
+
+impl SyscallStubs for DefaultStubs {}
+
+/// Installs `EventLogStubs` over the stubs `ProgramTest::start` sets up; the
+/// placeholder is only in place between two consecutive swaps.
+fn log_events_to_transaction_logs() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let program_test_stubs = program_stubs::set_syscall_stubs(Box::new(DefaultStubs));
+        program_stubs::set_syscall_stubs(Box::new(EventLogStubs(program_test_stubs)));
+    });
+}
+
+async fn process_transaction_logs(
+    banks_client: &mut solana_program_test::BanksClient,
+    payer: &Keypair,
+    instructions: Vec<Instruction>,
+    signers: Vec<&Keypair>,
+) -> Vec<String> {
+    log_events_to_transaction_logs();
+    let tx = signed_transaction(banks_client, payer, &instructions, signers).await;
+    // Logs come from a lock-free simulation. Executing through
+    // `process_transaction_with_metadata` races the queued `process_transaction`
+    // path, which can return before the previous batch releases its account
+    // locks and fail this transaction with `AccountInUse`.
+    let simulation = banks_client.simulate_transaction(tx.clone()).await.unwrap();
+    banks_client.process_transaction(tx).await.unwrap();
+    simulation
+        .simulation_details
+        .expect("simulation details missing")
+        .logs
+}
+
+fn decode_events<T: Event>(logs: &[String]) -> Vec<T> {
+    logs.iter()
+        .filter_map(|log| log.split_once("Program data: ").map(|(_, data)| data))
+        .map(|data| {
+            base64::engine::general_purpose::STANDARD
+                .decode(data)
+                .unwrap()
+        })
+        .filter(|data| data.starts_with(T::DISCRIMINATOR))
+        .map(|data| T::try_from_slice(&data[T::DISCRIMINATOR.len()..]).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn lifecycle_events_are_decodable_from_logs() {
+    let (mut context, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_context(default_cv_params()).await;
+    let payer = context.payer.insecure_clone();
+
+    let beneficiary = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &commons_treasury,
+        &payer,
+        100_000,
+    )
+    .await;
+
+    let proposal = Pubkey::find_program_address(
+        &[b"proposal", cv_config.as_ref(), &0u64.to_le_bytes()],
+        &CV_ID,
+    )
+    .0;
+    let create_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CreateProposal {
+            proposal,
+            cv_config,
+            beneficiary: Some(beneficiary),
+            commons_token_mint,
+            creator_token_account: None,
+            deposit_escrow: None,
+            authority: payer.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CreateProposal {
+            requested_amount: 40_000,
+            metadata_uri: "ipfs://proposal-0".to_string(),
+            category: String::new(),
+        }
+        .data(),
+    };
+    let logs =
+        process_transaction_logs(&mut context.banks_client, &payer, vec![create_ix], vec![]).await;
+    let created = decode_events::<ProposalCreated>(&logs);
+    assert_eq!(created.len(), 1);
+    assert_eq!(created[0].proposal, proposal);
+    assert_eq!(created[0].id, 0);
+    assert_eq!(created[0].creator, payer.pubkey());
+    assert_eq!(created[0].beneficiary, beneficiary);
+    assert_eq!(created[0].requested_amount, 40_000);
+
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut context.banks_client, &payer, vec![transfer], vec![]).await;
+    let user_commons_account = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        400_000,
+    )
+    .await;
+    let stake_account = Pubkey::find_program_address(
+        &[b"stake", user.pubkey().as_ref(), proposal.as_ref()],
+        &CV_ID,
+    )
+    .0;
+    let stake_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::StakeTokens {
+            stake_account,
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: None,
+            voter_account: Pubkey::find_program_address(
+                &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                &CV_ID,
+            )
+            .0,
+            personhood: None,
+            commons_token_mint,
+            user_commons_token_account: user_commons_account,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::StakeTokens { amount: 400_000 }.data(),
+    };
+    let logs =
+        process_transaction_logs(&mut context.banks_client, &user, vec![stake_ix], vec![]).await;
+    let staked = decode_events::<Staked>(&logs);
+    assert_eq!(staked.len(), 1);
+    assert_eq!(staked[0].user, user.pubkey());
+    assert_eq!(staked[0].amount, 400_000);
+    assert_eq!(staked[0].weighted_amount, 400_000);
+    assert_eq!(staked[0].conviction_before, 0);
+    assert_eq!(staked[0].conviction_after, 400_000);
+    // 40% of the 100_000 treasury at weight 1.0 needs 40% of the 400_000 staked
+    assert_eq!(staked[0].threshold, Some(160_000));
+
+    let unstake_ix = |commons_treasury: Pubkey, amount: u64| Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::UnstakeTokens {
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: None,
+            voter_account: Pubkey::find_program_address(
+                &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                &CV_ID,
+            )
+            .0,
+            stake_account,
+            user_commons_token_account: user_commons_account,
+            commons_token_mint,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::UnstakeTokens { amount }.data(),
+    };
+    // The wrong treasury only costs the event its threshold
+    let logs = process_transaction_logs(
+        &mut context.banks_client,
+        &user,
+        vec![unstake_ix(user_commons_account, 50_000)],
+        vec![],
+    )
+    .await;
+    let unstaked = decode_events::<Unstaked>(&logs);
+    assert_eq!(unstaked.len(), 1);
+    assert_eq!(unstaked[0].amount, 50_000);
+    assert_eq!(unstaked[0].conviction_before, 400_000);
+    assert_eq!(unstaked[0].conviction_after, 350_000);
+    assert_eq!(unstaked[0].threshold, None);
+
+    let logs = process_transaction_logs(
+        &mut context.banks_client,
+        &user,
+        vec![unstake_ix(commons_treasury, 50_000)],
+        vec![],
+    )
+    .await;
+    let unstaked = decode_events::<Unstaked>(&logs);
+    assert_eq!(unstaked.len(), 1);
+    assert_eq!(unstaked[0].conviction_before, 350_000);
+    assert_eq!(unstaked[0].conviction_after, 300_000);
+    assert_eq!(unstaked[0].threshold, Some(120_000));
+
+    let check_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CheckAndExecute {
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            beneficiary: Some(beneficiary),
+            abstain_proposal: None,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CheckAndExecute {}.data(),
+    };
+    let logs =
+        process_transaction_logs(&mut context.banks_client, &payer, vec![check_ix], vec![]).await;
+    let updated = decode_events::<ConvictionUpdated>(&logs);
+    assert_eq!(updated.len(), 1);
+    assert_eq!(updated[0].proposal, proposal);
+    assert_eq!(updated[0].conviction_before, 300_000);
+    assert_eq!(updated[0].conviction_after, 300_000);
+    let executed = decode_events::<ProposalExecuted>(&logs);
+    assert_eq!(executed.len(), 1);
+    assert_eq!(executed[0].conviction, 300_000);
+    // 40% of the treasury at weight 1.0 needs 40% of the 300_000 still staked
+    assert_eq!(executed[0].threshold, 120_000);
+    assert_eq!(executed[0].status, ProposalStatus::Approved);
+
+    let withdraw_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::WithdrawStake {
+            stake_account,
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: None,
+            voter_account: Pubkey::find_program_address(
+                &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                &CV_ID,
+            )
+            .0,
+            user_commons_token_account: user_commons_account,
+            commons_token_mint,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::WithdrawStake {}.data(),
+    };
+    let logs =
+        process_transaction_logs(&mut context.banks_client, &user, vec![withdraw_ix], vec![]).await;
+    let withdrawn = decode_events::<StakeWithdrawn>(&logs);
+    assert_eq!(withdrawn.len(), 1);
+    assert_eq!(withdrawn[0].user, user.pubkey());
+    assert_eq!(withdrawn[0].amount, 300_000);
+    assert_eq!(withdrawn[0].status, ProposalStatus::Approved);
+    assert_eq!(withdrawn[0].conviction_before, 300_000);
+    assert_eq!(withdrawn[0].conviction_after, 0);
+    // With nothing left staked the effective supply, and so the threshold, is zero
+    assert_eq!(withdrawn[0].threshold, Some(0));
+}
+
+fn check_and_execute_ix(
+    cv_config: Pubkey,
+    proposal: Pubkey,
+    commons_treasury: Pubkey,
+    beneficiary: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CheckAndExecute {
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            beneficiary: Some(beneficiary),
+            abstain_proposal: None,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CheckAndExecute {}.data(),
+    }
+}
+
+#[tokio::test]
+async fn spending_window_and_category_caps_block_execution() {
+    let (mut context, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_context(default_cv_params()).await;
+    let payer = context.payer.insecure_clone();
+
+    let limits_accounts = cv_accounts::SetSpendingLimits {
+        cv_config,
+        authority: payer.pubkey(),
+    }
+    .to_account_metas(None);
+    let set_cap_ix = Instruction {
+        program_id: CV_ID,
+        accounts: limits_accounts.clone(),
+        data: cv_instruction::SetSpendingCap {
+            spending_window_slots: 1_000,
+            spending_cap: 50_000,
+        }
+        .data(),
+    };
+    let set_category_ix = Instruction {
+        program_id: CV_ID,
+        accounts: limits_accounts,
+        data: cv_instruction::SetFundingCategory {
+            name: "events".to_string(),
+            cap: 20_000,
+        }
+        .data(),
+    };
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![set_cap_ix, set_category_ix],
+        vec![],
+    )
+    .await;
+
+    let beneficiary = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &commons_treasury,
+        &payer,
+        200_000,
+    )
+    .await;
+    let first = create_proposal_with_id(
+        &mut context.banks_client,
+        &payer,
+        cv_config,
+        beneficiary,
+        0,
+        40_000,
+    )
+    .await;
+    let second = create_proposal_with_id(
+        &mut context.banks_client,
+        &payer,
+        cv_config,
+        beneficiary,
+        1,
+        20_000,
+    )
+    .await;
+    let events = create_proposal_in_category(
+        &mut context.banks_client,
+        &payer,
+        cv_config,
+        beneficiary,
+        2,
+        25_000,
+        "events",
+    )
+    .await;
+
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut context.banks_client, &payer, vec![transfer], vec![]).await;
+    let user_commons_account = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        1_000_000,
+    )
+    .await;
+    for proposal in [first, second, events] {
+        stake_on_proposal(
+            &mut context.banks_client,
+            &user,
+            cv_config,
+            proposal,
+            commons_token_mint,
+            user_commons_account,
+            staking_vault,
+            100_000,
+        )
+        .await;
+    }
+
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![check_and_execute_ix(
+            cv_config,
+            first,
+            commons_treasury,
+            beneficiary,
+        )],
+        vec![],
+    )
+    .await;
+    // 40_000 + 20_000 would overrun the 50_000 window cap
+    expect_cv_error(
+        &mut context.banks_client,
+        &payer,
+        vec![check_and_execute_ix(
+            cv_config,
+            second,
+            commons_treasury,
+            beneficiary,
+        )],
+        vec![],
+        CustomError::SpendingBudgetExhausted,
+    )
+    .await;
+
+    // The window rolls rather than resetting: the first grant counts in full
+    // until a whole window has passed since it was paid
+    let paid_at = fetch_cv_config(&mut context.banks_client, cv_config)
+        .await
+        .spending
+        .buckets[SPENDING_BUCKETS - 1]
+        .last_slot;
+    context.warp_to_slot(paid_at + 900).unwrap();
+    // Earlier conviction has decayed away, so it needs fresh support
+    stake_on_proposal(
+        &mut context.banks_client,
+        &user,
+        cv_config,
+        second,
+        commons_token_mint,
+        user_commons_account,
+        staking_vault,
+        150_000,
+    )
+    .await;
+    expect_cv_error(
+        &mut context.banks_client,
+        &payer,
+        vec![check_and_execute_ix(
+            cv_config,
+            second,
+            commons_treasury,
+            beneficiary,
+        )],
+        vec![],
+        CustomError::SpendingBudgetExhausted,
+    )
+    .await;
+
+    context.warp_to_slot(paid_at + 1_000).unwrap();
+    stake_on_proposal(
+        &mut context.banks_client,
+        &user,
+        cv_config,
+        second,
+        commons_token_mint,
+        user_commons_account,
+        staking_vault,
+        150_000,
+    )
+    .await;
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![check_and_execute_ix(
+            cv_config,
+            second,
+            commons_treasury,
+            beneficiary,
+        )],
+        vec![],
+    )
+    .await;
+
+    // Once the overall budget has fully recovered the category's own cap still binds
+    context.warp_to_slot(paid_at + 3_000).unwrap();
+    stake_on_proposal(
+        &mut context.banks_client,
+        &user,
+        cv_config,
+        events,
+        commons_token_mint,
+        user_commons_account,
+        staking_vault,
+        250_000,
+    )
+    .await;
+    expect_cv_error(
+        &mut context.banks_client,
+        &payer,
+        vec![check_and_execute_ix(
+            cv_config,
+            events,
+            commons_treasury,
+            beneficiary,
+        )],
+        vec![],
+        CustomError::FundingCategoryCapExceeded,
+    )
+    .await;
+
+    let config = fetch_cv_config(&mut context.banks_client, cv_config).await;
+    let spent: Vec<u64> = config
+        .spending
+        .buckets
+        .iter()
+        .map(|bucket| bucket.spent)
+        .collect();
+    assert_eq!(spent, vec![0, 0, 40_000, 20_000]);
+    assert_eq!(
+        token_balance(&mut context.banks_client, beneficiary).await,
+        60_000
+    );
+}
+
+#[tokio::test]
+async fn registered_treasury_pays_requests_in_its_mint() {
+    let (mut context, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_context(default_cv_params()).await;
+    let payer = context.payer.insecure_clone();
+
+    let reserve_mint = create_mint(&mut context.banks_client, &payer, &payer.pubkey()).await;
+    let reserve_treasury =
+        create_token_account(&mut context.banks_client, &payer, &cv_config, &reserve_mint).await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &reserve_mint,
+        &reserve_treasury,
+        &payer,
+        100_000,
+    )
+    .await;
+    let treasury_registry =
+        Pubkey::find_program_address(&[b"treasury_registry", cv_config.as_ref()], &CV_ID).0;
+    let register_ix = |spending_cap: u64| Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::RegisterTreasury {
+            cv_config,
+            treasury_registry,
+            treasury: reserve_treasury,
+            authority: payer.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::RegisterTreasury { spending_cap }.data(),
+    };
+    // The reserve mint gets its own cap, in its own units
+    let set_cap_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::SetSpendingLimits {
+            cv_config,
+            authority: payer.pubkey(),
+        }
+        .to_account_metas(None),
+        data: cv_instruction::SetSpendingCap {
+            spending_window_slots: 1_000,
+            spending_cap: 0,
+        }
+        .data(),
+    };
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![set_cap_ix, register_ix(30_000)],
+        vec![],
+    )
+    .await;
+
+    // The beneficiary's mint decides which treasury pays the proposal
+    let beneficiary = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &payer.pubkey(),
+        &reserve_mint,
+    )
+    .await;
+    let proposal = create_proposal_with_id(
+        &mut context.banks_client,
+        &payer,
+        cv_config,
+        beneficiary,
+        0,
+        40_000,
+    )
+    .await;
+    assert_eq!(
+        fetch_proposal(&mut context.banks_client, proposal)
+            .await
+            .request_mint,
+        reserve_mint
+    );
+
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut context.banks_client, &payer, vec![transfer], vec![]).await;
+    let user_commons_account = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        400_000,
+    )
+    .await;
+    stake_on_proposal(
+        &mut context.banks_client,
+        &user,
+        cv_config,
+        proposal,
+        commons_token_mint,
+        user_commons_account,
+        staking_vault,
+        400_000,
+    )
+    .await;
+
+    let check_ix = |treasury: Pubkey, treasury_registry: Option<Pubkey>| Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CheckAndExecute {
+            cv_config,
+            proposal,
+            commons_treasury: treasury,
+            treasury_registry,
+            beneficiary: Some(beneficiary),
+            abstain_proposal: None,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CheckAndExecute {}.data(),
+    };
+    expect_cv_error(
+        &mut context.banks_client,
+        &payer,
+        vec![check_ix(reserve_treasury, None)],
+        vec![],
+        CustomError::UnregisteredTreasury,
+    )
+    .await;
+    expect_cv_error(
+        &mut context.banks_client,
+        &payer,
+        vec![check_ix(commons_treasury, Some(treasury_registry))],
+        vec![],
+        CustomError::TreasuryMismatch,
+    )
+    .await;
+
+    expect_cv_error(
+        &mut context.banks_client,
+        &payer,
+        vec![check_ix(reserve_treasury, Some(treasury_registry))],
+        vec![],
+        CustomError::SpendingBudgetExhausted,
+    )
+    .await;
+
+    // The threshold is measured against the reserve treasury's 100_000 balance
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![
+            register_ix(50_000),
+            check_ix(reserve_treasury, Some(treasury_registry)),
+        ],
+        vec![],
+    )
+    .await;
+    assert_eq!(
+        token_balance(&mut context.banks_client, beneficiary).await,
+        40_000
+    );
+    assert_eq!(
+        token_balance(&mut context.banks_client, reserve_treasury).await,
+        60_000
+    );
+    let registry_account = context
+        .banks_client
+        .get_account(treasury_registry)
+        .await
+        .unwrap()
+        .expect("treasury registry missing");
+    let registry = TreasuryRegistry::try_deserialize(&mut &registry_account.data[..]).unwrap();
+    assert_eq!(
+        registry.treasuries[0].spending.buckets[SPENDING_BUCKETS - 1].spent,
+        40_000
+    );
+}
+
+#[tokio::test]
+async fn emergency_pause_blocks_activity_and_releases_locked_stakes() {
+    let (mut context, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_context(default_cv_params()).await;
+    let payer = context.payer.insecure_clone();
+    let guardian = Keypair::new();
+    let set_guardian_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::SetGuardian {
+            cv_config,
+            authority: payer.pubkey(),
+        }
+        .to_account_metas(None),
+        data: cv_instruction::SetGuardian {
+            guardian: guardian.pubkey(),
+        }
+        .data(),
+    };
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![set_guardian_ix],
+        vec![],
+    )
+    .await;
+
+    let beneficiary = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &commons_treasury,
+        &payer,
+        100_000,
+    )
+    .await;
+    let proposal = create_proposal_with_id(
+        &mut context.banks_client,
+        &payer,
+        cv_config,
+        beneficiary,
+        0,
+        40_000,
+    )
+    .await;
+
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut context.banks_client, &payer, vec![transfer], vec![]).await;
+    let user_commons_account = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        500_000,
+    )
+    .await;
+    let stake_account = Pubkey::find_program_address(
+        &[b"stake", user.pubkey().as_ref(), proposal.as_ref()],
+        &CV_ID,
+    )
+    .0;
+    let stake_ix = |amount: u64| Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::StakeTokens {
+            stake_account,
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: None,
+            voter_account: Pubkey::find_program_address(
+                &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                &CV_ID,
+            )
+            .0,
+            personhood: None,
+            commons_token_mint,
+            user_commons_token_account: user_commons_account,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::StakeTokensLocked {
+            amount,
+            lock_duration: 10_000,
+        }
+        .data(),
+    };
+    process_transaction(
+        &mut context.banks_client,
+        &user,
+        vec![stake_ix(400_000)],
+        vec![],
+    )
+    .await;
+
+    let pause_ix = |signer: Pubkey| Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::GuardianAction {
+            cv_config,
+            guardian: signer,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::EmergencyPause {}.data(),
+    };
+    let emergency_withdraw_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::WithdrawStake {
+            stake_account,
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: None,
+            voter_account: Pubkey::find_program_address(
+                &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                &CV_ID,
+            )
+            .0,
+            user_commons_token_account: user_commons_account,
+            commons_token_mint,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::EmergencyWithdrawStake {}.data(),
+    };
+    expect_cv_error(
+        &mut context.banks_client,
+        &user,
+        vec![emergency_withdraw_ix.clone()],
+        vec![],
+        CustomError::ConfigNotPaused,
+    )
+    .await;
+    expect_cv_error(
+        &mut context.banks_client,
+        &payer,
+        vec![pause_ix(payer.pubkey())],
+        vec![],
+        CustomError::NotGuardian,
+    )
+    .await;
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![pause_ix(guardian.pubkey())],
+        vec![&guardian],
+    )
+    .await;
+
+    expect_cv_error(
+        &mut context.banks_client,
+        &user,
+        vec![stake_ix(100_000)],
+        vec![],
+        CustomError::ConfigPaused,
+    )
+    .await;
+    expect_cv_error(
+        &mut context.banks_client,
+        &payer,
+        vec![check_and_execute_ix(
+            cv_config,
+            proposal,
+            commons_treasury,
+            beneficiary,
+        )],
+        vec![],
+        CustomError::ConfigPaused,
+    )
+    .await;
+
+    // The stake is locked and its proposal still pending, yet it leaves whole
+    process_transaction(
+        &mut context.banks_client,
+        &user,
+        vec![emergency_withdraw_ix],
+        vec![],
+    )
+    .await;
+    assert_eq!(
+        token_balance(&mut context.banks_client, user_commons_account).await,
+        500_000
+    );
+    assert!(context
+        .banks_client
+        .get_account(stake_account)
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        fetch_cv_config(&mut context.banks_client, cv_config)
+            .await
+            .total_staked,
+        0
+    );
+    assert_eq!(
+        fetch_proposal(&mut context.banks_client, proposal)
+            .await
+            .current_conviction,
+        0
+    );
+}
+
+#[tokio::test]
+async fn emergency_withdraw_does_not_depend_on_event_only_accounts() {
+    let (mut context, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_context(default_cv_params()).await;
+    let payer = context.payer.insecure_clone();
+    let guardian = Keypair::new();
+    let set_guardian_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::SetGuardian {
+            cv_config,
+            authority: payer.pubkey(),
+        }
+        .to_account_metas(None),
+        data: cv_instruction::SetGuardian {
+            guardian: guardian.pubkey(),
+        }
+        .data(),
+    };
+    let abstain_proposal = Pubkey::find_program_address(
+        &[b"proposal", cv_config.as_ref(), &0u64.to_le_bytes()],
+        &CV_ID,
+    )
+    .0;
+    let create_abstain_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CreateAbstainProposal {
+            cv_config,
+            proposal: abstain_proposal,
+            authority: payer.pubkey(),
+            system_program: system_program::ID,
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CreateAbstainProposal {}.data(),
+    };
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![set_guardian_ix, create_abstain_ix],
+        vec![],
+    )
+    .await;
+
+    let beneficiary = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &commons_treasury,
+        &payer,
+        100_000,
+    )
+    .await;
+    let proposal = create_proposal_with_id(
+        &mut context.banks_client,
+        &payer,
+        cv_config,
+        beneficiary,
+        1,
+        40_000,
+    )
+    .await;
+
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut context.banks_client, &payer, vec![transfer], vec![]).await;
+    let user_commons_account = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        500_000,
+    )
+    .await;
+    let stake_account = Pubkey::find_program_address(
+        &[b"stake", user.pubkey().as_ref(), proposal.as_ref()],
+        &CV_ID,
+    )
+    .0;
+    let voter_account = Pubkey::find_program_address(
+        &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+        &CV_ID,
+    )
+    .0;
+    let stake_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::StakeTokens {
+            stake_account,
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: Some(abstain_proposal),
+            voter_account,
+            personhood: None,
+            commons_token_mint,
+            user_commons_token_account: user_commons_account,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::StakeTokensLocked {
+            amount: 400_000,
+            lock_duration: 10_000,
+        }
+        .data(),
+    };
+    process_transaction(&mut context.banks_client, &user, vec![stake_ix], vec![]).await;
+
+    let pause_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::GuardianAction {
+            cv_config,
+            guardian: guardian.pubkey(),
+        }
+        .to_account_metas(None),
+        data: cv_instruction::EmergencyPause {}.data(),
+    };
+    process_transaction(
+        &mut context.banks_client,
+        &payer,
+        vec![pause_ix],
+        vec![&guardian],
+    )
+    .await;
+
+    // Neither the payout treasury nor the abstain proposal needed for the
+    // event's threshold is right, yet the locked stake still leaves whole
+    let emergency_withdraw_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::WithdrawStake {
+            stake_account,
+            cv_config,
+            proposal,
+            commons_treasury: user_commons_account,
+            treasury_registry: None,
+            abstain_proposal: None,
+            voter_account,
+            user_commons_token_account: user_commons_account,
+            commons_token_mint,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::EmergencyWithdrawStake {}.data(),
+    };
+    let logs = process_transaction_logs(
+        &mut context.banks_client,
+        &user,
+        vec![emergency_withdraw_ix],
+        vec![],
+    )
+    .await;
+    let withdrawn = decode_events::<StakeWithdrawn>(&logs);
+    assert_eq!(withdrawn.len(), 1);
+    assert_eq!(withdrawn[0].amount, 400_000);
+    assert_eq!(withdrawn[0].threshold, None);
+    assert_eq!(
+        token_balance(&mut context.banks_client, user_commons_account).await,
+        500_000
+    );
+    assert!(context
+        .banks_client
+        .get_account(stake_account)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn quadratic_mode_weighs_root_of_stake_for_verified_people() {
+    let (mut context, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_context(default_cv_params()).await;
+    let payer = context.payer.insecure_clone();
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &commons_treasury,
+        &payer,
+        100_000,
+    )
+    .await;
+    let beneficiary = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    let proposal = create_proposal_with_id(
+        &mut context.banks_client,
+        &payer,
+        cv_config,
+        beneficiary,
+        0,
+        40_000,
+    )
+    .await;
+
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut context.banks_client, &payer, vec![transfer], vec![]).await;
+    let user_commons_account = create_token_account(
+        &mut context.banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut context.banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        10_000,
+    )
+    .await;
+
+    // Two-person set: the user and someone else
+    let user_leaf = hashv(&[user.pubkey().as_ref()]).to_bytes();
+    let other_leaf = hashv(&[Pubkey::new_unique().as_ref()]).to_bytes();
+    let personhood_root = if user_leaf <= other_leaf {
+        hashv(&[&user_leaf, &other_leaf]).to_bytes()
+    } else {
+        hashv(&[&other_leaf, &user_leaf]).to_bytes()
+    };
+    let params_accounts = cv_accounts::SetConvictionParams {
+        cv_config,
+        authority: payer.pubkey(),
+    }
+    .to_account_metas(None);
+    let configure_ixs = vec![
+        Instruction {
+            program_id: CV_ID,
+            accounts: params_accounts.clone(),
+            data: cv_instruction::SetConvictionMode {
+                conviction_mode: ConvictionMode::Quadratic,
+            }
+            .data(),
+        },
+        Instruction {
+            program_id: CV_ID,
+            accounts: params_accounts.clone(),
+            data: cv_instruction::SetPersonhoodRoot { personhood_root }.data(),
+        },
+    ];
+    process_transaction(&mut context.banks_client, &payer, configure_ixs, vec![]).await;
+
+    let personhood = Pubkey::find_program_address(
+        &[b"personhood", cv_config.as_ref(), user.pubkey().as_ref()],
+        &CV_ID,
+    )
+    .0;
+    let stake_ix = |personhood: Option<Pubkey>, amount: u64| Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::StakeTokens {
+            stake_account: Pubkey::find_program_address(
+                &[b"stake", user.pubkey().as_ref(), proposal.as_ref()],
+                &CV_ID,
+            )
+            .0,
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: None,
+            voter_account: Pubkey::find_program_address(
+                &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                &CV_ID,
+            )
+            .0,
+            personhood,
+            commons_token_mint,
+            user_commons_token_account: user_commons_account,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::StakeTokens { amount }.data(),
+    };
+    expect_cv_error(
+        &mut context.banks_client,
+        &user,
+        vec![stake_ix(None, 400)],
+        vec![],
+        CustomError::NotVerifiedPerson,
+    )
+    .await;
+
+    let verify_ix = |proof: Vec<[u8; 32]>| Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::VerifyPersonhood {
+            cv_config,
+            personhood,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::VerifyPersonhood { proof }.data(),
+    };
+    expect_cv_error(
+        &mut context.banks_client,
+        &user,
+        vec![verify_ix(vec![user_leaf])],
+        vec![],
+        CustomError::InvalidPersonhoodProof,
+    )
+    .await;
+    process_transaction(
+        &mut context.banks_client,
+        &user,
+        vec![verify_ix(vec![other_leaf])],
+        vec![],
+    )
+    .await;
+
+    // 400 tokens count as 20, in CV_SCALE fixed point
+    process_transaction(
+        &mut context.banks_client,
+        &user,
+        vec![stake_ix(Some(personhood), 400)],
+        vec![],
+    )
+    .await;
+    assert_eq!(
+        fetch_proposal(&mut context.banks_client, proposal)
+            .await
+            .current_conviction,
+        20_000_000
+    );
+    // Topping up to 900 re-roots the whole position rather than adding a second root
+    process_transaction(
+        &mut context.banks_client,
+        &user,
+        vec![stake_ix(Some(personhood), 500)],
+        vec![],
+    )
+    .await;
+    assert_eq!(
+        fetch_proposal(&mut context.banks_client, proposal)
+            .await
+            .current_conviction,
+        30_000_000
+    );
+    assert_eq!(
+        fetch_cv_config(&mut context.banks_client, cv_config)
+            .await
+            .total_staked,
+        30_000_000
+    );
+
+    expect_cv_error(
+        &mut context.banks_client,
+        &payer,
+        vec![Instruction {
+            program_id: CV_ID,
+            accounts: params_accounts,
+            data: cv_instruction::SetConvictionMode {
+                conviction_mode: ConvictionMode::Linear,
+            }
+            .data(),
+        }],
+        vec![],
+        CustomError::ConvictionModeLocked,
+    )
+    .await;
+
+    // A deposit allocated to the same proposal is rooted together with the stake:
+    // 900 staked plus 1,600 allocated counts as 50, not 30 + 40
+    let voter_account = Pubkey::find_program_address(
+        &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+        &CV_ID,
+    )
+    .0;
+    let stake_account = Pubkey::find_program_address(
+        &[b"stake", user.pubkey().as_ref(), proposal.as_ref()],
+        &CV_ID,
+    )
+    .0;
+    let deposit_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::DepositTokens {
+            cv_config,
+            voter_account,
+            personhood: Some(personhood),
+            commons_token_mint,
+            user_commons_token_account: user_commons_account,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None),
+        data: cv_instruction::DepositTokens { amount: 1_600 }.data(),
+    };
+    let reallocate_ix = |amount: u64| {
+        let mut accounts = cv_accounts::ReallocateSupport {
+            cv_config,
+            voter_account,
+            authority: user.pubkey(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None);
+        accounts.push(AccountMeta::new(proposal, false));
+        accounts.push(AccountMeta::new(stake_account, false));
+        Instruction {
+            program_id: CV_ID,
+            accounts,
+            data: cv_instruction::ReallocateSupport {
+                allocations: vec![SupportAllocation { proposal, amount }],
+            }
+            .data(),
+        }
+    };
+    process_transaction(
+        &mut context.banks_client,
+        &user,
+        vec![deposit_ix, reallocate_ix(1_600)],
+        vec![],
+    )
+    .await;
+    assert_eq!(
+        fetch_cv_config(&mut context.banks_client, cv_config)
+            .await
+            .total_staked,
+        50_000_000
+    );
+
+    // Unstaking releases only what the stake adds on top of the allocation
+    let unstake_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::UnstakeTokens {
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: None,
+            voter_account,
+            stake_account,
+            user_commons_token_account: user_commons_account,
+            commons_token_mint,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::UnstakeTokens { amount: 900 }.data(),
+    };
+    process_transaction(&mut context.banks_client, &user, vec![unstake_ix], vec![]).await;
+    assert_eq!(
+        fetch_cv_config(&mut context.banks_client, cv_config)
+            .await
+            .total_staked,
+        40_000_000
+    );
+    process_transaction(
+        &mut context.banks_client,
+        &user,
+        vec![reallocate_ix(0)],
+        vec![],
+    )
+    .await;
+    assert_eq!(
+        fetch_cv_config(&mut context.banks_client, cv_config)
+            .await
+            .total_staked,
+        0
+    );
+}
+
+#[tokio::test]
+async fn finished_proposal_and_empty_stakes_return_rent() {
+    let (mut banks_client, payer, commons_token_mint, commons_treasury, cv_config, staking_vault) =
+        setup_conviction_env().await;
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
+
+    let beneficiary = create_token_account(
+        &mut banks_client,
+        &payer,
+        &payer.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    let proposal =
+        create_proposal_with_id(&mut banks_client, &payer, cv_config, beneficiary, 0, 10_000).await;
+    let user_commons_account = create_token_account(
+        &mut banks_client,
+        &payer,
+        &user.pubkey(),
+        &commons_token_mint,
+    )
+    .await;
+    mint_to_account(
+        &mut banks_client,
+        &payer,
+        &commons_token_mint,
+        &user_commons_account,
+        &payer,
+        5_000,
+    )
+    .await;
+    stake_on_proposal(
+        &mut banks_client,
+        &user,
+        cv_config,
+        proposal,
+        commons_token_mint,
+        user_commons_account,
+        staking_vault,
+        5_000,
+    )
+    .await;
+    assert_eq!(
+        fetch_proposal(&mut banks_client, proposal)
+            .await
+            .stake_count,
+        1
+    );
+
+    let stake_account = Pubkey::find_program_address(
+        &[b"stake", user.pubkey().as_ref(), proposal.as_ref()],
+        &CV_ID,
+    )
+    .0;
+    let close_proposal_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CloseProposal {
+            cv_config,
+            proposal,
+            creator: payer.pubkey(),
+            proposal_instructions: Pubkey::find_program_address(
+                &[b"proposal_instructions", proposal.as_ref()],
+                &CV_ID,
+            )
+            .0,
+            funding_stream: Pubkey::find_program_address(
+                &[b"funding_stream", proposal.as_ref()],
+                &CV_ID,
+            )
+            .0,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CloseProposal {}.data(),
+    };
+    let close_stakes_ix = |pairs: &[(Pubkey, Pubkey)]| Instruction {
+        program_id: CV_ID,
+        accounts: pairs
+            .iter()
+            .flat_map(|(stake, owner)| {
+                [
+                    AccountMeta::new(*stake, false),
+                    AccountMeta::new(*owner, false),
+                ]
+            })
+            .collect(),
+        data: cv_instruction::CloseEmptyStakes {}.data(),
+    };
+
+    expect_cv_error(
+        &mut banks_client,
+        &payer,
+        vec![close_proposal_ix.clone()],
+        vec![],
+        CustomError::ProposalNotFinished,
+    )
+    .await;
+
+    // Fully unstaking leaves an empty stake account behind
+    let unstake_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::UnstakeTokens {
+            cv_config,
+            proposal,
+            commons_treasury,
+            treasury_registry: None,
+            abstain_proposal: None,
+            voter_account: Pubkey::find_program_address(
+                &[b"voter", cv_config.as_ref(), user.pubkey().as_ref()],
+                &CV_ID,
+            )
+            .0,
+            stake_account,
+            user_commons_token_account: user_commons_account,
+            commons_token_mint,
+            staking_vault,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::UnstakeTokens { amount: 2_000 }.data(),
+    };
+    process_transaction(&mut banks_client, &user, vec![unstake_ix.clone()], vec![]).await;
+
+    // The stake can only be drawn down against the proposal it backs
+    let other_proposal =
+        create_proposal_with_id(&mut banks_client, &payer, cv_config, beneficiary, 1, 10_000).await;
+    let mut mismatched_unstake_ix = unstake_ix.clone();
+    mismatched_unstake_ix.accounts[1].pubkey = other_proposal;
+    let tx = signed_transaction(&mut banks_client, &user, &[mismatched_unstake_ix], vec![]).await;
+    let err = banks_client.process_transaction(tx).await.unwrap_err();
+    match err {
+        BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) => assert_eq!(code, AnchorErrorCode::ConstraintHasOne as u32),
+        _ => panic!("expected has_one failure, got {:?}", err),
+    }
+
+    expect_cv_error(
+        &mut banks_client,
+        &payer,
+        vec![close_stakes_ix(&[(stake_account, user.pubkey())])],
+        vec![],
+        CustomError::StakeNotEmpty,
+    )
+    .await;
+    let unstake_rest_ix = Instruction {
+        data: cv_instruction::UnstakeTokens { amount: 3_000 }.data(),
+        ..unstake_ix
+    };
+    process_transaction(&mut banks_client, &user, vec![unstake_rest_ix], vec![]).await;
+    assert_eq!(
+        fetch_proposal(&mut banks_client, proposal)
+            .await
+            .stake_count,
+        0
+    );
+
+    let cancel_ix = Instruction {
+        program_id: CV_ID,
+        accounts: cv_accounts::CancelProposal {
+            cv_config,
+            proposal,
+            creator: payer.pubkey(),
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: cv_instruction::CancelProposal {}.data(),
+    };
+    process_transaction(&mut banks_client, &payer, vec![cancel_ix], vec![]).await;
+
+    let logs =
+        process_transaction_logs(&mut banks_client, &payer, vec![close_proposal_ix], vec![]).await;
+    let closed = decode_events::<ProposalClosed>(&logs);
+    assert_eq!(closed.len(), 1);
+    assert_eq!(closed[0].proposal, proposal);
+    assert_eq!(closed[0].requested_amount, 10_000);
+    assert_eq!(closed[0].status, ProposalStatus::Cancelled);
+    assert!(banks_client.get_account(proposal).await.unwrap().is_none());
+
+    // The crank only pays rent to the stake's owner, even after its proposal is gone
+    expect_cv_error(
+        &mut banks_client,
+        &payer,
+        vec![close_stakes_ix(&[(stake_account, payer.pubkey())])],
+        vec![],
+        CustomError::StakeAccountsMismatch,
+    )
+    .await;
+    let user_lamports = banks_client.get_balance(user.pubkey()).await.unwrap();
+    let stake_rent = banks_client
+        .get_account(stake_account)
+        .await
+        .unwrap()
+        .expect("stake account missing")
+        .lamports;
+    process_transaction(
+        &mut banks_client,
+        &payer,
+        vec![close_stakes_ix(&[(stake_account, user.pubkey())])],
+        vec![],
+    )
+    .await;
+    assert!(banks_client
+        .get_account(stake_account)
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        banks_client.get_balance(user.pubkey()).await.unwrap(),
+        user_lamports + stake_rent
+    );
 }
@@ -2,45 +2,85 @@ use anchor_lang::prelude::*;
 use anchor_lang::solana_program::clock::Clock;
 use anchor_lang::solana_program::hash;
 use anchor_spl::associated_token::AssociatedToken;
-use anchor_spl::token::{self, Mint, MintTo, Token, TokenAccount, Transfer};
+use anchor_spl::token::{
+    self, spl_token, Mint, MintTo, SetAuthority, Token, TokenAccount, Transfer,
+};
 use commons_abc::{
-    self, cpi::accounts::InitializeCurve, program::CommonsAbc, CurveConfig, ID as COMMONS_ABC_ID,
+    self, cpi::accounts::InitializeCurve, program::CommonsAbc, ID as COMMONS_ABC_ID,
 };
 
 declare_id!("CPjQgH9wbaJsW57qB1aaHasgv6MZAgQLwF1D77WZm2Uv");
 
+// Scale of the hatch price: reserve units per commons token, 1.0 == HATCH_PRICE_SCALE
+pub const HATCH_PRICE_SCALE: u64 = 1_000_000;
+
+// Scale of the funding-pool share of the raise, 100% == FUNDING_POOL_SCALE
+pub const FUNDING_POOL_SCALE: u64 = 1_000_000;
+
 #[program]
 pub mod commons_hatch {
     use super::*;
 
+    /// Creates hatch `hatch_id` of the signing creator for `reserve_asset_mint`.
+    /// Each creator can run any number of hatches per reserve mint side by side.
+    /// Contributors buy in at `initial_price` reserve units per commons token,
+    /// scaled by `HATCH_PRICE_SCALE`, and the curve is later initialized with
+    /// `kappa`, `exponent` and `friction`. On success `funding_pool_share`
+    /// (scaled by `FUNDING_POOL_SCALE`) of the raise goes to `funding_pool` and
+    /// the rest becomes the curve reserve.
+    /// Contributor tokens vest linearly over `vesting_duration_slots` from the
+    /// close slot, with nothing claimable before `vesting_cliff_slots`.
+    #[allow(clippy::too_many_arguments)]
     pub fn initialize_hatch(
         ctx: Context<InitializeHatch>,
+        hatch_id: u64,
         min_raise: u64,
         max_raise: u64,
+        initial_price: u64,
+        kappa: u64,
+        exponent: u64,
+        friction: u64,
         open_slot: u64,
         close_slot: u64,
         merkle_root: [u8; 32],
+        funding_pool_share: u64,
+        vesting_cliff_slots: u64,
+        vesting_duration_slots: u64,
     ) -> Result<()> {
+        require!(min_raise <= max_raise, HatchError::InvalidRaiseBounds);
+        require!(initial_price > 0, HatchError::InvalidHatchPrice);
+        require!(
+            funding_pool_share < FUNDING_POOL_SCALE,
+            HatchError::InvalidFundingPoolShare
+        );
+        require!(
+            vesting_cliff_slots <= vesting_duration_slots,
+            HatchError::InvalidVestingSchedule
+        );
         let hatch_config = &mut ctx.accounts.hatch_config;
+        hatch_config.creator = ctx.accounts.authority.key();
+        hatch_config.hatch_id = hatch_id;
         hatch_config.reserve_asset_mint = ctx.accounts.reserve_asset_mint.key();
         hatch_config.min_raise = min_raise;
         hatch_config.max_raise = max_raise;
+        hatch_config.initial_price = initial_price;
+        hatch_config.kappa = kappa;
+        hatch_config.exponent = exponent;
+        hatch_config.friction = friction;
         hatch_config.open_slot = open_slot;
         hatch_config.close_slot = close_slot;
         hatch_config.merkle_root = merkle_root;
+        hatch_config.funding_pool = ctx.accounts.funding_pool.key();
+        hatch_config.funding_pool_share = funding_pool_share;
+        hatch_config.vesting_cliff_slots = vesting_cliff_slots;
+        hatch_config.vesting_duration_slots = vesting_duration_slots;
         hatch_config.total_raised = 0;
+        hatch_config.initial_supply = 0;
         hatch_config.finalized = false;
         hatch_config.failed = false;
         hatch_config.hatch_config_bump = ctx.bumps.hatch_config; // Store the bump
         hatch_config.hatch_vault = ctx.accounts.hatch_vault.key(); // Store hatch_vault key
-        let (_vault_key, vault_bump) = Pubkey::find_program_address(
-            &[
-                b"hatch_vault",
-                ctx.accounts.reserve_asset_mint.key().as_ref(),
-            ],
-            ctx.program_id,
-        );
-        hatch_config.hatch_vault_bump = vault_bump;
+        hatch_config.hatch_vault_bump = ctx.bumps.hatch_vault;
         hatch_config.commons_token_mint = Pubkey::default();
         hatch_config.commons_token_mint_bump = 0;
         hatch_config.curve_config = Pubkey::default();
@@ -73,6 +113,13 @@ pub mod commons_hatch {
         // Verify Merkle proof
         require!(allowed_allocation > 0, HatchError::InvalidAllocation);
         require!(amount > 0, HatchError::InvalidContributionAmount);
+        // The contribution that would cross max_raise is only partially filled
+        let amount = amount.min(
+            hatch_config
+                .max_raise
+                .saturating_sub(hatch_config.total_raised),
+        );
+        require!(amount > 0, HatchError::HatchFull);
         let leaf = get_leaf_from_contributor_and_allocation(
             &ctx.accounts.authority.key(),
             allowed_allocation,
@@ -114,19 +161,24 @@ pub mod commons_hatch {
 
         // Update total raised
         let hatch_config = &mut ctx.accounts.hatch_config;
-        hatch_config.total_raised += amount;
+        hatch_config.total_raised = hatch_config
+            .total_raised
+            .checked_add(amount)
+            .ok_or(HatchError::AllocationExceeded)?;
 
         Ok(())
     }
 
-    pub fn finalize_hatch(
-        ctx: Context<FinalizeHatch>,
-        kappa: u64,
-        exponent: u64,
-        initial_price: u64,
-        friction: u64,
-    ) -> Result<()> {
+    /// Closes the hatch once `close_slot` is reached; anyone can call it. A
+    /// successful one sells its raise at the hatch price set at initialization,
+    /// seeding the curve configured there with that reserve and the resulting
+    /// initial supply. The raise leaves the hatch vault for
+    /// the curve reserve vault and the funding pool, and the initial supply is
+    /// minted into the hatch token vault before mint authority passes to the
+    /// curve.
+    pub fn finalize_hatch(ctx: Context<FinalizeHatch>) -> Result<()> {
         let hatch_config = &mut ctx.accounts.hatch_config;
+        let initial_price = hatch_config.initial_price;
         let clock = Clock::get()?;
 
         require!(!hatch_config.finalized, HatchError::AlreadyFinalized);
@@ -140,10 +192,18 @@ pub mod commons_hatch {
             return Ok(());
         }
 
-        require!(
-            hatch_config.total_raised <= hatch_config.max_raise,
-            HatchError::RaiseTooHigh
-        );
+        // `contribute` never lets the raise pass max_raise
+        let total_raised = hatch_config.total_raised;
+        let initial_supply =
+            u64::try_from(total_raised as u128 * HATCH_PRICE_SCALE as u128 / initial_price as u128)
+                .map_err(|_| error!(HatchError::InvalidHatchPrice))?;
+        let funding_pool_amount = u64::try_from(
+            total_raised as u128 * hatch_config.funding_pool_share as u128
+                / FUNDING_POOL_SCALE as u128,
+        )
+        .map_err(|_| error!(HatchError::InvalidFundingPoolShare))?;
+        let reserve_amount = total_raised - funding_pool_amount;
+        require!(reserve_amount > 0, HatchError::EmptyReserve);
 
         require!(
             ctx.accounts.reserve_asset_mint.key() == hatch_config.reserve_asset_mint,
@@ -179,24 +239,85 @@ pub mod commons_hatch {
         );
         commons_abc::cpi::initialize_curve(
             cpi_ctx,
-            kappa,
-            exponent,
+            hatch_config.kappa,
+            hatch_config.exponent,
             initial_price,
-            friction,
-            hatch_config.total_raised,
-            hatch_config.total_raised,
+            hatch_config.friction,
+            reserve_amount,
+            initial_supply,
         )?;
 
-        let (_commons_token_mint_key, commons_token_mint_bump) = Pubkey::find_program_address(
-            &[b"commons_token_mint", hatch_config.key().as_ref()],
-            ctx.program_id,
+        let hatch_id = hatch_config.hatch_id.to_le_bytes();
+        let seeds = &[
+            b"hatch_config".as_ref(),
+            hatch_config.creator.as_ref(),
+            hatch_config.reserve_asset_mint.as_ref(),
+            hatch_id.as_ref(),
+            &[hatch_config.hatch_config_bump],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.hatch_vault.to_account_info(),
+            to: ctx.accounts.reserve_vault.to_account_info(),
+            authority: hatch_config.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            cpi_accounts,
+            signer,
+        );
+        token::transfer(cpi_ctx, reserve_amount)?;
+
+        if funding_pool_amount > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.hatch_vault.to_account_info(),
+                to: ctx.accounts.funding_pool.to_account_info(),
+                authority: hatch_config.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                cpi_accounts,
+                signer,
+            );
+            token::transfer(cpi_ctx, funding_pool_amount)?;
+        }
+
+        let cpi_accounts = MintTo {
+            mint: ctx.accounts.commons_token_mint.to_account_info(),
+            to: ctx.accounts.hatch_token_vault.to_account_info(),
+            authority: hatch_config.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            cpi_accounts,
+            signer,
+        );
+        token::mint_to(cpi_ctx, initial_supply)?;
+
+        // From here on only the curve mints commons tokens
+        let cpi_accounts = SetAuthority {
+            current_authority: hatch_config.to_account_info(),
+            account_or_mint: ctx.accounts.commons_token_mint.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            cpi_accounts,
+            signer,
         );
+        token::set_authority(
+            cpi_ctx,
+            spl_token::instruction::AuthorityType::MintTokens,
+            Some(curve_config_key),
+        )?;
+
         hatch_config.commons_token_mint = ctx.accounts.commons_token_mint.key();
-        hatch_config.commons_token_mint_bump = commons_token_mint_bump;
+        hatch_config.commons_token_mint_bump = ctx.bumps.commons_token_mint;
+        hatch_config.hatch_token_vault = ctx.accounts.hatch_token_vault.key();
         hatch_config.curve_config = curve_config_key;
         hatch_config.curve_config_bump = curve_config_bump;
         hatch_config.reserve_vault = ctx.accounts.reserve_vault.key();
         hatch_config.commons_treasury = ctx.accounts.commons_treasury.key();
+        hatch_config.initial_supply = initial_supply;
         hatch_config.failed = false;
         hatch_config.finalized = true;
 
@@ -211,6 +332,7 @@ pub mod commons_hatch {
         if !hatch_config.failed {
             return err!(HatchError::HatchNotFailed);
         }
+        let refund_amount = contribution.amount;
 
         // Check if the contribution has already been refunded
         if contribution.refunded {
@@ -223,27 +345,34 @@ pub mod commons_hatch {
             authority: hatch_config.to_account_info(),
         };
         let cpi_program = ctx.accounts.token_program.to_account_info();
+        let hatch_id = hatch_config.hatch_id.to_le_bytes();
         let seeds = &[
-            b"hatch_config",
+            b"hatch_config".as_ref(),
+            hatch_config.creator.as_ref(),
             hatch_config.reserve_asset_mint.as_ref(),
+            hatch_id.as_ref(),
             &[hatch_config.hatch_config_bump],
         ];
         let signer = &[&seeds[..]];
         let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-        token::transfer(cpi_ctx, contribution.amount)?;
+        token::transfer(cpi_ctx, refund_amount)?;
 
         contribution.refunded = true;
         hatch_config.total_refunded = hatch_config
             .total_refunded
-            .checked_add(contribution.amount)
+            .checked_add(refund_amount)
             .ok_or(HatchError::RefundOverflow)?;
 
         Ok(())
     }
 
+    /// Pays out the part of a contributor's hatch tokens vested at the current
+    /// slot that has not been claimed yet. Can be called repeatedly.
     pub fn claim(ctx: Context<Claim>) -> Result<()> {
         let hatch_config = &mut ctx.accounts.hatch_config;
         let contribution = &mut ctx.accounts.contribution;
+        let vesting_position = &mut ctx.accounts.vesting_position;
+        let clock = Clock::get()?;
 
         // Check if the hatch succeeded
         if !hatch_config.finalized || hatch_config.failed {
@@ -255,27 +384,34 @@ pub mod commons_hatch {
             return err!(HatchError::AlreadyClaimed);
         }
 
-        let mint_amount = contribution.amount;
-        require!(mint_amount > 0, HatchError::EmptyContribution);
+        let total_amount = hatch_tokens_for(hatch_config, contribution.amount)?;
+        require!(total_amount > 0, HatchError::EmptyContribution);
+        vesting_position.total_amount = total_amount;
 
-        let commons_token_mint_key = ctx.accounts.commons_token_mint.key();
-        let curve_seeds = &[
-            b"curve_config",
-            commons_token_mint_key.as_ref(),
-            &[hatch_config.curve_config_bump],
-        ];
-        let signer_seeds = &[&curve_seeds[..]];
+        let vested = vested_amount(hatch_config, total_amount, clock.slot)?;
+        let mint_amount = vested.saturating_sub(vesting_position.claimed_amount);
+        require!(mint_amount > 0, HatchError::NothingVested);
 
-        let cpi_accounts = MintTo {
-            mint: ctx.accounts.commons_token_mint.to_account_info(),
+        let hatch_id = hatch_config.hatch_id.to_le_bytes();
+        let seeds = &[
+            b"hatch_config".as_ref(),
+            hatch_config.creator.as_ref(),
+            hatch_config.reserve_asset_mint.as_ref(),
+            hatch_id.as_ref(),
+            &[hatch_config.hatch_config_bump],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.hatch_token_vault.to_account_info(),
             to: ctx.accounts.user_commons_token_account.to_account_info(),
-            authority: ctx.accounts.curve_config.to_account_info(),
+            authority: hatch_config.to_account_info(),
         };
         let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-        token::mint_to(cpi_ctx, mint_amount)?;
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, mint_amount)?;
 
-        contribution.claimed = true;
+        vesting_position.claimed_amount = vested;
+        contribution.claimed = vested == total_amount;
 
         Ok(())
     }
@@ -298,8 +434,9 @@ pub mod commons_hatch {
 }
 
 #[derive(Accounts)]
+#[instruction(hatch_id: u64)]
 pub struct InitializeHatch<'info> {
-    #[account(init, payer = authority, space = 8 + 32 + 8 + 8 + 8 + 8 + 32 + 8 + 1 + 1 + 1 + 32 + 1 + 32 + 1 + 32 + 1 + 32 + 32 + 8, seeds = [b"hatch_config", reserve_asset_mint.key().as_ref()], bump)]
+    #[account(init, payer = authority, space = 8 + 32 + 8 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 32 + 8 + 8 + 1 + 1 + 1 + 32 + 1 + 32 + 1 + 32 + 1 + 32 + 32 + 32 + 8 + 32 + 8 + 8 + 8, seeds = [b"hatch_config", authority.key().as_ref(), reserve_asset_mint.key().as_ref(), &hatch_id.to_le_bytes()], bump)]
     // Added PDAs for hatch vault + ABC state
     pub hatch_config: Account<'info, HatchConfig>,
     pub reserve_asset_mint: Account<'info, Mint>,
@@ -308,10 +445,13 @@ pub struct InitializeHatch<'info> {
         payer = authority,
         token::mint = reserve_asset_mint,
         token::authority = hatch_config,
-        seeds = [b"hatch_vault", reserve_asset_mint.key().as_ref()],
+        seeds = [b"hatch_vault", hatch_config.key().as_ref()],
         bump
     )]
     pub hatch_vault: Account<'info, TokenAccount>,
+    // Receives the funding-pool share of a successful raise
+    #[account(token::mint = reserve_asset_mint)]
+    pub funding_pool: Account<'info, TokenAccount>,
     #[account(mut)]
     pub authority: Signer<'info>,
     pub system_program: Program<'info, System>,
@@ -321,19 +461,23 @@ pub struct InitializeHatch<'info> {
 
 #[derive(Accounts)]
 pub struct Contribute<'info> {
-    #[account(mut)]
+    #[account(
+        mut,
+        seeds = [b"hatch_config", hatch_config.creator.as_ref(), hatch_config.reserve_asset_mint.as_ref(), &hatch_config.hatch_id.to_le_bytes()],
+        bump = hatch_config.hatch_config_bump
+    )]
     pub hatch_config: Account<'info, HatchConfig>,
     #[account(
         init_if_needed,
         payer = authority,
         space = 8 + 8 + 1 + 1 + 8, // Added 1 byte for claimed and 8 bytes for max_allocation
-        seeds = [b"contribution", authority.key().as_ref()],
+        seeds = [b"contribution", hatch_config.key().as_ref(), authority.key().as_ref()],
         bump
     )]
     pub contribution: Account<'info, Contribution>,
     #[account(
         mut,
-        seeds = [b"hatch_vault", hatch_config.reserve_asset_mint.as_ref()], // Updated seeds
+        seeds = [b"hatch_vault", hatch_config.key().as_ref()],
         bump = hatch_config.hatch_vault_bump, // Use dedicated bump for hatch_vault
     )]
     pub hatch_vault: Account<'info, TokenAccount>,
@@ -346,12 +490,23 @@ pub struct Contribute<'info> {
 }
 
 #[derive(Accounts)]
-#[instruction(kappa: u64, exponent: u64, initial_price: u64, friction: u64)]
 pub struct FinalizeHatch<'info> {
-    #[account(mut)]
+    #[account(
+        mut,
+        seeds = [b"hatch_config", hatch_config.creator.as_ref(), hatch_config.reserve_asset_mint.as_ref(), &hatch_config.hatch_id.to_le_bytes()],
+        bump = hatch_config.hatch_config_bump
+    )]
     pub hatch_config: Account<'info, HatchConfig>,
     #[account(mut, address = hatch_config.reserve_asset_mint)]
     pub reserve_asset_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"hatch_vault", hatch_config.key().as_ref()],
+        bump = hatch_config.hatch_vault_bump,
+    )]
+    pub hatch_vault: Account<'info, TokenAccount>,
+    #[account(mut, address = hatch_config.funding_pool)]
+    pub funding_pool: Account<'info, TokenAccount>,
     #[account(mut)]
     pub authority: Signer<'info>,
     #[account(mut)]
@@ -359,18 +514,27 @@ pub struct FinalizeHatch<'info> {
     #[account(
         init,
         payer = authority,
-        mint::authority = curve_config,
+        mint::authority = hatch_config,
         mint::decimals = 6,
         seeds = [b"commons_token_mint", hatch_config.key().as_ref()],
         bump,
     )]
     pub commons_token_mint: Account<'info, Mint>,
-    /// CHECK: Created by the CPI in `commons_abc::initialize_curve`
+    // Holds the initial supply until contributors claim it
+    #[account(
+        init,
+        payer = authority,
+        token::mint = commons_token_mint,
+        token::authority = hatch_config,
+        seeds = [b"hatch_token_vault", hatch_config.key().as_ref()],
+        bump,
+    )]
+    pub hatch_token_vault: Account<'info, TokenAccount>,
+    // Fresh keypair accounts created by the CPI in `commons_abc::initialize_curve`
     #[account(mut)]
-    pub reserve_vault: UncheckedAccount<'info>,
-    /// CHECK: Created by the CPI in `commons_abc::initialize_curve`
+    pub reserve_vault: Signer<'info>,
     #[account(mut)]
-    pub commons_treasury: UncheckedAccount<'info>,
+    pub commons_treasury: Signer<'info>,
     pub commons_abc_program: Program<'info, CommonsAbc>,
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>,
@@ -379,17 +543,21 @@ pub struct FinalizeHatch<'info> {
 
 #[derive(Accounts)]
 pub struct Refund<'info> {
-    #[account(mut)]
+    #[account(
+        mut,
+        seeds = [b"hatch_config", hatch_config.creator.as_ref(), hatch_config.reserve_asset_mint.as_ref(), &hatch_config.hatch_id.to_le_bytes()],
+        bump = hatch_config.hatch_config_bump
+    )]
     pub hatch_config: Account<'info, HatchConfig>,
     #[account(
         mut,
-        seeds = [b"contribution", authority.key().as_ref()],
+        seeds = [b"contribution", hatch_config.key().as_ref(), authority.key().as_ref()],
         bump
     )]
     pub contribution: Account<'info, Contribution>,
     #[account(
         mut,
-        seeds = [b"hatch_vault", hatch_config.reserve_asset_mint.as_ref()],
+        seeds = [b"hatch_vault", hatch_config.key().as_ref()],
         bump = hatch_config.hatch_vault_bump,
     )]
     pub hatch_vault: Account<'info, TokenAccount>,
@@ -402,22 +570,30 @@ pub struct Refund<'info> {
 
 #[derive(Accounts)]
 pub struct Claim<'info> {
-    #[account(mut)]
+    #[account(
+        mut,
+        seeds = [b"hatch_config", hatch_config.creator.as_ref(), hatch_config.reserve_asset_mint.as_ref(), &hatch_config.hatch_id.to_le_bytes()],
+        bump = hatch_config.hatch_config_bump
+    )]
     pub hatch_config: Account<'info, HatchConfig>,
     #[account(
         mut,
-        seeds = [b"contribution", authority.key().as_ref()],
+        seeds = [b"contribution", hatch_config.key().as_ref(), authority.key().as_ref()],
         bump
     )]
     pub contribution: Account<'info, Contribution>,
-    #[account(mut, address = hatch_config.commons_token_mint)]
-    pub commons_token_mint: Account<'info, Mint>,
     #[account(
-        mut,
-        seeds = [b"curve_config", commons_token_mint.key().as_ref()],
-        bump = hatch_config.curve_config_bump
+        init_if_needed,
+        payer = authority,
+        space = 8 + 8 + 8,
+        seeds = [b"vesting", hatch_config.key().as_ref(), authority.key().as_ref()],
+        bump
     )]
-    pub curve_config: Account<'info, CurveConfig>,
+    pub vesting_position: Account<'info, VestingPosition>,
+    #[account(address = hatch_config.commons_token_mint)]
+    pub commons_token_mint: Account<'info, Mint>,
+    #[account(mut, address = hatch_config.hatch_token_vault)]
+    pub hatch_token_vault: Account<'info, TokenAccount>,
     #[account(
         init_if_needed,
         payer = authority,
@@ -443,13 +619,24 @@ pub struct CloseHatch<'info> {
 
 #[account]
 pub struct HatchConfig {
+    pub creator: Pubkey,
+    pub hatch_id: u64, // Chosen by the creator, unique per creator and reserve mint
     pub reserve_asset_mint: Pubkey,
     pub min_raise: u64,
     pub max_raise: u64,
+    pub initial_price: u64, // Reserve units per commons token, scaled by HATCH_PRICE_SCALE
+    pub kappa: u64,         // Curve parameters handed to commons_abc at finalize
+    pub exponent: u64,
+    pub friction: u64,
     pub open_slot: u64,
     pub close_slot: u64,
     pub merkle_root: [u8; 32],
+    pub funding_pool: Pubkey, // Reserve-mint account paid the funding-pool share
+    pub funding_pool_share: u64, // Share of the raise for funding_pool, scaled by FUNDING_POOL_SCALE
+    pub vesting_cliff_slots: u64, // Slots after close_slot before any hatch tokens vest
+    pub vesting_duration_slots: u64, // Slots after close_slot until hatch tokens are fully vested
     pub total_raised: u64,
+    pub initial_supply: u64, // Commons tokens sold for total_raised at the hatch price
     pub finalized: bool,
     pub failed: bool,
     pub hatch_config_bump: u8,
@@ -457,6 +644,7 @@ pub struct HatchConfig {
     pub hatch_vault_bump: u8,
     pub commons_token_mint: Pubkey,
     pub commons_token_mint_bump: u8,
+    pub hatch_token_vault: Pubkey, // Holds the initial supply until contributors claim it
     pub curve_config: Pubkey,
     pub curve_config_bump: u8,
     pub commons_treasury: Pubkey,
@@ -469,7 +657,38 @@ pub struct Contribution {
     pub amount: u64,
     pub max_allocation: u64,
     pub refunded: bool,
-    pub claimed: bool, // Add this field
+    pub claimed: bool, // Set once every hatch token has vested and been claimed
+}
+
+#[account]
+pub struct VestingPosition {
+    pub total_amount: u64, // Hatch tokens owed to the contributor once fully vested
+    pub claimed_amount: u64, // Hatch tokens already paid out by claim
+}
+
+// A contributor's pro-rata share of the initial supply
+fn hatch_tokens_for(hatch_config: &HatchConfig, amount: u64) -> Result<u64> {
+    if hatch_config.total_raised == 0 {
+        return Ok(0);
+    }
+    let tokens =
+        amount as u128 * hatch_config.initial_supply as u128 / hatch_config.total_raised as u128;
+    u64::try_from(tokens).map_err(|_| error!(HatchError::InvalidHatchPrice))
+}
+
+// Hatch tokens vested at `slot`: none before the cliff, then linear from
+// close_slot over the vesting duration
+fn vested_amount(hatch_config: &HatchConfig, total_amount: u64, slot: u64) -> Result<u64> {
+    let elapsed = slot.saturating_sub(hatch_config.close_slot);
+    if elapsed < hatch_config.vesting_cliff_slots {
+        return Ok(0);
+    }
+    if elapsed >= hatch_config.vesting_duration_slots {
+        return Ok(total_amount);
+    }
+    let vested =
+        total_amount as u128 * elapsed as u128 / hatch_config.vesting_duration_slots as u128;
+    u64::try_from(vested).map_err(|_| error!(HatchError::InvalidVestingSchedule))
 }
 
 // Helper function to create a leaf from the contributor's public key and allowed allocation
@@ -518,8 +737,6 @@ pub enum HatchError {
     InvalidAllocation,
     #[msg("Invalid contribution amount.")]
     InvalidContributionAmount,
-    #[msg("Total raise exceeds the maximum allowed.")]
-    RaiseTooHigh,
     #[msg("Provided reserve mint does not match the hatch configuration.")]
     IncorrectReserveMint,
     #[msg("Curve config PDA does not match the commons ABC derivation.")]
@@ -538,4 +755,18 @@ pub enum HatchError {
     HatchFinalized,
     #[msg("Hatch has failed.")]
     HatchFailed,
+    #[msg("Hatch has reached its maximum raise.")]
+    HatchFull,
+    #[msg("Hatch price must be positive and keep the initial supply within u64.")]
+    InvalidHatchPrice,
+    #[msg("Funding-pool share must leave part of the raise for the curve reserve.")]
+    InvalidFundingPoolShare,
+    #[msg("Hatch raise leaves the curve reserve empty.")]
+    EmptyReserve,
+    #[msg("Vesting cliff cannot be longer than the vesting duration.")]
+    InvalidVestingSchedule,
+    #[msg("No vested hatch tokens left to claim.")]
+    NothingVested,
+    #[msg("Minimum raise cannot exceed the maximum raise.")]
+    InvalidRaiseBounds,
 }
@@ -1,33 +1,52 @@
 #![cfg(test)]
 
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    entrypoint::ProgramResult, hash::hashv, instruction::InstructionError, program_pack::Pack,
+    sysvar,
+};
 use anchor_lang::{InstructionData, ToAccountMetas};
-use commons_abc::ID as ABC_PROGRAM_ID;
+use commons_abc::{CurveConfig, ID as ABC_PROGRAM_ID};
 use commons_hatch::{
     self, accounts as hatch_accounts, instruction as hatch_instruction, Contribution, HatchConfig,
-    HatchError, ID as HATCH_PROGRAM_ID,
-};
-use solana_program::{
-    hash::hashv,
-    instruction::InstructionError,
-    program_pack::Pack,
-    system_instruction,
-    sysvar,
+    HatchError, VestingPosition, FUNDING_POOL_SCALE, HATCH_PRICE_SCALE, ID as HATCH_PROGRAM_ID,
 };
-use solana_program_test::{processor, BanksClient, ProgramTest};
+use solana_program_test::{processor, BanksClient, BanksClientError, ProgramTest};
 use solana_sdk::{
     instruction::{AccountMeta, Instruction},
     pubkey::Pubkey,
     signature::{Keypair, Signer},
     transaction::{Transaction, TransactionError},
-    transport::TransportError,
 };
+use solana_system_interface::{instruction as system_instruction, program as system_program};
 use spl_token::{
     instruction as token_instruction,
     state::{Account as TokenAccountState, Mint},
 };
 use std::str::FromStr;
 
+// Anchor's `entry` ties the account slice to the accounts' own lifetime,
+// which `processor!` cannot express, so the shims leak a copy of the slice
+fn hatch_processor(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    commons_hatch::entry(program_id, accounts, data)
+}
+
+fn abc_processor(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    commons_abc::entry(program_id, accounts, data)
+}
+
+fn hatch_program_test() -> ProgramTest {
+    let mut program = ProgramTest::new(
+        "commons_hatch",
+        HATCH_PROGRAM_ID,
+        processor!(hatch_processor),
+    );
+    program.add_program("commons_abc", ABC_PROGRAM_ID, processor!(abc_processor));
+    program
+}
+
 async fn process_transaction(
     banks_client: &mut BanksClient,
     payer: &Keypair,
@@ -64,12 +83,13 @@ async fn expect_hatch_error(
     );
     let err = banks_client.process_transaction(tx).await.unwrap_err();
     let tx_err = match err {
-        TransportError::TransactionError(tx_err) => tx_err,
+        BanksClientError::TransactionError(tx_err) => tx_err,
+        BanksClientError::SimulationError { err: tx_err, .. } => tx_err,
         _ => panic!("expected transaction error, got {:?}", err),
     };
     match tx_err {
         TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
-            assert_eq!(code, expected as u32);
+            assert_eq!(code, u32::from(expected));
         }
         _ => panic!("unexpected transaction error: {:?}", tx_err),
     }
@@ -142,6 +162,46 @@ fn merkle_leaf(contributor: &Pubkey, allocation: u64) -> [u8; 32] {
     hashv(&[&data]).to_bytes()
 }
 
+fn hatch_pdas(creator: &Pubkey, reserve_mint: &Pubkey, hatch_id: u64) -> (Pubkey, Pubkey) {
+    let hatch_config = Pubkey::find_program_address(
+        &[
+            b"hatch_config",
+            creator.as_ref(),
+            reserve_mint.as_ref(),
+            &hatch_id.to_le_bytes(),
+        ],
+        &HATCH_PROGRAM_ID,
+    )
+    .0;
+    let hatch_vault =
+        Pubkey::find_program_address(&[b"hatch_vault", hatch_config.as_ref()], &HATCH_PROGRAM_ID).0;
+    (hatch_config, hatch_vault)
+}
+
+fn contribution_pda(hatch_config: &Pubkey, contributor: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"contribution", hatch_config.as_ref(), contributor.as_ref()],
+        &HATCH_PROGRAM_ID,
+    )
+    .0
+}
+
+fn vesting_position_pda(hatch_config: &Pubkey, contributor: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"vesting", hatch_config.as_ref(), contributor.as_ref()],
+        &HATCH_PROGRAM_ID,
+    )
+    .0
+}
+
+fn hatch_token_vault_pda(hatch_config: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"hatch_token_vault", hatch_config.as_ref()],
+        &HATCH_PROGRAM_ID,
+    )
+    .0
+}
+
 fn associated_token_program_id() -> Pubkey {
     Pubkey::from_str("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL").unwrap()
 }
@@ -177,35 +237,23 @@ fn create_associated_token_account_instruction(
 
 #[tokio::test]
 async fn finalize_and_claim_mints_tokens() {
-    let mut program = ProgramTest::new(
-        "commons_hatch",
-        HATCH_PROGRAM_ID,
-        processor!(commons_hatch::entry),
-    );
-    program.add_program(
-        "commons_abc",
-        ABC_PROGRAM_ID,
-        processor!(commons_abc::entry),
-    );
-
-    let (mut banks_client, payer, _recent_blockhash) = program.start().await;
+    let (mut banks_client, payer, _recent_blockhash) = hatch_program_test().start().await;
     let user = Keypair::new();
 
     let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
     process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
 
     let reserve_mint = create_mint(&mut banks_client, &payer, &payer.pubkey()).await;
-    let hatch_config =
-        Pubkey::find_program_address(&[b"hatch_config", reserve_mint.as_ref()], &HATCH_PROGRAM_ID)
-            .0;
-    let hatch_vault =
-        Pubkey::find_program_address(&[b"hatch_vault", reserve_mint.as_ref()], &HATCH_PROGRAM_ID).0;
+    let (hatch_config, hatch_vault) = hatch_pdas(&payer.pubkey(), &reserve_mint, 0);
+    let funding_pool =
+        create_user_token_account(&mut banks_client, &payer, &payer, &reserve_mint).await;
 
     let merkle_root = merkle_leaf(&user.pubkey(), 100);
     let init_accounts = hatch_accounts::InitializeHatch {
         hatch_config,
         reserve_asset_mint: reserve_mint,
         hatch_vault,
+        funding_pool,
         authority: payer.pubkey(),
         system_program: system_program::ID,
         token_program: spl_token::id(),
@@ -215,11 +263,19 @@ async fn finalize_and_claim_mints_tokens() {
         program_id: HATCH_PROGRAM_ID,
         accounts: init_accounts.to_account_metas(None),
         data: hatch_instruction::InitializeHatch {
+            hatch_id: 0,
             min_raise: 50,
             max_raise: 200,
+            initial_price: HATCH_PRICE_SCALE,
+            kappa: 1,
+            exponent: 1,
+            friction: 0,
             open_slot: 0,
-            close_slot: 0,
+            close_slot: 1,
             merkle_root,
+            funding_pool_share: 0,
+            vesting_cliff_slots: 0,
+            vesting_duration_slots: 0,
         }
         .data(),
     };
@@ -237,10 +293,7 @@ async fn finalize_and_claim_mints_tokens() {
     )
     .await;
 
-    let (contribution, _) = Pubkey::find_program_address(
-        &[b"contribution", user.pubkey().as_ref()],
-        &HATCH_PROGRAM_ID,
-    );
+    let contribution = contribution_pda(&hatch_config, &user.pubkey());
     let contribute_accounts = hatch_accounts::Contribute {
         hatch_config,
         contribution,
@@ -262,11 +315,10 @@ async fn finalize_and_claim_mints_tokens() {
     };
     process_transaction(&mut banks_client, &payer, vec![contribute_ix], vec![&user]).await;
 
-    let commons_token_mint = Pubkey::find_program_address(
+    let (commons_token_mint, commons_token_mint_bump) = Pubkey::find_program_address(
         &[b"commons_token_mint", hatch_config.as_ref()],
         &HATCH_PROGRAM_ID,
-    )
-    .0;
+    );
     let curve_config = Pubkey::find_program_address(
         &[b"curve_config", commons_token_mint.as_ref()],
         &ABC_PROGRAM_ID,
@@ -278,9 +330,12 @@ async fn finalize_and_claim_mints_tokens() {
     let finalize_accounts = hatch_accounts::FinalizeHatch {
         hatch_config,
         reserve_asset_mint: reserve_mint,
+        hatch_vault,
+        funding_pool,
         authority: payer.pubkey(),
         curve_config,
         commons_token_mint,
+        hatch_token_vault: hatch_token_vault_pda(&hatch_config),
         reserve_vault: reserve_vault.pubkey(),
         commons_treasury: commons_treasury.pubkey(),
         commons_abc_program: ABC_PROGRAM_ID,
@@ -291,13 +346,7 @@ async fn finalize_and_claim_mints_tokens() {
     let finalize_ix = Instruction {
         program_id: HATCH_PROGRAM_ID,
         accounts: finalize_accounts.to_account_metas(None),
-        data: hatch_instruction::FinalizeHatch {
-            kappa: 1,
-            exponent: 1,
-            initial_price: 1,
-            friction: 0,
-        }
-        .data(),
+        data: hatch_instruction::FinalizeHatch {}.data(),
     };
     process_transaction(
         &mut banks_client,
@@ -316,13 +365,45 @@ async fn finalize_and_claim_mints_tokens() {
     let config = HatchConfig::try_deserialize(&mut data).unwrap();
     assert!(config.finalized);
     assert!(!config.failed);
+    assert_eq!(config.commons_token_mint_bump, commons_token_mint_bump);
+
+    // The hatch holds the initial supply for claims and only the curve mints from now on
+    let vault_account = banks_client
+        .get_account(hatch_token_vault_pda(&hatch_config))
+        .await
+        .unwrap()
+        .expect("hatch token vault missing");
+    let vault_state = TokenAccountState::unpack(&vault_account.data).unwrap();
+    assert_eq!(vault_state.amount, config.initial_supply);
+    let mint_account = banks_client
+        .get_account(commons_token_mint)
+        .await
+        .unwrap()
+        .expect("commons token mint missing");
+    let mint_state = Mint::unpack(&mint_account.data).unwrap();
+    assert_eq!(mint_state.supply, config.initial_supply);
+    assert_eq!(Option::from(mint_state.mint_authority), Some(curve_config));
+
+    // The curve is seeded with the parameters fixed at initialization
+    let curve_account = banks_client
+        .get_account(curve_config)
+        .await
+        .unwrap()
+        .expect("curve config missing");
+    let mut curve_data: &[u8] = &curve_account.data;
+    let curve_state = CurveConfig::try_deserialize(&mut curve_data).unwrap();
+    assert_eq!(curve_state.kappa, config.kappa);
+    assert_eq!(curve_state.exponent, config.exponent);
+    assert_eq!(curve_state.friction, config.friction);
+    assert_eq!(curve_state.initial_price, config.initial_price);
 
     let user_commons_account = associated_token_address(&user.pubkey(), &commons_token_mint);
     let claim_accounts = hatch_accounts::Claim {
         hatch_config,
         contribution,
+        vesting_position: vesting_position_pda(&hatch_config, &user.pubkey()),
         commons_token_mint,
-        curve_config,
+        hatch_token_vault: hatch_token_vault_pda(&hatch_config),
         user_commons_token_account: user_commons_account,
         authority: user.pubkey(),
         token_program: spl_token::id(),
@@ -357,35 +438,23 @@ async fn finalize_and_claim_mints_tokens() {
 
 #[tokio::test]
 async fn finalize_failure_triggers_refund_and_close() {
-    let mut program = ProgramTest::new(
-        "commons_hatch",
-        HATCH_PROGRAM_ID,
-        processor!(commons_hatch::entry),
-    );
-    program.add_program(
-        "commons_abc",
-        ABC_PROGRAM_ID,
-        processor!(commons_abc::entry),
-    );
-
-    let (mut banks_client, payer, _recent_blockhash) = program.start().await;
+    let (mut banks_client, payer, _recent_blockhash) = hatch_program_test().start().await;
     let user = Keypair::new();
 
     let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
     process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
 
     let reserve_mint = create_mint(&mut banks_client, &payer, &payer.pubkey()).await;
-    let hatch_config =
-        Pubkey::find_program_address(&[b"hatch_config", reserve_mint.as_ref()], &HATCH_PROGRAM_ID)
-            .0;
-    let hatch_vault =
-        Pubkey::find_program_address(&[b"hatch_vault", reserve_mint.as_ref()], &HATCH_PROGRAM_ID).0;
+    let (hatch_config, hatch_vault) = hatch_pdas(&payer.pubkey(), &reserve_mint, 0);
+    let funding_pool =
+        create_user_token_account(&mut banks_client, &payer, &payer, &reserve_mint).await;
 
     let merkle_root = merkle_leaf(&user.pubkey(), 100);
     let init_accounts = hatch_accounts::InitializeHatch {
         hatch_config,
         reserve_asset_mint: reserve_mint,
         hatch_vault,
+        funding_pool,
         authority: payer.pubkey(),
         system_program: system_program::ID,
         token_program: spl_token::id(),
@@ -395,11 +464,19 @@ async fn finalize_failure_triggers_refund_and_close() {
         program_id: HATCH_PROGRAM_ID,
         accounts: init_accounts.to_account_metas(None),
         data: hatch_instruction::InitializeHatch {
+            hatch_id: 0,
             min_raise: 50,
             max_raise: 200,
+            initial_price: HATCH_PRICE_SCALE,
+            kappa: 1,
+            exponent: 1,
+            friction: 0,
             open_slot: 0,
-            close_slot: 0,
+            close_slot: 1,
             merkle_root,
+            funding_pool_share: 0,
+            vesting_cliff_slots: 0,
+            vesting_duration_slots: 0,
         }
         .data(),
     };
@@ -417,10 +494,7 @@ async fn finalize_failure_triggers_refund_and_close() {
     )
     .await;
 
-    let (contribution, _) = Pubkey::find_program_address(
-        &[b"contribution", user.pubkey().as_ref()],
-        &HATCH_PROGRAM_ID,
-    );
+    let contribution = contribution_pda(&hatch_config, &user.pubkey());
     let contribute_accounts = hatch_accounts::Contribute {
         hatch_config,
         contribution,
@@ -455,35 +529,33 @@ async fn finalize_failure_triggers_refund_and_close() {
     let reserve_vault = Keypair::new();
     let commons_treasury = Keypair::new();
 
-    let finalize_accounts = hatch_accounts::FinalizeHatch {
-        hatch_config,
-        reserve_asset_mint: reserve_mint,
-        authority: payer.pubkey(),
-        curve_config,
-        commons_token_mint,
-        reserve_vault: reserve_vault.pubkey(),
-        commons_treasury: commons_treasury.pubkey(),
-        commons_abc_program: ABC_PROGRAM_ID,
-        system_program: system_program::ID,
-        token_program: spl_token::id(),
-        rent: sysvar::rent::ID,
-    };
-    let finalize_ix = Instruction {
+    let finalize_ix = |authority: Pubkey| Instruction {
         program_id: HATCH_PROGRAM_ID,
-        accounts: finalize_accounts.to_account_metas(None),
-        data: hatch_instruction::FinalizeHatch {
-            kappa: 1,
-            exponent: 1,
-            initial_price: 1,
-            friction: 0,
+        accounts: hatch_accounts::FinalizeHatch {
+            hatch_config,
+            reserve_asset_mint: reserve_mint,
+            hatch_vault,
+            funding_pool,
+            authority,
+            curve_config,
+            commons_token_mint,
+            hatch_token_vault: hatch_token_vault_pda(&hatch_config),
+            reserve_vault: reserve_vault.pubkey(),
+            commons_treasury: commons_treasury.pubkey(),
+            commons_abc_program: ABC_PROGRAM_ID,
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            rent: sysvar::rent::ID,
         }
-        .data(),
+        .to_account_metas(None),
+        data: hatch_instruction::FinalizeHatch {}.data(),
     };
+    // Any contributor can close a failed hatch, so refunds never wait on the creator
     process_transaction(
         &mut banks_client,
         &payer,
-        vec![finalize_ix],
-        vec![&reserve_vault, &commons_treasury],
+        vec![finalize_ix(user.pubkey())],
+        vec![&user, &reserve_vault, &commons_treasury],
     )
     .await;
 
@@ -546,21 +618,15 @@ async fn finalize_failure_triggers_refund_and_close() {
 
 #[tokio::test]
 async fn contribute_rejected_before_open() {
-    let mut program = ProgramTest::new(
-        "commons_hatch",
-        HATCH_PROGRAM_ID,
-        processor!(commons_hatch::entry),
-    );
-    program.add_program("commons_abc", ABC_PROGRAM_ID, processor!(commons_abc::entry));
-
-    let (mut banks_client, payer, _recent_blockhash) = program.start().await;
+    let (mut banks_client, payer, _recent_blockhash) = hatch_program_test().start().await;
     let user = Keypair::new();
     let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
     process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
 
     let reserve_mint = create_mint(&mut banks_client, &payer, &payer.pubkey()).await;
-    let hatch_config = Pubkey::find_program_address(&[b"hatch_config", reserve_mint.as_ref()], &HATCH_PROGRAM_ID).0;
-    let hatch_vault = Pubkey::find_program_address(&[b"hatch_vault", reserve_mint.as_ref()], &HATCH_PROGRAM_ID).0;
+    let (hatch_config, hatch_vault) = hatch_pdas(&payer.pubkey(), &reserve_mint, 0);
+    let funding_pool =
+        create_user_token_account(&mut banks_client, &payer, &payer, &reserve_mint).await;
 
     let merkle_root = merkle_leaf(&user.pubkey(), 100);
     let init_ix = Instruction {
@@ -569,6 +635,7 @@ async fn contribute_rejected_before_open() {
             hatch_config,
             reserve_asset_mint: reserve_mint,
             hatch_vault,
+            funding_pool,
             authority: payer.pubkey(),
             system_program: system_program::ID,
             token_program: spl_token::id(),
@@ -576,11 +643,19 @@ async fn contribute_rejected_before_open() {
         }
         .to_account_metas(None),
         data: hatch_instruction::InitializeHatch {
+            hatch_id: 0,
             min_raise: 50,
             max_raise: 200,
+            initial_price: HATCH_PRICE_SCALE,
+            kappa: 1,
+            exponent: 1,
+            friction: 0,
             open_slot: 10,
             close_slot: 20,
             merkle_root,
+            funding_pool_share: 0,
+            vesting_cliff_slots: 0,
+            vesting_duration_slots: 0,
         }
         .data(),
     };
@@ -598,11 +673,7 @@ async fn contribute_rejected_before_open() {
     )
     .await;
 
-    let contribution = Pubkey::find_program_address(
-        &[b"contribution", user.pubkey().as_ref()],
-        &HATCH_PROGRAM_ID,
-    )
-    .0;
+    let contribution = contribution_pda(&hatch_config, &user.pubkey());
     let contribute_ix = Instruction {
         program_id: HATCH_PROGRAM_ID,
         accounts: hatch_accounts::Contribute {
@@ -635,21 +706,17 @@ async fn contribute_rejected_before_open() {
 
 #[tokio::test]
 async fn contribute_rejected_after_close() {
-    let mut program = ProgramTest::new(
-        "commons_hatch",
-        HATCH_PROGRAM_ID,
-        processor!(commons_hatch::entry),
-    );
-    program.add_program("commons_abc", ABC_PROGRAM_ID, processor!(commons_abc::entry));
-
-    let (mut banks_client, payer, _recent_blockhash) = program.start().await;
+    let mut context = hatch_program_test().start_with_context().await;
+    let mut banks_client = context.banks_client.clone();
+    let payer = context.payer.insecure_clone();
     let user = Keypair::new();
     let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
     process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
 
     let reserve_mint = create_mint(&mut banks_client, &payer, &payer.pubkey()).await;
-    let hatch_config = Pubkey::find_program_address(&[b"hatch_config", reserve_mint.as_ref()], &HATCH_PROGRAM_ID).0;
-    let hatch_vault = Pubkey::find_program_address(&[b"hatch_vault", reserve_mint.as_ref()], &HATCH_PROGRAM_ID).0;
+    let (hatch_config, hatch_vault) = hatch_pdas(&payer.pubkey(), &reserve_mint, 0);
+    let funding_pool =
+        create_user_token_account(&mut banks_client, &payer, &payer, &reserve_mint).await;
 
     let merkle_root = merkle_leaf(&user.pubkey(), 100);
     let init_ix = Instruction {
@@ -658,6 +725,7 @@ async fn contribute_rejected_after_close() {
             hatch_config,
             reserve_asset_mint: reserve_mint,
             hatch_vault,
+            funding_pool,
             authority: payer.pubkey(),
             system_program: system_program::ID,
             token_program: spl_token::id(),
@@ -665,11 +733,19 @@ async fn contribute_rejected_after_close() {
         }
         .to_account_metas(None),
         data: hatch_instruction::InitializeHatch {
+            hatch_id: 0,
             min_raise: 50,
             max_raise: 200,
+            initial_price: HATCH_PRICE_SCALE,
+            kappa: 1,
+            exponent: 1,
+            friction: 0,
             open_slot: 0,
             close_slot: 2,
             merkle_root,
+            funding_pool_share: 0,
+            vesting_cliff_slots: 0,
+            vesting_duration_slots: 0,
         }
         .data(),
     };
@@ -687,13 +763,9 @@ async fn contribute_rejected_after_close() {
     )
     .await;
 
-    banks_client.warp_to_slot(5).await.unwrap();
+    context.warp_to_slot(5).unwrap();
 
-    let contribution = Pubkey::find_program_address(
-        &[b"contribution", user.pubkey().as_ref()],
-        &HATCH_PROGRAM_ID,
-    )
-    .0;
+    let contribution = contribution_pda(&hatch_config, &user.pubkey());
     let contribute_ix = Instruction {
         program_id: HATCH_PROGRAM_ID,
         accounts: hatch_accounts::Contribute {
@@ -726,35 +798,23 @@ async fn contribute_rejected_after_close() {
 
 #[tokio::test]
 async fn contribute_rejected_after_finalize() {
-    let mut program = ProgramTest::new(
-        "commons_hatch",
-        HATCH_PROGRAM_ID,
-        processor!(commons_hatch::entry),
-    );
-    program.add_program(
-        "commons_abc",
-        ABC_PROGRAM_ID,
-        processor!(commons_abc::entry),
-    );
-
-    let (mut banks_client, payer, _recent_blockhash) = program.start().await;
+    let (mut banks_client, payer, _recent_blockhash) = hatch_program_test().start().await;
     let user = Keypair::new();
 
     let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
     process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
 
     let reserve_mint = create_mint(&mut banks_client, &payer, &payer.pubkey()).await;
-    let hatch_config =
-        Pubkey::find_program_address(&[b"hatch_config", reserve_mint.as_ref()], &HATCH_PROGRAM_ID)
-            .0;
-    let hatch_vault =
-        Pubkey::find_program_address(&[b"hatch_vault", reserve_mint.as_ref()], &HATCH_PROGRAM_ID).0;
+    let (hatch_config, hatch_vault) = hatch_pdas(&payer.pubkey(), &reserve_mint, 0);
+    let funding_pool =
+        create_user_token_account(&mut banks_client, &payer, &payer, &reserve_mint).await;
 
     let merkle_root = merkle_leaf(&user.pubkey(), 100);
     let init_accounts = hatch_accounts::InitializeHatch {
         hatch_config,
         reserve_asset_mint: reserve_mint,
         hatch_vault,
+        funding_pool,
         authority: payer.pubkey(),
         system_program: system_program::ID,
         token_program: spl_token::id(),
@@ -764,11 +824,19 @@ async fn contribute_rejected_after_finalize() {
         program_id: HATCH_PROGRAM_ID,
         accounts: init_accounts.to_account_metas(None),
         data: hatch_instruction::InitializeHatch {
+            hatch_id: 0,
             min_raise: 50,
             max_raise: 200,
+            initial_price: HATCH_PRICE_SCALE,
+            kappa: 1,
+            exponent: 1,
+            friction: 0,
             open_slot: 0,
-            close_slot: 0,
+            close_slot: 1,
             merkle_root,
+            funding_pool_share: 0,
+            vesting_cliff_slots: 0,
+            vesting_duration_slots: 0,
         }
         .data(),
     };
@@ -786,10 +854,7 @@ async fn contribute_rejected_after_finalize() {
     )
     .await;
 
-    let (contribution, _) = Pubkey::find_program_address(
-        &[b"contribution", user.pubkey().as_ref()],
-        &HATCH_PROGRAM_ID,
-    );
+    let contribution = contribution_pda(&hatch_config, &user.pubkey());
     let contribute_accounts = hatch_accounts::Contribute {
         hatch_config,
         contribution,
@@ -827,9 +892,12 @@ async fn contribute_rejected_after_finalize() {
     let finalize_accounts = hatch_accounts::FinalizeHatch {
         hatch_config,
         reserve_asset_mint: reserve_mint,
+        hatch_vault,
+        funding_pool,
         authority: payer.pubkey(),
         curve_config,
         commons_token_mint,
+        hatch_token_vault: hatch_token_vault_pda(&hatch_config),
         reserve_vault: reserve_vault.pubkey(),
         commons_treasury: commons_treasury.pubkey(),
         commons_abc_program: ABC_PROGRAM_ID,
@@ -840,13 +908,7 @@ async fn contribute_rejected_after_finalize() {
     let finalize_ix = Instruction {
         program_id: HATCH_PROGRAM_ID,
         accounts: finalize_accounts.to_account_metas(None),
-        data: hatch_instruction::FinalizeHatch {
-            kappa: 1,
-            exponent: 1,
-            initial_price: 1,
-            friction: 0,
-        }
-        .data(),
+        data: hatch_instruction::FinalizeHatch {}.data(),
     };
     process_transaction(
         &mut banks_client,
@@ -888,31 +950,23 @@ async fn contribute_rejected_after_finalize() {
 
 #[tokio::test]
 async fn contribute_rejected_after_failure() {
-    let mut program = ProgramTest::new(
-        "commons_hatch",
-        HATCH_PROGRAM_ID,
-        processor!(commons_hatch::entry),
-    );
-    program.add_program("commons_abc", ABC_PROGRAM_ID, processor!(commons_abc::entry));
-
-    let (mut banks_client, payer, _recent_blockhash) = program.start().await;
+    let (mut banks_client, payer, _recent_blockhash) = hatch_program_test().start().await;
     let user = Keypair::new();
 
     let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
     process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
 
     let reserve_mint = create_mint(&mut banks_client, &payer, &payer.pubkey()).await;
-    let hatch_config =
-        Pubkey::find_program_address(&[b"hatch_config", reserve_mint.as_ref()], &HATCH_PROGRAM_ID)
-            .0;
-    let hatch_vault =
-        Pubkey::find_program_address(&[b"hatch_vault", reserve_mint.as_ref()], &HATCH_PROGRAM_ID).0;
+    let (hatch_config, hatch_vault) = hatch_pdas(&payer.pubkey(), &reserve_mint, 0);
+    let funding_pool =
+        create_user_token_account(&mut banks_client, &payer, &payer, &reserve_mint).await;
 
     let merkle_root = merkle_leaf(&user.pubkey(), 100);
     let init_accounts = hatch_accounts::InitializeHatch {
         hatch_config,
         reserve_asset_mint: reserve_mint,
         hatch_vault,
+        funding_pool,
         authority: payer.pubkey(),
         system_program: system_program::ID,
         token_program: spl_token::id(),
@@ -922,11 +976,19 @@ async fn contribute_rejected_after_failure() {
         program_id: HATCH_PROGRAM_ID,
         accounts: init_accounts.to_account_metas(None),
         data: hatch_instruction::InitializeHatch {
+            hatch_id: 0,
             min_raise: 50,
             max_raise: 200,
+            initial_price: HATCH_PRICE_SCALE,
+            kappa: 1,
+            exponent: 1,
+            friction: 0,
             open_slot: 0,
-            close_slot: 0,
+            close_slot: 1,
             merkle_root,
+            funding_pool_share: 0,
+            vesting_cliff_slots: 0,
+            vesting_duration_slots: 0,
         }
         .data(),
     };
@@ -944,10 +1006,7 @@ async fn contribute_rejected_after_failure() {
     )
     .await;
 
-    let (contribution, _) = Pubkey::find_program_address(
-        &[b"contribution", user.pubkey().as_ref()],
-        &HATCH_PROGRAM_ID,
-    );
+    let contribution = contribution_pda(&hatch_config, &user.pubkey());
     let contribute_accounts = hatch_accounts::Contribute {
         hatch_config,
         contribution,
@@ -985,9 +1044,12 @@ async fn contribute_rejected_after_failure() {
     let finalize_accounts = hatch_accounts::FinalizeHatch {
         hatch_config,
         reserve_asset_mint: reserve_mint,
+        hatch_vault,
+        funding_pool,
         authority: payer.pubkey(),
         curve_config,
         commons_token_mint,
+        hatch_token_vault: hatch_token_vault_pda(&hatch_config),
         reserve_vault: reserve_vault.pubkey(),
         commons_treasury: commons_treasury.pubkey(),
         commons_abc_program: ABC_PROGRAM_ID,
@@ -998,13 +1060,7 @@ async fn contribute_rejected_after_failure() {
     let finalize_ix = Instruction {
         program_id: HATCH_PROGRAM_ID,
         accounts: finalize_accounts.to_account_metas(None),
-        data: hatch_instruction::FinalizeHatch {
-            kappa: 1,
-            exponent: 1,
-            initial_price: 1,
-            friction: 0,
-        }
-        .data(),
+        data: hatch_instruction::FinalizeHatch {}.data(),
     };
     process_transaction(
         &mut banks_client,
@@ -1043,3 +1099,854 @@ async fn contribute_rejected_after_failure() {
     )
     .await;
 }
+
+#[tokio::test]
+async fn concurrent_hatches_on_one_reserve_mint_stay_separate() {
+    let (mut banks_client, payer, _recent_blockhash) = hatch_program_test().start().await;
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
+
+    let reserve_mint = create_mint(&mut banks_client, &payer, &payer.pubkey()).await;
+    let user_reserve_account =
+        create_user_token_account(&mut banks_client, &payer, &user, &reserve_mint).await;
+    mint_to_account(
+        &mut banks_client,
+        &payer,
+        &reserve_mint,
+        &user_reserve_account,
+        &payer,
+        100,
+    )
+    .await;
+
+    let funding_pool =
+        create_user_token_account(&mut banks_client, &payer, &payer, &reserve_mint).await;
+
+    // Hatch 0 needs 50 and gets 60; hatch 1 needs 100 and only gets 40
+    let merkle_root = merkle_leaf(&user.pubkey(), 100);
+    let mut hatches = Vec::new();
+    for (hatch_id, min_raise, amount) in [(0u64, 50u64, 60u64), (1, 100, 40)] {
+        let (hatch_config, hatch_vault) = hatch_pdas(&payer.pubkey(), &reserve_mint, hatch_id);
+        let init_ix = Instruction {
+            program_id: HATCH_PROGRAM_ID,
+            accounts: hatch_accounts::InitializeHatch {
+                hatch_config,
+                reserve_asset_mint: reserve_mint,
+                hatch_vault,
+                funding_pool,
+                authority: payer.pubkey(),
+                system_program: system_program::ID,
+                token_program: spl_token::id(),
+                rent: sysvar::rent::ID,
+            }
+            .to_account_metas(None),
+            data: hatch_instruction::InitializeHatch {
+                hatch_id,
+                min_raise,
+                max_raise: 200,
+                initial_price: HATCH_PRICE_SCALE,
+                kappa: 1,
+                exponent: 1,
+                friction: 0,
+                open_slot: 0,
+                close_slot: 1,
+                merkle_root,
+                funding_pool_share: 0,
+                vesting_cliff_slots: 0,
+                vesting_duration_slots: 0,
+            }
+            .data(),
+        };
+        process_transaction(&mut banks_client, &payer, vec![init_ix], vec![]).await;
+
+        let contribution = contribution_pda(&hatch_config, &user.pubkey());
+        let contribute_ix = Instruction {
+            program_id: HATCH_PROGRAM_ID,
+            accounts: hatch_accounts::Contribute {
+                hatch_config,
+                contribution,
+                hatch_vault,
+                user_reserve_token_account: user_reserve_account,
+                authority: user.pubkey(),
+                system_program: system_program::ID,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: hatch_instruction::Contribute {
+                amount,
+                allowed_allocation: 100,
+                proof: vec![],
+            }
+            .data(),
+        };
+        process_transaction(&mut banks_client, &payer, vec![contribute_ix], vec![&user]).await;
+        hatches.push((hatch_config, hatch_vault, contribution, amount));
+    }
+
+    for &(hatch_config, hatch_vault, contribution, amount) in hatches.iter() {
+        let contribution_account = banks_client
+            .get_account(contribution)
+            .await
+            .unwrap()
+            .expect("contribution account missing");
+        let mut contrib_data: &[u8] = &contribution_account.data;
+        let contribution_state = Contribution::try_deserialize(&mut contrib_data).unwrap();
+        assert_eq!(contribution_state.amount, amount);
+
+        let vault_account = banks_client
+            .get_account(hatch_vault)
+            .await
+            .unwrap()
+            .expect("hatch vault missing");
+        let vault_state = TokenAccountState::unpack(&vault_account.data).unwrap();
+        assert_eq!(vault_state.amount, amount);
+
+        let commons_token_mint = Pubkey::find_program_address(
+            &[b"commons_token_mint", hatch_config.as_ref()],
+            &HATCH_PROGRAM_ID,
+        )
+        .0;
+        let curve_config = Pubkey::find_program_address(
+            &[b"curve_config", commons_token_mint.as_ref()],
+            &ABC_PROGRAM_ID,
+        )
+        .0;
+        let reserve_vault = Keypair::new();
+        let commons_treasury = Keypair::new();
+        let finalize_ix = Instruction {
+            program_id: HATCH_PROGRAM_ID,
+            accounts: hatch_accounts::FinalizeHatch {
+                hatch_config,
+                reserve_asset_mint: reserve_mint,
+                hatch_vault,
+                funding_pool,
+                authority: payer.pubkey(),
+                curve_config,
+                commons_token_mint,
+                hatch_token_vault: hatch_token_vault_pda(&hatch_config),
+                reserve_vault: reserve_vault.pubkey(),
+                commons_treasury: commons_treasury.pubkey(),
+                commons_abc_program: ABC_PROGRAM_ID,
+                system_program: system_program::ID,
+                token_program: spl_token::id(),
+                rent: sysvar::rent::ID,
+            }
+            .to_account_metas(None),
+            data: hatch_instruction::FinalizeHatch {}.data(),
+        };
+        process_transaction(
+            &mut banks_client,
+            &payer,
+            vec![finalize_ix],
+            vec![&reserve_vault, &commons_treasury],
+        )
+        .await;
+    }
+
+    let (succeeded, succeeded_vault, _, _) = hatches[0];
+    let (failed, failed_vault, failed_contribution, _) = hatches[1];
+    let mut configs = Vec::new();
+    for hatch_config in [succeeded, failed] {
+        let account = banks_client
+            .get_account(hatch_config)
+            .await
+            .unwrap()
+            .expect("failed to fetch hatch config");
+        let mut data: &[u8] = &account.data;
+        configs.push(HatchConfig::try_deserialize(&mut data).unwrap());
+    }
+    assert!(configs[0].finalized && !configs[0].failed);
+    assert!(configs[1].failed);
+    assert_eq!(configs[1].hatch_id, 1);
+
+    // Refunding the failed hatch only touches its own vault and record
+    let refund_ix = Instruction {
+        program_id: HATCH_PROGRAM_ID,
+        accounts: hatch_accounts::Refund {
+            hatch_config: failed,
+            contribution: failed_contribution,
+            hatch_vault: failed_vault,
+            user_reserve_token_account: user_reserve_account,
+            authority: user.pubkey(),
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None),
+        data: hatch_instruction::Refund {}.data(),
+    };
+    process_transaction(&mut banks_client, &payer, vec![refund_ix], vec![&user]).await;
+
+    let reserve_account = banks_client
+        .get_account(user_reserve_account)
+        .await
+        .unwrap()
+        .expect("reserve account missing");
+    let reserve_state = TokenAccountState::unpack(&reserve_account.data).unwrap();
+    assert_eq!(reserve_state.amount, 40);
+    // The successful hatch moved its raise into its own curve reserve
+    let vault_account = banks_client
+        .get_account(succeeded_vault)
+        .await
+        .unwrap()
+        .expect("hatch vault missing");
+    let vault_state = TokenAccountState::unpack(&vault_account.data).unwrap();
+    assert_eq!(vault_state.amount, 0);
+}
+
+#[tokio::test]
+async fn contribute_fills_up_to_max_raise() {
+    let (mut banks_client, payer, _recent_blockhash) = hatch_program_test().start().await;
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
+
+    let reserve_mint = create_mint(&mut banks_client, &payer, &payer.pubkey()).await;
+    let (hatch_config, hatch_vault) = hatch_pdas(&payer.pubkey(), &reserve_mint, 0);
+    let funding_pool =
+        create_user_token_account(&mut banks_client, &payer, &payer, &reserve_mint).await;
+
+    let merkle_root = merkle_leaf(&user.pubkey(), 300);
+    let init_ix = |min_raise: u64| Instruction {
+        program_id: HATCH_PROGRAM_ID,
+        accounts: hatch_accounts::InitializeHatch {
+            hatch_config,
+            reserve_asset_mint: reserve_mint,
+            hatch_vault,
+            funding_pool,
+            authority: payer.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: hatch_instruction::InitializeHatch {
+            hatch_id: 0,
+            min_raise,
+            max_raise: 200,
+            initial_price: HATCH_PRICE_SCALE,
+            kappa: 1,
+            exponent: 1,
+            friction: 0,
+            open_slot: 0,
+            close_slot: 100,
+            merkle_root,
+            funding_pool_share: 0,
+            vesting_cliff_slots: 0,
+            vesting_duration_slots: 0,
+        }
+        .data(),
+    };
+    expect_hatch_error(
+        &mut banks_client,
+        &payer,
+        vec![init_ix(201)],
+        vec![],
+        HatchError::InvalidRaiseBounds,
+    )
+    .await;
+    process_transaction(&mut banks_client, &payer, vec![init_ix(50)], vec![]).await;
+
+    let user_reserve_account =
+        create_user_token_account(&mut banks_client, &payer, &user, &reserve_mint).await;
+    mint_to_account(
+        &mut banks_client,
+        &payer,
+        &reserve_mint,
+        &user_reserve_account,
+        &payer,
+        300,
+    )
+    .await;
+
+    let contribution = contribution_pda(&hatch_config, &user.pubkey());
+    let contribute_ix = |amount: u64| Instruction {
+        program_id: HATCH_PROGRAM_ID,
+        accounts: hatch_accounts::Contribute {
+            hatch_config,
+            contribution,
+            hatch_vault,
+            user_reserve_token_account: user_reserve_account,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None),
+        data: hatch_instruction::Contribute {
+            amount,
+            allowed_allocation: 300,
+            proof: vec![],
+        }
+        .data(),
+    };
+    process_transaction(
+        &mut banks_client,
+        &payer,
+        vec![contribute_ix(150)],
+        vec![&user],
+    )
+    .await;
+    // Only 50 of this one fits under max_raise
+    process_transaction(
+        &mut banks_client,
+        &payer,
+        vec![contribute_ix(100)],
+        vec![&user],
+    )
+    .await;
+
+    let account = banks_client
+        .get_account(hatch_config)
+        .await
+        .unwrap()
+        .expect("failed to fetch hatch config");
+    let mut data: &[u8] = &account.data;
+    let config = HatchConfig::try_deserialize(&mut data).unwrap();
+    assert_eq!(config.total_raised, 200);
+
+    let contribution_account = banks_client
+        .get_account(contribution)
+        .await
+        .unwrap()
+        .expect("contribution account missing");
+    let mut contrib_data: &[u8] = &contribution_account.data;
+    let contribution_state = Contribution::try_deserialize(&mut contrib_data).unwrap();
+    assert_eq!(contribution_state.amount, 200);
+
+    let reserve_account = banks_client
+        .get_account(user_reserve_account)
+        .await
+        .unwrap()
+        .expect("reserve account missing");
+    let reserve_state = TokenAccountState::unpack(&reserve_account.data).unwrap();
+    assert_eq!(reserve_state.amount, 100);
+
+    expect_hatch_error(
+        &mut banks_client,
+        &payer,
+        vec![contribute_ix(10)],
+        vec![&user],
+        HatchError::HatchFull,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn claim_mints_pro_rata_share_at_hatch_price() {
+    let (mut banks_client, payer, _recent_blockhash) = hatch_program_test().start().await;
+    let users = [Keypair::new(), Keypair::new()];
+    for user in users.iter() {
+        let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+        process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
+    }
+
+    let reserve_mint = create_mint(&mut banks_client, &payer, &payer.pubkey()).await;
+    let (hatch_config, hatch_vault) = hatch_pdas(&payer.pubkey(), &reserve_mint, 0);
+    let funding_pool =
+        create_user_token_account(&mut banks_client, &payer, &payer, &reserve_mint).await;
+
+    let leaves = [
+        merkle_leaf(&users[0].pubkey(), 100),
+        merkle_leaf(&users[1].pubkey(), 100),
+    ];
+    let merkle_root = if leaves[0] <= leaves[1] {
+        hashv(&[&leaves[0], &leaves[1]]).to_bytes()
+    } else {
+        hashv(&[&leaves[1], &leaves[0]]).to_bytes()
+    };
+    let init_ix = Instruction {
+        program_id: HATCH_PROGRAM_ID,
+        accounts: hatch_accounts::InitializeHatch {
+            hatch_config,
+            reserve_asset_mint: reserve_mint,
+            hatch_vault,
+            funding_pool,
+            authority: payer.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: hatch_instruction::InitializeHatch {
+            hatch_id: 0,
+            min_raise: 50,
+            max_raise: 200,
+            initial_price: HATCH_PRICE_SCALE / 2,
+            kappa: 1,
+            exponent: 1,
+            friction: 0,
+            open_slot: 0,
+            close_slot: 1,
+            merkle_root,
+            funding_pool_share: 0,
+            vesting_cliff_slots: 0,
+            vesting_duration_slots: 0,
+        }
+        .data(),
+    };
+    process_transaction(&mut banks_client, &payer, vec![init_ix], vec![]).await;
+
+    let mut contributions = Vec::new();
+    for (index, (user, amount)) in users.iter().zip([60u64, 40]).enumerate() {
+        let user_reserve_account =
+            create_user_token_account(&mut banks_client, &payer, user, &reserve_mint).await;
+        mint_to_account(
+            &mut banks_client,
+            &payer,
+            &reserve_mint,
+            &user_reserve_account,
+            &payer,
+            amount,
+        )
+        .await;
+        let contribution = contribution_pda(&hatch_config, &user.pubkey());
+        let contribute_ix = Instruction {
+            program_id: HATCH_PROGRAM_ID,
+            accounts: hatch_accounts::Contribute {
+                hatch_config,
+                contribution,
+                hatch_vault,
+                user_reserve_token_account: user_reserve_account,
+                authority: user.pubkey(),
+                system_program: system_program::ID,
+                token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: hatch_instruction::Contribute {
+                amount,
+                allowed_allocation: 100,
+                proof: vec![leaves[1 - index]],
+            }
+            .data(),
+        };
+        process_transaction(&mut banks_client, &payer, vec![contribute_ix], vec![user]).await;
+        contributions.push(contribution);
+    }
+
+    let commons_token_mint = Pubkey::find_program_address(
+        &[b"commons_token_mint", hatch_config.as_ref()],
+        &HATCH_PROGRAM_ID,
+    )
+    .0;
+    let curve_config = Pubkey::find_program_address(
+        &[b"curve_config", commons_token_mint.as_ref()],
+        &ABC_PROGRAM_ID,
+    )
+    .0;
+    let reserve_vault = Keypair::new();
+    let commons_treasury = Keypair::new();
+    // Half a reserve unit per token: the 100 raised buys an initial supply of 200
+    let finalize_ix = Instruction {
+        program_id: HATCH_PROGRAM_ID,
+        accounts: hatch_accounts::FinalizeHatch {
+            hatch_config,
+            reserve_asset_mint: reserve_mint,
+            hatch_vault,
+            funding_pool,
+            authority: payer.pubkey(),
+            curve_config,
+            commons_token_mint,
+            hatch_token_vault: hatch_token_vault_pda(&hatch_config),
+            reserve_vault: reserve_vault.pubkey(),
+            commons_treasury: commons_treasury.pubkey(),
+            commons_abc_program: ABC_PROGRAM_ID,
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: hatch_instruction::FinalizeHatch {}.data(),
+    };
+    process_transaction(
+        &mut banks_client,
+        &payer,
+        vec![finalize_ix],
+        vec![&reserve_vault, &commons_treasury],
+    )
+    .await;
+
+    let account = banks_client
+        .get_account(hatch_config)
+        .await
+        .unwrap()
+        .expect("failed to fetch hatch config");
+    let mut data: &[u8] = &account.data;
+    let config = HatchConfig::try_deserialize(&mut data).unwrap();
+    assert_eq!(config.initial_supply, 200);
+
+    for ((user, contribution), expected) in users.iter().zip(contributions).zip([120u64, 80]) {
+        let user_commons_account = associated_token_address(&user.pubkey(), &commons_token_mint);
+        let claim_ix = Instruction {
+            program_id: HATCH_PROGRAM_ID,
+            accounts: hatch_accounts::Claim {
+                hatch_config,
+                contribution,
+                vesting_position: vesting_position_pda(&hatch_config, &user.pubkey()),
+                commons_token_mint,
+                hatch_token_vault: hatch_token_vault_pda(&hatch_config),
+                user_commons_token_account: user_commons_account,
+                authority: user.pubkey(),
+                token_program: spl_token::id(),
+                associated_token_program: associated_token_program_id(),
+                system_program: system_program::ID,
+                rent: sysvar::rent::ID,
+            }
+            .to_account_metas(None),
+            data: hatch_instruction::Claim {}.data(),
+        };
+        process_transaction(&mut banks_client, &payer, vec![claim_ix], vec![user]).await;
+
+        let commons_account_data = banks_client
+            .get_account(user_commons_account)
+            .await
+            .unwrap()
+            .expect("commons token account missing");
+        let token_state = TokenAccountState::unpack(&commons_account_data.data).unwrap();
+        assert_eq!(token_state.amount, expected);
+    }
+}
+
+#[tokio::test]
+async fn finalize_moves_raise_into_reserve_and_funding_pool() {
+    let (mut banks_client, payer, _recent_blockhash) = hatch_program_test().start().await;
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
+
+    let reserve_mint = create_mint(&mut banks_client, &payer, &payer.pubkey()).await;
+    let (hatch_config, hatch_vault) = hatch_pdas(&payer.pubkey(), &reserve_mint, 0);
+    let funding_pool =
+        create_user_token_account(&mut banks_client, &payer, &payer, &reserve_mint).await;
+
+    let merkle_root = merkle_leaf(&user.pubkey(), 100);
+    let init_ix = |funding_pool_share: u64| Instruction {
+        program_id: HATCH_PROGRAM_ID,
+        accounts: hatch_accounts::InitializeHatch {
+            hatch_config,
+            reserve_asset_mint: reserve_mint,
+            hatch_vault,
+            funding_pool,
+            authority: payer.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: hatch_instruction::InitializeHatch {
+            hatch_id: 0,
+            min_raise: 50,
+            max_raise: 200,
+            initial_price: HATCH_PRICE_SCALE,
+            kappa: 1,
+            exponent: 1,
+            friction: 0,
+            open_slot: 0,
+            close_slot: 1,
+            merkle_root,
+            funding_pool_share,
+            vesting_cliff_slots: 0,
+            vesting_duration_slots: 0,
+        }
+        .data(),
+    };
+    // The whole raise going to the funding pool would leave the curve without a reserve
+    expect_hatch_error(
+        &mut banks_client,
+        &payer,
+        vec![init_ix(FUNDING_POOL_SCALE)],
+        vec![],
+        HatchError::InvalidFundingPoolShare,
+    )
+    .await;
+    process_transaction(
+        &mut banks_client,
+        &payer,
+        vec![init_ix(FUNDING_POOL_SCALE / 4)],
+        vec![],
+    )
+    .await;
+
+    let user_reserve_account =
+        create_user_token_account(&mut banks_client, &payer, &user, &reserve_mint).await;
+    mint_to_account(
+        &mut banks_client,
+        &payer,
+        &reserve_mint,
+        &user_reserve_account,
+        &payer,
+        80,
+    )
+    .await;
+    let contribution = contribution_pda(&hatch_config, &user.pubkey());
+    let contribute_ix = Instruction {
+        program_id: HATCH_PROGRAM_ID,
+        accounts: hatch_accounts::Contribute {
+            hatch_config,
+            contribution,
+            hatch_vault,
+            user_reserve_token_account: user_reserve_account,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None),
+        data: hatch_instruction::Contribute {
+            amount: 80,
+            allowed_allocation: 100,
+            proof: vec![],
+        }
+        .data(),
+    };
+    process_transaction(&mut banks_client, &payer, vec![contribute_ix], vec![&user]).await;
+
+    let commons_token_mint = Pubkey::find_program_address(
+        &[b"commons_token_mint", hatch_config.as_ref()],
+        &HATCH_PROGRAM_ID,
+    )
+    .0;
+    let curve_config = Pubkey::find_program_address(
+        &[b"curve_config", commons_token_mint.as_ref()],
+        &ABC_PROGRAM_ID,
+    )
+    .0;
+    let reserve_vault = Keypair::new();
+    let commons_treasury = Keypair::new();
+    let finalize_ix = Instruction {
+        program_id: HATCH_PROGRAM_ID,
+        accounts: hatch_accounts::FinalizeHatch {
+            hatch_config,
+            hatch_vault,
+            funding_pool,
+            reserve_asset_mint: reserve_mint,
+            authority: payer.pubkey(),
+            curve_config,
+            commons_token_mint,
+            hatch_token_vault: hatch_token_vault_pda(&hatch_config),
+            reserve_vault: reserve_vault.pubkey(),
+            commons_treasury: commons_treasury.pubkey(),
+            commons_abc_program: ABC_PROGRAM_ID,
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: hatch_instruction::FinalizeHatch {}.data(),
+    };
+    process_transaction(
+        &mut banks_client,
+        &payer,
+        vec![finalize_ix],
+        vec![&reserve_vault, &commons_treasury],
+    )
+    .await;
+
+    // A quarter of the 80 raised funds the pool, the rest backs the curve
+    for (account, expected) in [
+        (hatch_vault, 0u64),
+        (reserve_vault.pubkey(), 60),
+        (funding_pool, 20),
+    ] {
+        let token_account = banks_client
+            .get_account(account)
+            .await
+            .unwrap()
+            .expect("token account missing");
+        let token_state = TokenAccountState::unpack(&token_account.data).unwrap();
+        assert_eq!(token_state.amount, expected);
+    }
+}
+
+#[tokio::test]
+async fn claim_releases_vested_tokens_over_time() {
+    let mut context = hatch_program_test().start_with_context().await;
+    let mut banks_client = context.banks_client.clone();
+    let payer = context.payer.insecure_clone();
+    let user = Keypair::new();
+    let transfer = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 5_000_000_000);
+    process_transaction(&mut banks_client, &payer, vec![transfer], vec![]).await;
+
+    let reserve_mint = create_mint(&mut banks_client, &payer, &payer.pubkey()).await;
+    let (hatch_config, hatch_vault) = hatch_pdas(&payer.pubkey(), &reserve_mint, 0);
+    let funding_pool =
+        create_user_token_account(&mut banks_client, &payer, &payer, &reserve_mint).await;
+
+    // Nothing before slot 30, then 1% per slot from the close at slot 10
+    let close_slot = 10;
+    let merkle_root = merkle_leaf(&user.pubkey(), 100);
+    let init_ix = Instruction {
+        program_id: HATCH_PROGRAM_ID,
+        accounts: hatch_accounts::InitializeHatch {
+            hatch_config,
+            reserve_asset_mint: reserve_mint,
+            hatch_vault,
+            funding_pool,
+            authority: payer.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: hatch_instruction::InitializeHatch {
+            hatch_id: 0,
+            min_raise: 50,
+            max_raise: 200,
+            initial_price: HATCH_PRICE_SCALE,
+            kappa: 1,
+            exponent: 1,
+            friction: 0,
+            open_slot: 0,
+            close_slot,
+            merkle_root,
+            funding_pool_share: 0,
+            vesting_cliff_slots: 20,
+            vesting_duration_slots: 100,
+        }
+        .data(),
+    };
+    process_transaction(&mut banks_client, &payer, vec![init_ix], vec![]).await;
+
+    let user_reserve_account =
+        create_user_token_account(&mut banks_client, &payer, &user, &reserve_mint).await;
+    mint_to_account(
+        &mut banks_client,
+        &payer,
+        &reserve_mint,
+        &user_reserve_account,
+        &payer,
+        100,
+    )
+    .await;
+    let contribution = contribution_pda(&hatch_config, &user.pubkey());
+    let contribute_ix = Instruction {
+        program_id: HATCH_PROGRAM_ID,
+        accounts: hatch_accounts::Contribute {
+            hatch_config,
+            contribution,
+            hatch_vault,
+            user_reserve_token_account: user_reserve_account,
+            authority: user.pubkey(),
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None),
+        data: hatch_instruction::Contribute {
+            amount: 100,
+            allowed_allocation: 100,
+            proof: vec![],
+        }
+        .data(),
+    };
+    process_transaction(&mut banks_client, &payer, vec![contribute_ix], vec![&user]).await;
+
+    context.warp_to_slot(close_slot).unwrap();
+
+    let commons_token_mint = Pubkey::find_program_address(
+        &[b"commons_token_mint", hatch_config.as_ref()],
+        &HATCH_PROGRAM_ID,
+    )
+    .0;
+    let curve_config = Pubkey::find_program_address(
+        &[b"curve_config", commons_token_mint.as_ref()],
+        &ABC_PROGRAM_ID,
+    )
+    .0;
+    let reserve_vault = Keypair::new();
+    let commons_treasury = Keypair::new();
+    let finalize_ix = Instruction {
+        program_id: HATCH_PROGRAM_ID,
+        accounts: hatch_accounts::FinalizeHatch {
+            hatch_config,
+            hatch_vault,
+            funding_pool,
+            reserve_asset_mint: reserve_mint,
+            authority: payer.pubkey(),
+            curve_config,
+            commons_token_mint,
+            hatch_token_vault: hatch_token_vault_pda(&hatch_config),
+            reserve_vault: reserve_vault.pubkey(),
+            commons_treasury: commons_treasury.pubkey(),
+            commons_abc_program: ABC_PROGRAM_ID,
+            system_program: system_program::ID,
+            token_program: spl_token::id(),
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: hatch_instruction::FinalizeHatch {}.data(),
+    };
+    process_transaction(
+        &mut banks_client,
+        &payer,
+        vec![finalize_ix],
+        vec![&reserve_vault, &commons_treasury],
+    )
+    .await;
+
+    let vesting_position = vesting_position_pda(&hatch_config, &user.pubkey());
+    let user_commons_account = associated_token_address(&user.pubkey(), &commons_token_mint);
+    let claim_ix = Instruction {
+        program_id: HATCH_PROGRAM_ID,
+        accounts: hatch_accounts::Claim {
+            hatch_config,
+            contribution,
+            vesting_position,
+            commons_token_mint,
+            hatch_token_vault: hatch_token_vault_pda(&hatch_config),
+            user_commons_token_account: user_commons_account,
+            authority: user.pubkey(),
+            token_program: spl_token::id(),
+            associated_token_program: associated_token_program_id(),
+            system_program: system_program::ID,
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: hatch_instruction::Claim {}.data(),
+    };
+
+    context.warp_to_slot(close_slot + 10).unwrap();
+    expect_hatch_error(
+        &mut banks_client,
+        &payer,
+        vec![claim_ix.clone()],
+        vec![&user],
+        HatchError::NothingVested,
+    )
+    .await;
+
+    for (slot, expected) in [(close_slot + 50, 50u64), (close_slot + 150, 100)] {
+        context.warp_to_slot(slot).unwrap();
+        process_transaction(
+            &mut banks_client,
+            &payer,
+            vec![claim_ix.clone()],
+            vec![&user],
+        )
+        .await;
+
+        let commons_account_data = banks_client
+            .get_account(user_commons_account)
+            .await
+            .unwrap()
+            .expect("commons token account missing");
+        let token_state = TokenAccountState::unpack(&commons_account_data.data).unwrap();
+        assert_eq!(token_state.amount, expected);
+
+        let position_account = banks_client
+            .get_account(vesting_position)
+            .await
+            .unwrap()
+            .expect("vesting position missing");
+        let mut position_data: &[u8] = &position_account.data;
+        let position = VestingPosition::try_deserialize(&mut position_data).unwrap();
+        assert_eq!(position.total_amount, 100);
+        assert_eq!(position.claimed_amount, expected);
+    }
+
+    context.warp_to_slot(close_slot + 160).unwrap();
+    expect_hatch_error(
+        &mut banks_client,
+        &payer,
+        vec![claim_ix],
+        vec![&user],
+        HatchError::AlreadyClaimed,
+    )
+    .await;
+}
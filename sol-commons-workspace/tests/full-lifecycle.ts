@@ -13,11 +13,19 @@ const DECIMALS = 6;
 const FRICTION = 50_000;
 const KAPPA = 2;
 const EXPONENT = 1;
-const INITIAL_PRICE = 1;
+const INITIAL_PRICE = 1_000_000; // One reserve unit per commons token
+const FUNDING_POOL_SHARE = 200_000; // 20% of the raise funds the commons
+const VESTING_CLIFF_SLOTS = 0;
+const VESTING_DURATION_SLOTS = 0; // Hatch tokens are claimable in full once the hatch closes
 const DECAY_RATE = 500_000;
 const MAX_RATIO = 1_000_000;
 const WEIGHT_EXPONENT = 1_000_000;
 const MIN_THRESHOLD = 100_000;
+const PROPOSAL_EXPIRY_SLOTS = 0;
+const SIGNALING_THRESHOLD = 250_000;
+const STREAM_DURATION_SLOTS = 0;
+const PROPOSAL_DEPOSIT = 0;
+const TIME_BASE = { slots: {} };
 
 anchor.setProvider(anchor.AnchorProvider.env());
 const provider = anchor.getProvider() as anchor.AnchorProvider;
@@ -87,27 +95,43 @@ describe("full lifecycle", () => {
     const openSlot = Math.max(0, currentSlot - 1);
     const closeSlot = currentSlot + 2;
     const merkleRoot = hashContributorAllocation(provider.wallet.publicKey, depositAmount);
+    const hatchId = new anchor.BN(0);
     const [hatchConfig] = await anchor.web3.PublicKey.findProgramAddress(
-      [Buffer.from("hatch_config"), reserveTokenMint.publicKey.toBuffer()],
+      [
+        Buffer.from("hatch_config"),
+        provider.wallet.publicKey.toBuffer(),
+        reserveTokenMint.publicKey.toBuffer(),
+        hatchId.toArrayLike(Buffer, "le", 8),
+      ],
       hatchProgram.programId
     );
     const [hatchVault] = await anchor.web3.PublicKey.findProgramAddress(
-      [Buffer.from("hatch_vault"), reserveTokenMint.publicKey.toBuffer()],
+      [Buffer.from("hatch_vault"), hatchConfig.toBuffer()],
       hatchProgram.programId
     );
+    const fundingPool = await reserveToken.createAccount(provider.wallet.publicKey);
 
     await hatchProgram.methods
       .initializeHatch(
+        hatchId,
         new anchor.BN(depositAmount),
         new anchor.BN(depositAmount),
+        new anchor.BN(INITIAL_PRICE),
+        new anchor.BN(KAPPA),
+        new anchor.BN(EXPONENT),
+        new anchor.BN(FRICTION),
         new anchor.BN(openSlot),
         new anchor.BN(closeSlot),
-        Array.from(merkleRoot)
+        Array.from(merkleRoot),
+        new anchor.BN(FUNDING_POOL_SHARE),
+        new anchor.BN(VESTING_CLIFF_SLOTS),
+        new anchor.BN(VESTING_DURATION_SLOTS)
       )
       .accounts({
         hatchConfig,
         reserveAssetMint: reserveTokenMint.publicKey,
         hatchVault,
+        fundingPool,
         authority: provider.wallet.publicKey,
         systemProgram: anchor.web3.SystemProgram.programId,
         tokenProgram: TOKEN_PROGRAM_ID,
@@ -116,7 +140,11 @@ describe("full lifecycle", () => {
       .rpc();
 
     const [contribution] = await anchor.web3.PublicKey.findProgramAddress(
-      [Buffer.from("contribution"), provider.wallet.publicKey.toBuffer()],
+      [
+        Buffer.from("contribution"),
+        hatchConfig.toBuffer(),
+        provider.wallet.publicKey.toBuffer(),
+      ],
       hatchProgram.programId
     );
 
@@ -146,20 +174,22 @@ describe("full lifecycle", () => {
       [Buffer.from("curve_config"), commonsTokenMint.toBuffer()],
       abcProgram.programId
     );
+    const [hatchTokenVault] = await anchor.web3.PublicKey.findProgramAddress(
+      [Buffer.from("hatch_token_vault"), hatchConfig.toBuffer()],
+      hatchProgram.programId
+    );
 
     await hatchProgram.methods
-      .finalizeHatch(
-        new anchor.BN(KAPPA),
-        new anchor.BN(EXPONENT),
-        new anchor.BN(INITIAL_PRICE),
-        new anchor.BN(FRICTION)
-      )
+      .finalizeHatch()
       .accounts({
         hatchConfig,
         reserveAssetMint: reserveTokenMint.publicKey,
+        hatchVault,
+        fundingPool,
         authority: provider.wallet.publicKey,
         curveConfig,
         commonsTokenMint,
+        hatchTokenVault,
         reserveVault: reserveVault.publicKey,
         commonsTreasury: commonsTreasury.publicKey,
         commonsAbcProgram: abcProgram.programId,
@@ -170,6 +200,12 @@ describe("full lifecycle", () => {
       .signers([reserveVault, commonsTreasury])
       .rpc();
 
+    const fundingPoolAmount = (depositAmount * FUNDING_POOL_SHARE) / 1_000_000;
+    const fundingPoolBalance = (await reserveToken.getAccountInfo(fundingPool)).amount.toNumber();
+    expect(fundingPoolBalance).to.equal(fundingPoolAmount);
+    const reserveVaultBalance = (await reserveToken.getAccountInfo(reserveVault.publicKey)).amount.toNumber();
+    expect(reserveVaultBalance).to.equal(depositAmount - fundingPoolAmount);
+
     const commonsToken = new Token(
       provider.connection,
       commonsTokenMint,
@@ -180,14 +216,19 @@ describe("full lifecycle", () => {
     const userCommonsAccount = await commonsToken.getOrCreateAssociatedAccountInfo(
       provider.wallet.publicKey
     );
+    const [vestingPosition] = await anchor.web3.PublicKey.findProgramAddress(
+      [Buffer.from("vesting"), hatchConfig.toBuffer(), provider.wallet.publicKey.toBuffer()],
+      hatchProgram.programId
+    );
 
     await hatchProgram.methods
       .claim()
       .accounts({
         hatchConfig,
         contribution,
+        vestingPosition,
         commonsTokenMint,
-        curveConfig,
+        hatchTokenVault,
         userCommonsTokenAccount: userCommonsAccount.address,
         authority: provider.wallet.publicKey,
         tokenProgram: TOKEN_PROGRAM_ID,
@@ -234,7 +275,12 @@ describe("full lifecycle", () => {
         new anchor.BN(DECAY_RATE),
         new anchor.BN(MAX_RATIO),
         new anchor.BN(WEIGHT_EXPONENT),
-        new anchor.BN(MIN_THRESHOLD)
+        new anchor.BN(MIN_THRESHOLD),
+        new anchor.BN(PROPOSAL_EXPIRY_SLOTS),
+        new anchor.BN(SIGNALING_THRESHOLD),
+        new anchor.BN(STREAM_DURATION_SLOTS),
+        new anchor.BN(PROPOSAL_DEPOSIT),
+        TIME_BASE
       )
       .accounts({
         cvConfig,
@@ -248,19 +294,34 @@ describe("full lifecycle", () => {
       })
       .rpc();
 
+    const recipient = anchor.web3.Keypair.generate();
+    const recipientCommonsAccount = await commonsToken.getOrCreateAssociatedAccountInfo(
+      recipient.publicKey
+    );
+
     const requestedAmount = 1_000;
-    const requestBuffer = toU64Buffer(requestedAmount);
+    const cvConfigState = await cvProgram.account.cvConfig.fetch(cvConfig);
     const [proposal] = await anchor.web3.PublicKey.findProgramAddress(
-      [Buffer.from("proposal"), provider.wallet.publicKey.toBuffer(), requestBuffer],
+      [
+        Buffer.from("proposal"),
+        cvConfig.toBuffer(),
+        toU64Buffer(cvConfigState.proposalCount.toNumber()),
+      ],
       cvProgram.programId
     );
 
     await cvProgram.methods
-      .createProposal(new anchor.BN(requestedAmount), "full-lifecycle")
+      .createProposal(new anchor.BN(requestedAmount), "ipfs://full-lifecycle", "")
       .accounts({
         proposal,
+        cvConfig,
+        beneficiary: recipientCommonsAccount.address,
+        commonsTokenMint,
+        creatorTokenAccount: null,
+        depositEscrow: null,
         authority: provider.wallet.publicKey,
         systemProgram: anchor.web3.SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
         clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
       })
       .rpc();
@@ -273,6 +334,14 @@ describe("full lifecycle", () => {
       ],
       cvProgram.programId
     );
+    const [voterAccount] = await anchor.web3.PublicKey.findProgramAddress(
+      [
+        Buffer.from("voter"),
+        cvConfig.toBuffer(),
+        provider.wallet.publicKey.toBuffer(),
+      ],
+      cvProgram.programId
+    );
 
     await cvProgram.methods
       .stakeTokens(new anchor.BN(stakeAmount))
@@ -280,6 +349,11 @@ describe("full lifecycle", () => {
         stakeAccount,
         cvConfig,
         proposal,
+        commonsTreasury: commonsTreasury.publicKey,
+        treasuryRegistry: null,
+        abstainProposal: null,
+        voterAccount,
+        personhood: null,
         commonsTokenMint,
         userCommonsTokenAccount: userCommonsAccount.address,
         stakingVault,
@@ -290,13 +364,8 @@ describe("full lifecycle", () => {
       })
       .rpc();
 
-    const recipient = anchor.web3.Keypair.generate();
-    const recipientReserveAccount = await reserveToken.getOrCreateAssociatedAccountInfo(
-      recipient.publicKey
-    );
-
     const treasuryBefore = (
-      await reserveToken.getAccountInfo(commonsTreasury.publicKey)
+      await commonsToken.getAccountInfo(commonsTreasury.publicKey)
     ).amount.toNumber();
 
     await cvProgram.methods
@@ -305,18 +374,19 @@ describe("full lifecycle", () => {
         cvConfig,
         proposal,
         commonsTreasury: commonsTreasury.publicKey,
-        recipientTokenAccount: recipientReserveAccount.address,
-        authority: provider.wallet.publicKey,
+        treasuryRegistry: null,
+        beneficiary: recipientCommonsAccount.address,
+        abstainProposal: null,
         tokenProgram: TOKEN_PROGRAM_ID,
         clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
       })
       .rpc();
 
     const treasuryAfter = (
-      await reserveToken.getAccountInfo(commonsTreasury.publicKey)
+      await commonsToken.getAccountInfo(commonsTreasury.publicKey)
     ).amount.toNumber();
     const recipientAfter = (
-      await reserveToken.getAccountInfo(recipientReserveAccount.address)
+      await commonsToken.getAccountInfo(recipientCommonsAccount.address)
     ).amount.toNumber();
 
     expect(treasuryBefore - treasuryAfter).to.equal(requestedAmount);